@@ -0,0 +1,223 @@
+use super::ClipboardSource;
+use crate::protocol::{SourceDataItem, fingerprint_content};
+use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// The contents recently read from the clipboard by their fingerprint, so reading the same
+/// content again returns the buffer which is already held instead of another copy of it.
+/// The least recently used one is dropped when it is full.
+pub struct ContentCache {
+    capacity: usize,
+    // The least recently used first
+    entries: VecDeque<(u64, Rc<Vec<u8>>)>,
+}
+
+impl ContentCache {
+    pub fn new(capacity: usize) -> Self {
+        ContentCache {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The cached content with the fingerprint, see `fingerprint_content`.
+    pub fn get(&mut self, fingerprint: u64) -> Option<Rc<Vec<u8>>> {
+        let i = self.entries.iter().position(|(f, _)| *f == fingerprint)?;
+        Some(self.touch(i))
+    }
+
+    /// Return the cached content equal to `content` if there is one, otherwise cache `content`
+    /// and return it. The bytes are compared on a fingerprint match, since the fingerprint can
+    /// collide.
+    pub fn intern(&mut self, content: Rc<Vec<u8>>) -> Rc<Vec<u8>> {
+        let fingerprint = fingerprint_content(&content);
+        if let Some(i) = self
+            .entries
+            .iter()
+            .position(|(f, c)| *f == fingerprint && c == &content)
+        {
+            return self.touch(i);
+        }
+        if self.capacity == 0 {
+            return content;
+        }
+        // An older content colliding with it is replaced, `get` has to find one of them
+        self.entries.retain(|(f, _)| *f != fingerprint);
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((fingerprint, content.clone()));
+        content
+    }
+
+    // Make the entry the most recently used one.
+    fn touch(&mut self, i: usize) -> Rc<Vec<u8>> {
+        let entry = self.entries.remove(i).unwrap();
+        let content = entry.1.clone();
+        self.entries.push_back(entry);
+        content
+    }
+}
+
+/// A `ClipboardSource` whose contents are deduplicated with a `ContentCache`, e.g. for a
+/// watcher which keeps the items it reads, so the same content copied again shares the buffer
+/// of the earlier one.
+/// If the source has a `ClipboardSource::change_token` and it is the same as the one of the last
+/// read, the items of the last read are returned without fetching anything. Otherwise the
+/// contents have to be fetched to tell they are the same, since the fingerprint is only known
+/// from the content.
+pub struct CachedSource<S: ClipboardSource> {
+    pub source: S,
+    cache: RefCell<ContentCache>,
+    // The change token and the items of the last read
+    last: RefCell<Option<(u64, Vec<SourceDataItem>)>>,
+}
+
+impl<S: ClipboardSource> CachedSource<S> {
+    pub fn new(source: S, capacity: usize) -> Self {
+        CachedSource {
+            source,
+            cache: RefCell::new(ContentCache::new(capacity)),
+            last: RefCell::new(None),
+        }
+    }
+}
+
+impl<S: ClipboardSource> ClipboardSource for CachedSource<S> {
+    fn read(&self) -> Result<Vec<SourceDataItem>> {
+        let token = self.source.change_token();
+        if let Some(token) = token
+            && let Some((last_token, items)) = &*self.last.borrow()
+            && *last_token == token
+        {
+            log::debug!("The clipboard is unchanged, return the cached items");
+            return Ok(items.clone());
+        }
+        let mut items = self.source.read()?;
+        let mut cache = self.cache.borrow_mut();
+        for item in &mut items {
+            item.content = cache.intern(item.content.clone());
+        }
+        *self.last.borrow_mut() = token.map(|token| (token, items.clone()));
+        Ok(items)
+    }
+
+    fn change_token(&self) -> Option<u64> {
+        self.source.change_token()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_cache() {
+        let mut cache = ContentCache::new(2);
+        let a = cache.intern(Rc::new(b"A".to_vec()));
+        // The same content returns the cached buffer
+        let again = cache.intern(Rc::new(b"A".to_vec()));
+        assert!(Rc::ptr_eq(&a, &again));
+        assert_eq!(cache.len(), 1);
+        assert!(Rc::ptr_eq(
+            &cache.get(fingerprint_content(b"A")).unwrap(),
+            &a
+        ));
+
+        let b = cache.intern(Rc::new(b"B".to_vec()));
+        // 'A' is used more recently than 'B'
+        cache.intern(Rc::new(b"A".to_vec()));
+        cache.intern(Rc::new(b"C".to_vec()));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(fingerprint_content(b"B")).is_none());
+        assert!(!Rc::ptr_eq(&cache.intern(Rc::new(b"B".to_vec())), &b));
+        assert!(cache.get(fingerprint_content(b"A")).is_none());
+
+        let mut cache = ContentCache::new(0);
+        cache.intern(Rc::new(b"A".to_vec()));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_content_cache_collision() {
+        let mut cache = ContentCache::new(2);
+        let a = cache.intern(Rc::new(b"A".to_vec()));
+        // Pretend another content has the same fingerprint as 'B'
+        let fingerprint = fingerprint_content(b"B");
+        cache
+            .entries
+            .push_back((fingerprint, Rc::new(b"not B".to_vec())));
+
+        // Replaced without evicting the others
+        let b = cache.intern(Rc::new(b"B".to_vec()));
+        assert_eq!(cache.len(), 2);
+        assert!(Rc::ptr_eq(&cache.get(fingerprint).unwrap(), &b));
+        assert!(Rc::ptr_eq(
+            &cache.get(fingerprint_content(b"A")).unwrap(),
+            &a
+        ));
+    }
+
+    struct RepeatSource;
+
+    impl ClipboardSource for RepeatSource {
+        fn read(&self) -> Result<Vec<SourceDataItem>> {
+            Ok(vec![SourceDataItem::new(
+                vec!["text/plain".to_string()],
+                b"GOOD".to_vec(),
+            )])
+        }
+    }
+
+    #[test]
+    fn test_cached_source() {
+        let source = CachedSource::new(RepeatSource, 8);
+        let first = source.read().unwrap();
+        let second = source.read().unwrap();
+        assert_eq!(second[0].content.as_slice(), b"GOOD");
+        assert!(Rc::ptr_eq(&first[0].content, &second[0].content));
+    }
+
+    // Counts the reads, the token is changed by the test
+    #[derive(Default)]
+    struct TokenSource {
+        token: std::cell::Cell<u64>,
+        reads: std::cell::Cell<usize>,
+    }
+
+    impl ClipboardSource for TokenSource {
+        fn read(&self) -> Result<Vec<SourceDataItem>> {
+            self.reads.set(self.reads.get() + 1);
+            RepeatSource.read()
+        }
+
+        fn change_token(&self) -> Option<u64> {
+            Some(self.token.get())
+        }
+    }
+
+    #[test]
+    fn test_cached_source_change_token() {
+        let source = CachedSource::new(TokenSource::default(), 8);
+        let first = source.read().unwrap();
+        // Not fetched again while the token is the same
+        let second = source.read().unwrap();
+        assert_eq!(source.source.reads.get(), 1);
+        assert!(Rc::ptr_eq(&first[0].content, &second[0].content));
+
+        source.source.token.set(1);
+        let third = source.read().unwrap();
+        assert_eq!(source.source.reads.get(), 2);
+        assert!(Rc::ptr_eq(&first[0].content, &third[0].content));
+    }
+}
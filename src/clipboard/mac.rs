@@ -1,10 +1,13 @@
 use super::ClipBackend;
 use super::CopyConfig;
 use super::PasteConfig;
+use super::uti::{mime_type_to_uti, uti_to_mime_type};
+use crate::protocol::SourceDataItem;
 use anyhow::{Context, Result, bail};
 
 use cocoa::appkit;
 use cocoa::appkit::NSPasteboard;
+use cocoa::appkit::NSPasteboardItem;
 use cocoa::base::id;
 use cocoa::base::nil;
 use cocoa::foundation::NSArray;
@@ -12,6 +15,7 @@ use cocoa::foundation::NSAutoreleasePool;
 use cocoa::foundation::NSData;
 use cocoa::foundation::NSString;
 
+use objc::{class, msg_send, sel, sel_impl};
 use std::collections::HashMap;
 use std::ffi::CStr;
 use std::sync::LazyLock;
@@ -137,6 +141,55 @@ unsafe fn paste_mac(config: PasteConfig) -> Result<()> {
     Ok(())
 }
 
+impl SourceDataItem {
+    /// Create a retained `NSPasteboardItem` holding the content for every mime-type which has a
+    /// known UTI, the others are skipped. The caller is responsible for releasing it.
+    pub unsafe fn to_macos_pasteboard_item(&self) -> id {
+        let item: id = msg_send![class!(NSPasteboardItem), new];
+        let bytes = self.content.as_ptr() as *const std::os::raw::c_void;
+        let length = self.content.len() as u64;
+        let nsdata = NSData::dataWithBytes_length_(nil, bytes, length);
+        for mime_type in &self.mime_type {
+            let Some(uti) = mime_type_to_uti(mime_type) else {
+                log::warn!("No UTI for mime-type {mime_type}, skipped");
+                continue;
+            };
+            let nstr_type = NSString::alloc(nil).init_str(uti);
+            if item.setData_forType(nsdata, nstr_type) != objc::runtime::YES {
+                log::error!("Failed to call setData_forType on {uti}");
+            }
+        }
+        item
+    }
+
+    /// Read the `NSPasteboardItem`. All the types are expected to hold the same content, the
+    /// first one which has data is used.
+    pub unsafe fn from_macos_pasteboard_item(item: id) -> Result<SourceDataItem> {
+        let types = item.types();
+        let mut mime_type = Vec::<String>::new();
+        let mut content = None;
+        for i in 0..types.count() {
+            let t = types.objectAtIndex(i);
+            let mime = uti_to_mime_type(&nsstring_to_string(t)).to_string();
+            if !mime_type.contains(&mime) {
+                mime_type.push(mime);
+            }
+            if content.is_none() {
+                let data = item.dataForType(t);
+                if data != nil {
+                    let bytes = data.bytes() as *const u8;
+                    let length = data.length() as usize;
+                    content = Some(std::slice::from_raw_parts(bytes, length).to_vec());
+                }
+            }
+        }
+        if mime_type.is_empty() {
+            bail!("The pasteboard item has no types");
+        }
+        Ok(SourceDataItem::new(mime_type, content.unwrap_or_default()))
+    }
+}
+
 unsafe fn nsstring_to_string(ns_str: id) -> String {
     let c_str: *const i8 = NSString::UTF8String(ns_str);
 
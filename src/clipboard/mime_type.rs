@@ -40,6 +40,162 @@ fn try_any_text(supported: &[String]) -> Option<String> {
     None
 }
 
+/// Whether the content of the mime-type is text. An empty mime-type is regarded as text, since
+/// that is what gets pasted by default.
+pub fn is_text_mime_type(mime_type: &str) -> bool {
+    mime_type.is_empty()
+        || mime_type.eq_ignore_ascii_case("text")
+        || mime_type.to_ascii_lowercase().ends_with("json")
+        || try_any_text(&[mime_type.to_string()]).is_some()
+}
+
+/// The magic bytes at the start of the content.
+const MAGIC_BYTES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+];
+
+/// Guess the mime-type from the first bytes of the content: the magic bytes of the common image
+/// and archive formats, then HTML, SVG and plain text. `None` if nothing matches, e.g. for
+/// arbitrary binary data.
+pub fn sniff_mime_type(head: &[u8]) -> Option<&'static str> {
+    if let Some((_, mime_type)) = MAGIC_BYTES
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+    {
+        return Some(mime_type);
+    }
+    // RIFF container with the size in between
+    if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    // The head may end in the middle of a multi-byte char
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    if text.contains('\0') {
+        return None;
+    }
+    let start = text.trim_start().to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        Some("text/html")
+    } else if start.starts_with("<svg") || (start.starts_with("<?xml") && start.contains("<svg")) {
+        Some("image/svg+xml")
+    } else {
+        Some("text/plain;charset=utf-8")
+    }
+}
+
+/// The mime-types of the languages, by the interpreter or editor file type names.
+const CODE_TYPES: &[(&[&str], &str)] = &[
+    (&["python"], "text/x-python"),
+    (&["sh", "bash", "zsh", "dash", "ksh"], "text/x-shellscript"),
+    (&["perl"], "text/x-perl"),
+    (&["ruby"], "text/x-ruby"),
+    (&["node", "nodejs", "javascript", "js"], "text/javascript"),
+    (&["php"], "text/x-php"),
+    (&["lua"], "text/x-lua"),
+    (&["rust"], "text/rust"),
+    (&["c"], "text/x-c"),
+    (&["cpp"], "text/x-c++"),
+    (&["go"], "text/x-go"),
+    (&["java"], "text/x-java"),
+];
+
+// The modelines are only looked for in the first and the last lines, like vim does.
+const MODELINE_LINES: usize = 5;
+
+fn code_type(name: &str) -> Option<&'static str> {
+    // `python3.12` is python
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    CODE_TYPES
+        .iter()
+        .find(|(names, _)| names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+        .map(|(_, mime_type)| *mime_type)
+}
+
+// The interpreter of `#!/usr/bin/python3` or `#!/usr/bin/env -S python3 -u`.
+fn shebang_type(line: &str) -> Option<&'static str> {
+    let mut args = line.strip_prefix("#!")?.split_whitespace();
+    let mut interpreter = args.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = args.find(|a| !a.starts_with('-'))?;
+    }
+    code_type(interpreter)
+}
+
+// The file type of a vim modeline, `vim: set ft=python:` or `vi: filetype=python`, or an Emacs
+// one, `-*- mode: python -*-` or `-*- python -*-`.
+fn modeline_type(line: &str) -> Option<&'static str> {
+    if let Some((_, emacs)) = line.split_once("-*-") {
+        let vars = emacs.split("-*-").next()?.trim();
+        let mode = vars
+            .split(';')
+            .find_map(|v| v.trim().strip_prefix("mode:"))
+            .unwrap_or(vars);
+        return code_type(mode.trim());
+    }
+    let (_, vim) = line
+        .split_once("vim:")
+        .or_else(|| line.split_once("vi:"))
+        .or_else(|| line.split_once("ex:"))?;
+    vim.split(|c: char| c == ':' || c.is_whitespace())
+        .find_map(|o| {
+            o.strip_prefix("ft=")
+                .or_else(|| o.strip_prefix("filetype="))
+        })
+        .and_then(code_type)
+}
+
+/// Guess the mime-type of a code snippet from its shebang, or a vim or Emacs modeline in its
+/// first or last lines. `None` if there is neither, or the language is not known.
+pub fn sniff_code_mime_type(content: &[u8]) -> Option<&'static str> {
+    let text = std::str::from_utf8(content).ok()?;
+    let lines: Vec<&str> = text.lines().collect();
+    if let Some(mime_type) = lines.first().and_then(|l| shebang_type(l)) {
+        return Some(mime_type);
+    }
+    let head = lines.iter().take(MODELINE_LINES);
+    let tail = lines.iter().skip(MODELINE_LINES).rev().take(MODELINE_LINES);
+    head.chain(tail).find_map(|l| modeline_type(l))
+}
+
+/// The mime-types of the common file extensions.
+const EXTENSION_TYPES: &[(&[&str], &str)] = &[
+    (&["txt", "text"], "text/plain;charset=utf-8"),
+    (&["html", "htm"], "text/html"),
+    (&["md", "markdown"], "text/markdown"),
+    (&["csv"], "text/csv"),
+    (&["rtf"], "text/rtf"),
+    (&["json"], "application/json"),
+    (&["xml"], "application/xml"),
+    (&["png"], "image/png"),
+    (&["jpg", "jpeg"], "image/jpeg"),
+    (&["gif"], "image/gif"),
+    (&["webp"], "image/webp"),
+    (&["svg"], "image/svg+xml"),
+    (&["bmp"], "image/bmp"),
+    (&["pdf"], "application/pdf"),
+    (&["zip"], "application/zip"),
+    (&["gz"], "application/gzip"),
+];
+
+/// The mime-type of a file by its extension, `application/octet-stream` if it is not known.
+pub fn mime_type_for_extension(extension: &str) -> &'static str {
+    EXTENSION_TYPES
+        .iter()
+        .find(|(extensions, _)| extensions.iter().any(|e| e.eq_ignore_ascii_case(extension)))
+        .map_or("application/octet-stream", |(_, mime_type)| mime_type)
+}
+
 /// Based on the given preferred mime-type, and the mime-types supported by the current clipboard
 /// content, return the best match mime-type to paste.
 pub(super) fn decide_mime_type(preferred: &str, supported: &Vec<String>) -> Result<String> {
@@ -115,6 +271,94 @@ mod tests {
         assert_eq!(r, "text/plain;charset=utf-8");
     }
 
+    #[test]
+    fn test_sniff_mime_type() {
+        assert_eq!(
+            sniff_mime_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Some("image/png")
+        );
+        assert_eq!(sniff_mime_type(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(
+            sniff_mime_type(b"RIFF\x10\0\0\0WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(sniff_mime_type(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(
+            sniff_mime_type(b"\n<!DOCTYPE html><p>GOOD"),
+            Some("text/html")
+        );
+        assert_eq!(
+            sniff_mime_type(b"<?xml version=\"1.0\"?>\n<svg>"),
+            Some("image/svg+xml")
+        );
+        assert_eq!(sniff_mime_type(b"GOOD"), Some("text/plain;charset=utf-8"));
+        // Cut in the middle of 'é'
+        assert_eq!(
+            sniff_mime_type(&"café".as_bytes()[..4]),
+            Some("text/plain;charset=utf-8")
+        );
+
+        // Truncated signature and binary data
+        assert_eq!(sniff_mime_type(b"RIFF\x10\0"), None);
+        assert_eq!(sniff_mime_type(b"\x00\x01\x02"), None);
+        assert_eq!(sniff_mime_type(b"\xfe\xfe\xfe"), None);
+    }
+
+    #[test]
+    fn test_mime_type_for_extension() {
+        assert_eq!(mime_type_for_extension("png"), "image/png");
+        assert_eq!(mime_type_for_extension("JPEG"), "image/jpeg");
+        assert_eq!(mime_type_for_extension("htm"), "text/html");
+        assert_eq!(mime_type_for_extension("txt"), "text/plain;charset=utf-8");
+        assert_eq!(mime_type_for_extension(""), "application/octet-stream");
+        assert_eq!(mime_type_for_extension("xyz"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_sniff_code_mime_type() {
+        assert_eq!(
+            sniff_code_mime_type(b"#!/usr/bin/env python\nprint('GOOD')\n"),
+            Some("text/x-python")
+        );
+        assert_eq!(
+            sniff_code_mime_type(b"#!/usr/bin/python3.12 -u\n"),
+            Some("text/x-python")
+        );
+        assert_eq!(
+            sniff_code_mime_type(b"#!/usr/bin/env -S bash -e\necho GOOD"),
+            Some("text/x-shellscript")
+        );
+        // Modelines at the end and at the start
+        let tail = b"fn main() {}\n\n\n\n\n\n\n// vim: set ft=rust ts=4:\n";
+        assert_eq!(sniff_code_mime_type(tail), Some("text/rust"));
+        assert_eq!(
+            sniff_code_mime_type(b"# -*- mode: ruby; coding: utf-8 -*-\nputs 1"),
+            Some("text/x-ruby")
+        );
+        assert_eq!(
+            sniff_code_mime_type(b"/* -*- c -*- */\nint x;"),
+            Some("text/x-c")
+        );
+
+        // Plain text and unknown languages
+        assert_eq!(sniff_code_mime_type(b"Some plain text\n"), None);
+        assert_eq!(sniff_code_mime_type(b"#!/usr/bin/unknown\n"), None);
+        assert_eq!(sniff_code_mime_type(b"Don't #!/bin/sh\n"), None);
+        assert_eq!(sniff_code_mime_type(b"\xff\xfe"), None);
+    }
+
+    #[test]
+    fn test_is_text_mime_type() {
+        assert!(is_text_mime_type(""));
+        assert!(is_text_mime_type("text"));
+        assert!(is_text_mime_type("UTF8_STRING"));
+        assert!(is_text_mime_type("text/html"));
+        assert!(is_text_mime_type("application/json"));
+        assert!(is_text_mime_type("application/x-yaml"));
+        assert!(!is_text_mime_type("image/png"));
+        assert!(!is_text_mime_type("application/octet-stream"));
+    }
+
     #[test]
     fn test_exact_preferred() {
         // match a text type
@@ -1,15 +1,23 @@
+mod cache;
 #[cfg(target_os = "macos")]
 mod mac;
 mod mime_type;
+mod uti;
 #[cfg(target_os = "linux")]
 mod wayland;
 mod x;
 
-use super::protocol::SourceData;
-use anyhow::Result;
+use super::protocol::{
+    ProtocolWriter, SourceData, SourceDataItem, check_utf8_text, fingerprint_items,
+    receive_data_bulk,
+};
 #[cfg(target_os = "linux")]
 use anyhow::bail;
-use std::io::Write;
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
 
 pub trait ClipBackend {
     fn copy(&self, config: CopyConfig) -> Result<()>;
@@ -31,6 +39,207 @@ pub struct CopyConfig {
     pub x_chunk_size: usize,
 }
 
+/// Something which can take over the clipboard with the given items.
+pub trait ClipboardSink {
+    /// Offer the items with a priority each, the items with higher priorities are advertised
+    /// first. See `sort_by_priority`.
+    fn offer_with_priority(&mut self, items: &[(SourceDataItem, u16)]) -> Result<()>;
+
+    /// Offer the items in the given order.
+    fn offer(&mut self, items: &[SourceDataItem]) -> Result<()> {
+        let items: Vec<_> = items.iter().map(|item| (item.clone(), 0)).collect();
+        self.offer_with_priority(&items)
+    }
+
+    /// Offer the contents of the source. The sinks which hand the source to a `ClipBackend`
+    /// fetch a content only when it is pasted, the others fetch all of them up front and offer
+    /// an item for every mime-type.
+    fn offer_source(&mut self, source: Box<dyn SourceData>) -> Result<()> {
+        let items: Vec<_> = source
+            .mime_types()
+            .into_iter()
+            .map(|mime_type| {
+                let (_, content) = source.content_by_mime_type(&mime_type);
+                SourceDataItem::new(vec![mime_type], content)
+            })
+            .collect();
+        self.offer(&items)
+    }
+}
+
+/// Order the items by priority descending. Items with the same priority keep their order.
+pub fn sort_by_priority(items: &[(SourceDataItem, u16)]) -> Vec<SourceDataItem> {
+    let mut items = items.to_vec();
+    items.sort_by_key(|(_, priority)| std::cmp::Reverse(*priority));
+    items.into_iter().map(|(item, _)| item).collect()
+}
+
+/// Keep the offered items, in the order of their priorities, e.g. to transform them before they
+/// are copied. The items of every offer replace the earlier ones.
+impl ClipboardSink for Vec<SourceDataItem> {
+    fn offer_with_priority(&mut self, items: &[(SourceDataItem, u16)]) -> Result<()> {
+        *self = sort_by_priority(items);
+        Ok(())
+    }
+}
+
+/// Offer the items through a `ClipBackend`. Note that `ClipBackend::copy` only returns after the
+/// clipboard ownership is lost.
+pub struct BackendSink {
+    pub backend: Box<dyn ClipBackend>,
+    pub use_primary: bool,
+}
+
+impl ClipboardSink for BackendSink {
+    fn offer_with_priority(&mut self, items: &[(SourceDataItem, u16)]) -> Result<()> {
+        self.backend.copy(CopyConfig {
+            use_primary: self.use_primary,
+            source_data: Box::new(sort_by_priority(items)),
+            x_chunk_size: 0,
+        })
+    }
+}
+
+/// Something the current clipboard content can be read from.
+pub trait ClipboardSource {
+    fn read(&self) -> Result<Vec<SourceDataItem>>;
+
+    /// A value which changes whenever the content of the clipboard does, and is known without
+    /// fetching the content, e.g. the timestamp of the X selection owner. `None` if the source
+    /// can't tell, see `CachedSource`.
+    fn change_token(&self) -> Option<u64> {
+        None
+    }
+
+    /// Wait for `interval`, then read the clipboard. The content is returned only if its
+    /// `fingerprint_items` differs from `last_fingerprint`. Call it in a loop to watch the
+    /// clipboard.
+    fn poll_for_change(
+        &self,
+        interval: Duration,
+        last_fingerprint: u64,
+    ) -> Result<Option<Vec<SourceDataItem>>> {
+        std::thread::sleep(interval);
+        let items = self.read()?;
+        if fingerprint_items(&items) == last_fingerprint {
+            Ok(None)
+        } else {
+            Ok(Some(items))
+        }
+    }
+}
+
+/// Read the clipboard through a `ClipBackend`, see `read_all`.
+pub struct BackendSource {
+    pub backend: Box<dyn ClipBackend>,
+    pub use_primary: bool,
+}
+
+impl ClipboardSource for BackendSource {
+    fn read(&self) -> Result<Vec<SourceDataItem>> {
+        read_all(self.backend.as_ref(), self.use_primary)
+    }
+}
+
+/// A `Write` which can be handed over to `PasteConfig` while still being readable afterwards.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Targets reported by X selection owners which are not real content types.
+const META_TARGETS: &[&str] = &["TARGETS", "MULTIPLE", "TIMESTAMP", "SAVE_TARGETS"];
+
+/// Read every mime-type offered by the current clipboard content. Mime-types with identical
+/// content are grouped into the same item.
+pub fn read_all(backend: &dyn ClipBackend, use_primary: bool) -> Result<Vec<SourceDataItem>> {
+    let buffer = SharedBuffer::default();
+    backend
+        .paste(PasteConfig {
+            list_types_only: true,
+            use_primary,
+            expected_mime_type: "".to_string(),
+            writter: Box::new(buffer.clone()),
+        })
+        .context("Failed to list the mime-types")?;
+    let listed = String::from_utf8(buffer.take()).context("Failed to parse the mime-types")?;
+
+    let mut ret = Vec::<SourceDataItem>::new();
+    for mime_type in listed.lines() {
+        if mime_type.is_empty() || META_TARGETS.contains(&mime_type) {
+            continue;
+        }
+        backend
+            .paste(PasteConfig {
+                list_types_only: false,
+                use_primary,
+                expected_mime_type: mime_type.to_string(),
+                writter: Box::new(buffer.clone()),
+            })
+            .with_context(|| format!("Failed to paste the content of '{mime_type}'"))?;
+        let content = buffer.take();
+        match ret.iter_mut().find(|item| *item.content == content) {
+            Some(item) => item.mime_type.push(mime_type.to_string()),
+            None => ret.push(SourceDataItem::new(
+                vec![mime_type.to_string()],
+                Rc::new(content),
+            )),
+        }
+    }
+    Ok(ret)
+}
+
+/// Read the clipboard from the source and write all its items in the format `receive_data_bulk`
+/// parses. An empty clipboard is written as the header only. Returns the number of items.
+/// Text items which are not valid UTF-8 are warned about, or fail it if `strict_utf8` is set,
+/// see `check_utf8_text`.
+pub fn export_items(
+    source: &dyn ClipboardSource,
+    writer: impl Write,
+    strict_utf8: bool,
+) -> Result<usize> {
+    let items = source.read()?;
+    check_utf8_text(&items, strict_utf8)?;
+    let mut writer = ProtocolWriter::new(writer)?;
+    for item in &items {
+        writer.write_item(item)?;
+    }
+    writer
+        .into_inner()
+        .flush()
+        .context("Failed to write the items")?;
+    Ok(items.len())
+}
+
+/// Parse the output of `export_items` and offer the items to the sink. Zero items are offered
+/// as well, which clears the clipboard. Returns the number of items.
+pub fn import_export(reader: impl Read, sink: &mut dyn ClipboardSink) -> Result<usize> {
+    let items = receive_data_bulk(reader)?;
+    sink.offer(&items)?;
+    Ok(items.len())
+}
+
+pub use cache::{CachedSource, ContentCache};
+pub use mime_type::{
+    is_text_mime_type, mime_type_for_extension, sniff_code_mime_type, sniff_mime_type,
+};
+pub use uti::{mime_type_to_uti, uti_to_mime_type};
+
 #[cfg(target_os = "macos")]
 use mac::MacBackend;
 
@@ -61,3 +270,165 @@ pub fn create_backend() -> Result<Box<dyn ClipBackend>> {
 
     Ok(Box::new(MacBackend {}))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct MockSource {
+        reads: Cell<usize>,
+    }
+
+    impl ClipboardSource for MockSource {
+        fn read(&self) -> Result<Vec<SourceDataItem>> {
+            // The content changes after the second read
+            let n = self.reads.get();
+            self.reads.set(n + 1);
+            let content = if n < 2 { b"A" } else { b"B" };
+            Ok(vec![SourceDataItem::new(
+                vec!["text/plain".to_string()],
+                Rc::new(content.to_vec()),
+            )])
+        }
+    }
+
+    struct FixedSource(Vec<SourceDataItem>);
+
+    impl ClipboardSource for FixedSource {
+        fn read(&self) -> Result<Vec<SourceDataItem>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[derive(Default)]
+    struct MockSink {
+        offered: Vec<Vec<SourceDataItem>>,
+    }
+
+    impl ClipboardSink for MockSink {
+        fn offer_with_priority(&mut self, items: &[(SourceDataItem, u16)]) -> Result<()> {
+            self.offered.push(sort_by_priority(items));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_offer_with_priority() {
+        let item = |content: &[u8]| {
+            SourceDataItem::new(vec!["text/plain".to_string()], Rc::new(content.to_vec()))
+        };
+        let mut sink = MockSink::default();
+        sink.offer_with_priority(&[
+            (item(b"A"), 1),
+            (item(b"B"), u16::MAX),
+            (item(b"C"), 0),
+            (item(b"D"), 1),
+        ])
+        .unwrap();
+        // The same priorities keep the order
+        sink.offer(&[item(b"A"), item(b"B")]).unwrap();
+
+        let contents = |items: &Vec<SourceDataItem>| {
+            items
+                .iter()
+                .map(|item| item.content.as_slice().to_vec())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(contents(&sink.offered[0]), [b"B", b"A", b"D", b"C"]);
+        assert_eq!(contents(&sink.offered[1]), [b"A", b"B"]);
+    }
+
+    #[test]
+    fn test_export_import_roundtrip() {
+        let source = FixedSource(vec![
+            SourceDataItem::new(
+                vec!["text/plain".to_string(), "TEXT".to_string()],
+                Rc::new(b"GOOD".to_vec()),
+            ),
+            SourceDataItem::new(
+                vec!["text/html".to_string()],
+                Rc::new(b"<b>GOOD</b>".to_vec()),
+            ),
+        ]);
+        let mut pipe = Vec::new();
+        assert_eq!(export_items(&source, &mut pipe, true).unwrap(), 2);
+
+        let mut sink = MockSink::default();
+        assert_eq!(import_export(&pipe[..], &mut sink).unwrap(), 2);
+        assert_eq!(sink.offered.len(), 1);
+        let offered = &sink.offered[0];
+        assert_eq!(offered[0].mime_type, ["text/plain", "TEXT"]);
+        assert_eq!(offered[0].content.as_slice(), b"GOOD");
+        assert_eq!(offered[1].mime_type, ["text/html"]);
+        assert_eq!(offered[1].content.as_slice(), b"<b>GOOD</b>");
+    }
+
+    #[test]
+    fn test_export_import_clear() {
+        let mut pipe = Vec::new();
+        assert_eq!(
+            export_items(&FixedSource(vec![]), &mut pipe, true).unwrap(),
+            0
+        );
+
+        // Still offered, to clear the clipboard
+        let mut sink = MockSink::default();
+        assert_eq!(import_export(&pipe[..], &mut sink).unwrap(), 0);
+        assert_eq!(sink.offered.len(), 1);
+        assert!(sink.offered[0].is_empty());
+
+        // Not an export
+        assert!(import_export(&b"text"[..], &mut sink).is_err());
+        assert_eq!(sink.offered.len(), 1);
+    }
+
+    #[test]
+    fn test_import_export_into_vec() {
+        let source = FixedSource(vec![SourceDataItem::new(
+            vec!["text/plain".to_string()],
+            Rc::new(b"GOOD".to_vec()),
+        )]);
+        let mut pipe = Vec::new();
+        export_items(&source, &mut pipe, true).unwrap();
+        let mut items = vec![SourceDataItem::new(vec!["OLD".to_string()], vec![])];
+        assert_eq!(import_export(&pipe[..], &mut items).unwrap(), 1);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].content.as_slice(), b"GOOD");
+    }
+
+    #[test]
+    fn test_export_strict_utf8() {
+        let source = FixedSource(vec![SourceDataItem::new(
+            vec!["text/plain".to_string()],
+            Rc::new(b"\xff".to_vec()),
+        )]);
+        let mut pipe = Vec::new();
+        assert_eq!(export_items(&source, &mut pipe, false).unwrap(), 1);
+        let mut pipe = Vec::new();
+        assert!(export_items(&source, &mut pipe, true).is_err());
+        assert!(pipe.is_empty());
+    }
+
+    #[test]
+    fn test_poll_for_change() {
+        let source = MockSource {
+            reads: Cell::new(0),
+        };
+        let interval = Duration::from_millis(1);
+        let items = source.poll_for_change(interval, 0).unwrap().unwrap();
+        let fingerprint = fingerprint_items(&items);
+        assert!(
+            source
+                .poll_for_change(interval, fingerprint)
+                .unwrap()
+                .is_none()
+        );
+        let items = source
+            .poll_for_change(interval, fingerprint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(items[0].content.as_slice(), b"B");
+        assert_eq!(source.reads.get(), 3);
+    }
+}
@@ -0,0 +1,82 @@
+// Mime-types and the Uniform Type Identifiers used by the macOS pasteboard. The first match is
+// used for both directions, so put the preferred ones first.
+// See https://developer.apple.com/documentation/uniformtypeidentifiers/system-declared-uniform-type-identifiers
+const MIME_UTI_MAP: &[(&str, &str)] = &[
+    ("text/plain", "public.utf8-plain-text"),
+    ("text/plain;charset=utf-8", "public.utf8-plain-text"),
+    ("text/plain", "public.plain-text"),
+    ("text/html", "public.html"),
+    ("text/html;charset=utf-8", "public.html"),
+    ("application/rtf", "public.rtf"),
+    ("text/rtf", "public.rtf"),
+    ("text/xml", "public.xml"),
+    ("application/xml", "public.xml"),
+    ("application/json", "public.json"),
+    ("text/csv", "public.comma-separated-values-text"),
+    ("text/uri-list", "public.url"),
+    ("image/png", "public.png"),
+    ("image/jpeg", "public.jpeg"),
+    ("image/tiff", "public.tiff"),
+    ("image/gif", "com.compuserve.gif"),
+    ("image/svg+xml", "public.svg-image"),
+    ("application/pdf", "com.adobe.pdf"),
+];
+
+// For the identifiers not listed above, guess the mime-type from what the UTI conforms to.
+const UTI_SUFFIX_FALLBACK: &[(&str, &str)] = &[
+    ("-plain-text", "text/plain"),
+    (".text", "text/plain"),
+    (".image", "image/*"),
+    (".data", "application/octet-stream"),
+];
+
+/// Translate the mime-type to the UTI, `None` if it is unknown.
+pub fn mime_type_to_uti(mime_type: &str) -> Option<&'static str> {
+    MIME_UTI_MAP
+        .iter()
+        .find(|(mime, _)| mime.eq_ignore_ascii_case(mime_type))
+        .map(|(_, uti)| *uti)
+}
+
+/// Translate the UTI to the mime-type. The unknown ones fall back to a generic mime-type of the
+/// same kind, or `application/octet-stream`.
+pub fn uti_to_mime_type(uti: &str) -> &'static str {
+    if let Some((mime, _)) = MIME_UTI_MAP.iter().find(|(_, u)| *u == uti) {
+        return mime;
+    }
+    UTI_SUFFIX_FALLBACK
+        .iter()
+        .find(|(suffix, _)| uti.ends_with(suffix))
+        .map(|(_, mime)| *mime)
+        .unwrap_or("application/octet-stream")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mime_type_to_uti() {
+        assert_eq!(
+            mime_type_to_uti("text/plain"),
+            Some("public.utf8-plain-text")
+        );
+        assert_eq!(mime_type_to_uti("TEXT/HTML"), Some("public.html"));
+        assert_eq!(mime_type_to_uti("image/png"), Some("public.png"));
+        assert_eq!(mime_type_to_uti("x-unknown/type"), None);
+    }
+
+    #[test]
+    fn test_uti_to_mime_type() {
+        assert_eq!(uti_to_mime_type("public.utf8-plain-text"), "text/plain");
+        assert_eq!(uti_to_mime_type("public.plain-text"), "text/plain");
+        assert_eq!(uti_to_mime_type("public.html"), "text/html");
+        // Fallback
+        assert_eq!(uti_to_mime_type("public.utf16-plain-text"), "text/plain");
+        assert_eq!(uti_to_mime_type("com.example.image"), "image/*");
+        assert_eq!(
+            uti_to_mime_type("com.example.whatever"),
+            "application/octet-stream"
+        );
+    }
+}
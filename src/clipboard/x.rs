@@ -97,6 +97,7 @@ struct XSelectionReceiver<T> {
 struct XCopyState {
     source_data: Box<dyn SourceData>,
     ongoing_senders: HashMap<Window, XSelectionSender>,
+    atom_cache: AtomCache,
 }
 
 impl ClipBackend for XBackend {
@@ -402,37 +403,62 @@ fn get_atom_name_default(conn: &RustConnection, atom: Atom) -> String {
     get_atom_name(conn, atom).unwrap_or(format!("Unknown Atom {}", atom))
 }
 
-fn targets_to_strings(
-    client: &mut XClient,
-    receiver: &XSelectionReceiver<u32>,
-) -> Result<Vec<String>> {
-    let mut ret = Vec::<String>::new();
-    for v in &receiver.buffer {
-        match get_atom_name(&client.conn, *v) {
-            Ok(name) => {
-                ret.push(name);
+/// Translate between mime-types and atoms. The results are cached since the same mime-types are
+/// asked for every time a client requests the TARGETS.
+#[derive(Default)]
+struct AtomCache {
+    atoms: HashMap<String, Atom>,
+}
+
+impl AtomCache {
+    /// Mime-types which cannot be interned are logged and skipped.
+    fn mime_types_to_atoms(
+        &mut self,
+        conn: &RustConnection,
+        mime_types: &[String],
+    ) -> Result<Vec<Atom>> {
+        // Send all the requests first to avoid a round trip per mime-type
+        let mut cookies = Vec::new();
+        for mime_type in mime_types {
+            if !self.atoms.contains_key(mime_type) {
+                cookies.push((mime_type, conn.intern_atom(false, mime_type.as_bytes())?));
             }
-            Err(error) => {
-                log::error!("Failed to get name for Atom '{}', error: {}", v, error);
+        }
+        for (mime_type, cookie) in cookies {
+            match cookie.reply() {
+                Ok(reply) => {
+                    self.atoms.insert(mime_type.clone(), reply.atom);
+                }
+                Err(e) => log::error!("Failed to convert {} into atom, {}", mime_type, e),
             }
         }
-    }
 
-    Ok(ret)
-}
+        Ok(mime_types
+            .iter()
+            .filter_map(|mime_type| self.atoms.get(mime_type).copied())
+            .collect())
+    }
 
-fn mime_types_to_targets(conn: &RustConnection, mime_types: &Vec<String>) -> Vec<u32> {
-    let mut ret = vec![];
-    for str in mime_types {
-        match get_atom_id_by_name(conn, str) {
-            Ok(atom) => ret.push(atom),
-            Err(e) => {
-                log::error!("Failed to convert {} into atom, {}", str, e)
+    /// Atoms whose names cannot be retrieved are logged and skipped.
+    fn atoms_to_mime_types(&mut self, conn: &RustConnection, atoms: &[Atom]) -> Vec<String> {
+        let mut ret = Vec::<String>::new();
+        for atom in atoms {
+            if let Some((name, _)) = self.atoms.iter().find(|(_, a)| *a == atom) {
+                ret.push(name.clone());
+                continue;
+            }
+            match get_atom_name(conn, *atom) {
+                Ok(name) => {
+                    self.atoms.insert(name.clone(), *atom);
+                    ret.push(name);
+                }
+                Err(error) => {
+                    log::error!("Failed to get name for Atom '{}', error: {}", atom, error);
+                }
             }
         }
+        ret
     }
-
-    ret
 }
 
 fn decide_mime_type_with_atom(
@@ -478,7 +504,7 @@ fn create_x_client(display_name: Option<&str>) -> Result<XClient> {
 }
 
 fn paste_x(config: PasteConfig) -> Result<()> {
-    let mut client = create_x_client(None)?;
+    let client = create_x_client(None)?;
 
     let selection = if config.use_primary {
         client.atoms.PRIMARY
@@ -535,8 +561,8 @@ fn paste_x(config: PasteConfig) -> Result<()> {
                     receiver
                         .receive(&client)
                         .context("Failed to retrieve TARGETS")?;
-                    let mime_types = targets_to_strings(&mut client, &receiver)
-                        .context("Failed to get supported targets")?;
+                    let mime_types =
+                        AtomCache::default().atoms_to_mime_types(&client.conn, &receiver.buffer);
                     if mime_types.is_empty() {
                         log::debug!("Got 0 targets which probably means the clipboard is empty");
                         log::debug!(
@@ -632,6 +658,7 @@ fn copy_x(config: CopyConfig) -> Result<()> {
     let mut state = XCopyState {
         source_data: config.source_data,
         ongoing_senders: HashMap::new(),
+        atom_cache: AtomCache::default(),
     };
     let client = create_x_client(None)?;
 
@@ -665,10 +692,11 @@ fn copy_x(config: CopyConfig) -> Result<()> {
                     // Ask for supported mime-types
                     // 'TARGETS' should always be the first supported target (mime-type)
                     let mut atoms = vec![client.atoms.TARGETS];
-                    atoms.extend(mime_types_to_targets(
-                        &client.conn,
-                        &state.source_data.mime_types(),
-                    ));
+                    atoms.extend(
+                        state
+                            .atom_cache
+                            .mime_types_to_atoms(&client.conn, &state.source_data.mime_types())?,
+                    );
                     // In theory, sending TARGETS could cause INCR transfer as well.
                     // However, that requires some complex generic handling for XSelectionSender
                     // which I failed to implement nicely.
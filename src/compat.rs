@@ -0,0 +1,217 @@
+use anyhow::{Context, Result, bail};
+use std::ffi::OsString;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tool {
+    Xclip,
+    WlCopy,
+    WlPaste,
+    // `richclip copy` or `richclip paste`
+    Richclip,
+}
+
+/// Translate the common arguments of `xclip`, `wl-copy` and `wl-paste` to the ones of richclip,
+/// so the existing scripts work unchanged, see the README for the mapping.
+/// If richclip is run as one of them, e.g. through a `xclip` symlink, all the arguments are
+/// translated and the defaults of the tool are kept: `xclip` uses the primary selection, the
+/// stdin is copied as it is, and `wl-paste` appends a newline to text. Otherwise only the
+/// `xclip` arguments of `richclip copy` and `richclip paste` are translated, which don't clash
+/// with the native ones, and richclip's defaults are kept. Other arguments are left for the
+/// parser to accept or reject.
+pub fn translate_args(args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let program = args
+        .first()
+        .and_then(|a| Path::new(a).file_name())
+        .and_then(|n| n.to_str())
+        .unwrap_or("");
+    let (tool, skip) = match program {
+        "xclip" => (Tool::Xclip, 1),
+        "wl-copy" => (Tool::WlCopy, 1),
+        "wl-paste" => (Tool::WlPaste, 1),
+        _ => match args.get(1).and_then(|a| a.to_str()) {
+            Some("copy" | "paste") => (Tool::Richclip, 2),
+            _ => return Ok(args),
+        },
+    };
+    let foreign = tool != Tool::Richclip;
+    let mut paste =
+        tool == Tool::WlPaste || (tool == Tool::Richclip && args[1].to_str() == Some("paste"));
+    let mut primary = None;
+    let mut foreground = false;
+    let mut list_types = false;
+    let mut newline = tool == Tool::WlPaste;
+    let mut has_type = false;
+
+    let mut rest = vec![];
+    let mut iter = args.iter().skip(skip);
+    while let Some(arg) = iter.next() {
+        match arg.to_str() {
+            Some("-o" | "-out" | "--paste") => paste = true,
+            Some("-i" | "-in") => paste = false,
+            Some(name @ ("-selection" | "-sel")) => {
+                let value = next_value(&mut iter, name)?;
+                primary = match value.as_str() {
+                    "clipboard" | "c" => Some(false),
+                    "primary" | "p" => Some(true),
+                    _ => bail!("Unsupported selection '{value}'"),
+                };
+            }
+            Some(name @ "-target") => {
+                let value = next_value(&mut iter, name)?;
+                target(&mut rest, value, &mut list_types, &mut has_type);
+            }
+            Some(name @ ("-t" | "--type")) if foreign => {
+                let value = next_value(&mut iter, name)?;
+                target(&mut rest, value, &mut list_types, &mut has_type);
+            }
+            Some("-quiet") => foreground = true,
+            Some("-f" | "--foreground") if foreign => foreground = true,
+            // The default of richclip
+            Some("-silent") => {}
+            Some("-p" | "--primary") if foreign => primary = Some(true),
+            Some("-l" | "--list-types") if foreign => list_types = true,
+            Some("-n" | "--no-newline") if tool == Tool::WlPaste => newline = false,
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    let mut ret = vec![args[0].clone(), if paste { "paste" } else { "copy" }.into()];
+    let primary = primary.unwrap_or(tool == Tool::Xclip && cfg!(target_os = "linux"));
+    if primary {
+        ret.push("--primary".into());
+    }
+    if paste {
+        if list_types {
+            ret.push("--list-types".into());
+        } else if newline {
+            ret.push("--newline".into());
+        }
+    } else {
+        if foreground {
+            ret.push("--foreground".into());
+        }
+        if foreign && !has_type {
+            ret.push("--one-shot".into());
+        }
+    }
+    ret.extend(rest);
+    log::debug!("Translated the arguments to {ret:?}");
+    Ok(ret)
+}
+
+fn next_value<'a>(iter: &mut impl Iterator<Item = &'a OsString>, name: &str) -> Result<String> {
+    iter.next()
+        .with_context(|| format!("Missing the value of '{name}'"))?
+        .to_str()
+        .map(|v| v.to_string())
+        .with_context(|| format!("The value of '{name}' is not valid UTF-8"))
+}
+
+// `TARGETS` lists the mime-types like `xclip -o -t TARGETS` does.
+fn target(rest: &mut Vec<OsString>, value: String, list_types: &mut bool, has_type: &mut bool) {
+    if value == "TARGETS" {
+        *list_types = true;
+    } else {
+        *has_type = true;
+        rest.push("--type".into());
+        rest.push(value.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(args: &[&str]) -> Vec<String> {
+        let args = args.iter().map(OsString::from).collect();
+        translate_args(args)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_xclip() {
+        let primary: &[&str] = if cfg!(target_os = "linux") {
+            &["--primary"]
+        } else {
+            &[]
+        };
+        assert_eq!(
+            translate(&["/usr/bin/xclip", "-selection", "clipboard"]),
+            ["/usr/bin/xclip", "copy", "--one-shot"]
+        );
+        assert_eq!(
+            translate(&["xclip", "-sel", "c", "-t", "text/html", "-quiet"]),
+            ["xclip", "copy", "--foreground", "--type", "text/html"]
+        );
+        // The primary selection by default
+        assert_eq!(
+            translate(&["xclip", "-o"]),
+            [&["xclip", "paste"], primary].concat()
+        );
+        assert_eq!(
+            translate(&[
+                "xclip",
+                "-selection",
+                "clipboard",
+                "-o",
+                "-target",
+                "TARGETS"
+            ]),
+            ["xclip", "paste", "--list-types"]
+        );
+        assert!(
+            translate_args(vec![
+                "xclip".into(),
+                "-selection".into(),
+                "secondary".into()
+            ])
+            .is_err()
+        );
+        assert!(translate_args(vec!["xclip".into(), "-sel".into()]).is_err());
+    }
+
+    #[test]
+    fn test_wl_clipboard() {
+        assert_eq!(
+            translate(&["wl-copy", "--type", "image/png", "-f"]),
+            ["wl-copy", "copy", "--foreground", "--type", "image/png"]
+        );
+        assert_eq!(
+            translate(&["wl-copy", "-p"]),
+            ["wl-copy", "copy", "--primary", "--one-shot"]
+        );
+        assert_eq!(
+            translate(&["wl-paste", "-t", "text/html"]),
+            ["wl-paste", "paste", "--newline", "--type", "text/html"]
+        );
+        assert_eq!(
+            translate(&["wl-paste", "-n", "--primary"]),
+            ["wl-paste", "paste", "--primary"]
+        );
+        assert_eq!(
+            translate(&["wl-paste", "-l"]),
+            ["wl-paste", "paste", "--list-types"]
+        );
+    }
+
+    #[test]
+    fn test_richclip() {
+        // The native arguments are kept
+        let args = ["richclip", "copy", "-t", "--one-shot", "-p"];
+        assert_eq!(translate(&args), args);
+        assert_eq!(
+            translate(&["richclip", "paste", "-selection", "primary", "-t", "TEXT"]),
+            ["richclip", "paste", "--primary", "-t", "TEXT"]
+        );
+        assert_eq!(
+            translate(&["richclip", "copy", "--paste", "-target", "TARGETS"]),
+            ["richclip", "paste", "--list-types"]
+        );
+        let args = ["richclip", "debug", "-o"];
+        assert_eq!(translate(&args), args);
+    }
+}
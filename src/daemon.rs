@@ -0,0 +1,257 @@
+use crate::clipboard::ClipboardSink;
+use crate::history::ClipboardHistory;
+use crate::protocol::SourceDataItem;
+use crate::store::{ContentStore, StoredItem, StoredSource};
+use anyhow::Result;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Command {
+    /// Take over the clipboard with new content.
+    Set(Vec<SourceDataItem>),
+    /// Re-set the currently held content to refresh the ownership. Some compositors drop a
+    /// selection which hasn't been asserted for a while, a reassert timer can send this
+    /// periodically to keep it alive.
+    Touch,
+}
+
+enum Held {
+    Items(Vec<SourceDataItem>),
+    Stored(Vec<StoredItem>),
+}
+
+/// Holds the clipboard content on behalf of the clients and forwards it to the sink.
+pub struct Daemon<S: ClipboardSink> {
+    sink: S,
+    current: Option<Held>,
+    history: ClipboardHistory,
+    store: Option<ContentStore>,
+}
+
+impl<S: ClipboardSink> Daemon<S> {
+    pub fn new(sink: S) -> Self {
+        Daemon {
+            sink,
+            current: None,
+            history: ClipboardHistory::default(),
+            store: None,
+        }
+    }
+
+    /// Offload the held contents to a `ContentStore` in the directory, only their hashes and
+    /// mime-types are kept in memory. They are offered as a `StoredSource`, which the sink fetches
+    /// from the store on paste. The contents are not added to the history then, since it would
+    /// keep them in memory.
+    pub fn with_content_store(mut self, dir: impl Into<PathBuf>) -> Result<Self> {
+        self.store = Some(ContentStore::new(dir)?);
+        Ok(self)
+    }
+
+    /// Set how many recent contents are kept in the history.
+    pub fn with_history_depth(mut self, depth: usize) -> Self {
+        self.history.set_depth(depth);
+        self
+    }
+
+    pub fn handle(&mut self, command: Command) -> Result<()> {
+        match command {
+            Command::Set(items) => match &self.store {
+                Some(store) => {
+                    let stored: Vec<_> = items
+                        .iter()
+                        .map(|item| store.put(item))
+                        .collect::<Result<_>>()?;
+                    drop(items);
+                    self.offer_stored(&stored)?;
+                    self.current = Some(Held::Stored(stored));
+                }
+                None => {
+                    self.sink.offer(&items)?;
+                    self.history.push(items.clone());
+                    self.current = Some(Held::Items(items));
+                }
+            },
+            Command::Touch => match &self.current {
+                Some(Held::Items(items)) => self.sink.offer(items)?,
+                Some(Held::Stored(stored)) => self.offer_stored(&stored.clone())?,
+                None => log::debug!("Nothing to touch, no content is held"),
+            },
+        }
+        Ok(())
+    }
+
+    fn offer_stored(&mut self, stored: &[StoredItem]) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        self.sink.offer_source(Box::new(StoredSource {
+            store: store.clone(),
+            items: stored.to_vec(),
+        }))
+    }
+
+    /// The held items, `None` if nothing is held or they are offloaded to the content store. See
+    /// `load_current`.
+    pub fn current(&self) -> Option<&[SourceDataItem]> {
+        match &self.current {
+            Some(Held::Items(items)) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// The held items, read from the content store if they are offloaded.
+    pub fn load_current(&self) -> Result<Option<Vec<SourceDataItem>>> {
+        match (&self.current, &self.store) {
+            (Some(Held::Items(items)), _) => Ok(Some(items.clone())),
+            (Some(Held::Stored(stored)), Some(store)) => Ok(Some(
+                stored
+                    .iter()
+                    .map(|item| store.get(item))
+                    .collect::<Result<_>>()?,
+            )),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn history(&self) -> &ClipboardHistory {
+        &self.history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct MockSink {
+        offered: Vec<Vec<SourceDataItem>>,
+    }
+
+    impl ClipboardSink for MockSink {
+        fn offer_with_priority(&mut self, items: &[(SourceDataItem, u16)]) -> Result<()> {
+            self.offered
+                .push(items.iter().map(|(item, _)| item.clone()).collect());
+            Ok(())
+        }
+    }
+
+    // Keeps the offered sources as they are, like a backend pasting from them later
+    #[derive(Default)]
+    struct SourceSink {
+        sources: Vec<Box<dyn crate::protocol::SourceData>>,
+    }
+
+    impl ClipboardSink for SourceSink {
+        fn offer_with_priority(&mut self, _: &[(SourceDataItem, u16)]) -> Result<()> {
+            unreachable!()
+        }
+
+        fn offer_source(&mut self, source: Box<dyn crate::protocol::SourceData>) -> Result<()> {
+            self.sources.push(source);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_touch() {
+        let mut daemon = Daemon::new(MockSink::default());
+
+        // Nothing held yet
+        daemon.handle(Command::Touch).unwrap();
+        assert!(daemon.sink.offered.is_empty());
+
+        let items = vec![SourceDataItem::new(
+            vec!["text/plain".to_string()],
+            Rc::new(b"GOOD".to_vec()),
+        )];
+        daemon.handle(Command::Set(items)).unwrap();
+        daemon.handle(Command::Touch).unwrap();
+
+        let offered = &daemon.sink.offered;
+        assert_eq!(offered.len(), 2);
+        assert_eq!(offered[1].len(), 1);
+        assert_eq!(offered[1][0].mime_type, offered[0][0].mime_type);
+        assert_eq!(offered[1][0].content, offered[0][0].content);
+        // Touching doesn't add history
+        assert_eq!(daemon.history().len(), 1);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut daemon = Daemon::new(MockSink::default()).with_history_depth(4);
+        assert_eq!(daemon.history().depth(), 4);
+        for content in [b"A", b"B", b"A"] {
+            let items = vec![SourceDataItem::new(
+                vec!["text/plain".to_string()],
+                Rc::new(content.to_vec()),
+            )];
+            daemon.handle(Command::Set(items)).unwrap();
+        }
+        let history: Vec<&[u8]> = daemon
+            .history()
+            .iter()
+            .map(|e| e.items[0].content.as_slice())
+            .collect();
+        assert_eq!(history, [b"B", b"A"]);
+    }
+
+    #[test]
+    fn test_content_store() {
+        let dir = std::env::temp_dir().join(format!("richclip-{}-daemon", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut daemon = Daemon::new(MockSink::default())
+            .with_content_store(&dir)
+            .unwrap();
+        assert!(daemon.load_current().unwrap().is_none());
+
+        let content: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let items = vec![
+            SourceDataItem::new(vec!["image/png".to_string()], Rc::new(content.clone())),
+            SourceDataItem::new(vec!["text/plain".to_string()], Rc::new(b"GOOD".to_vec())),
+        ];
+        daemon.handle(Command::Set(items)).unwrap();
+        assert_eq!(daemon.sink.offered.len(), 1);
+        // Only the offered items hold the contents
+        daemon.sink.offered.clear();
+        assert!(daemon.current().is_none());
+        assert!(daemon.history().is_empty());
+
+        let loaded = daemon.load_current().unwrap().unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].mime_type, ["image/png"]);
+        assert_eq!(*loaded[0].content, content);
+        assert_eq!(loaded[1].content.as_slice(), b"GOOD");
+
+        // Touching offers the stored contents again
+        daemon.handle(Command::Touch).unwrap();
+        assert_eq!(*daemon.sink.offered[0][0].content, content);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_content_store_fetch_on_paste() {
+        let dir = std::env::temp_dir().join(format!("richclip-{}-paste", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut daemon = Daemon::new(SourceSink::default())
+            .with_content_store(&dir)
+            .unwrap();
+        let content: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 253) as u8).collect();
+        let items = vec![SourceDataItem::new(
+            vec!["image/png".to_string()],
+            Rc::new(content.clone()),
+        )];
+        daemon.handle(Command::Set(items)).unwrap();
+        daemon.handle(Command::Touch).unwrap();
+
+        for source in &daemon.sink.sources {
+            assert_eq!(source.mime_types(), ["image/png"]);
+            let (found, pasted) = source.content_by_mime_type("image/png");
+            assert!(found);
+            assert_eq!(*pasted, content);
+        }
+        // Nothing left to paste once the store is gone
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!daemon.sink.sources[0].content_by_mime_type("image/png").0);
+    }
+}
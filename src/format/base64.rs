@@ -0,0 +1,114 @@
+use anyhow::{Result, bail};
+
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Encode with the standard alphabet and padding (RFC 4648, section 4).
+pub fn encode(data: &[u8]) -> String {
+    encode_with(data, STANDARD, true)
+}
+
+/// Encode with the URL and filename safe alphabet without padding (RFC 4648, section 5), to be
+/// embedded in URLs as it is.
+pub fn encode_url(data: &[u8]) -> String {
+    encode_with(data, URL_SAFE, false)
+}
+
+fn encode_with(data: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut ret = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(alphabet[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else if pad {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
+
+/// Decode the URL safe base64 of `encode_url`. The padding is optional, but must be complete if
+/// there is any. The trailing whitespace, e.g. the newline of `echo`, is ignored.
+pub fn decode_url(text: &[u8]) -> Result<Vec<u8>> {
+    let text = text.trim_ascii_end();
+    let data = text.strip_suffix(b"==").or_else(|| text.strip_suffix(b"="));
+    let data = match data {
+        Some(data) if !text.len().is_multiple_of(4) || data.len() % 4 < 2 => {
+            bail!("Invalid base64url padding")
+        }
+        Some(data) => data,
+        None => text,
+    };
+    if data.len() % 4 == 1 {
+        bail!("Invalid base64url length {}", data.len());
+    }
+    let mut ret = Vec::with_capacity(data.len() / 4 * 3 + 2);
+    for chunk in data.chunks(4) {
+        let mut n = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let Some(v) = URL_SAFE.iter().position(|a| a == c) else {
+                bail!("Invalid base64url character {:?}", *c as char);
+            };
+            n |= (v as u32) << (18 - i * 6);
+        }
+        ret.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        // Test vectors from RFC 4648
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"f"), "Zg==");
+        assert_eq!(encode(b"fo"), "Zm8=");
+        assert_eq!(encode(b"foo"), "Zm9v");
+        assert_eq!(encode(b"foob"), "Zm9vYg==");
+        assert_eq!(encode(b"fooba"), "Zm9vYmE=");
+        assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_encode_url() {
+        // Not padded
+        assert_eq!(encode_url(b""), "");
+        assert_eq!(encode_url(b"f"), "Zg");
+        assert_eq!(encode_url(b"fo"), "Zm8");
+        assert_eq!(encode_url(b"foo"), "Zm9v");
+        // '-' and '_' in place of '+' and '/'
+        assert_eq!(encode(&[0xfb, 0xff]), "+/8=");
+        assert_eq!(encode_url(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn test_decode_url() {
+        assert_eq!(decode_url(b"").unwrap(), b"");
+        assert_eq!(decode_url(b"Zg").unwrap(), b"f");
+        assert_eq!(decode_url(b"Zm8").unwrap(), b"fo");
+        assert_eq!(decode_url(b"Zm9vYmFy\n").unwrap(), b"foobar");
+        assert_eq!(decode_url(b"-_8").unwrap(), [0xfb, 0xff]);
+        // With padding
+        assert_eq!(decode_url(b"Zg==").unwrap(), b"f");
+        assert_eq!(decode_url(b"Zm8=").unwrap(), b"fo");
+        assert!(decode_url(b"Zg=").is_err());
+        assert!(decode_url(b"Zm9v=").is_err());
+        assert!(decode_url(b"Z").is_err());
+        // The standard alphabet is not accepted
+        assert!(decode_url(b"+/8").is_err());
+        assert!(decode_url(b"Zm 9v").is_err());
+
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode_url(encode_url(&data).as_bytes()).unwrap(), data);
+    }
+}
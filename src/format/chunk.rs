@@ -0,0 +1,244 @@
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// Write the content in chunks of `chunk_size` bytes, the last one may be shorter. Every chunk
+/// is flushed separately, and prefixed with its length as big-endian uint32 if `length_prefixed`
+/// is set, so the other side can reassemble it. Returns the number of chunks written.
+pub fn write_chunks(
+    content: &[u8],
+    chunk_size: usize,
+    length_prefixed: bool,
+    mut writer: impl Write,
+) -> std::io::Result<usize> {
+    let mut count = 0;
+    for chunk in content.chunks(chunk_size.max(1)) {
+        if length_prefixed {
+            writer.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        }
+        writer.write_all(chunk)?;
+        writer.flush()?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Same as `write_chunks` with `length_prefixed`, but every chunk is prefixed with its index
+/// before its length, both as big-endian uint32, so a transport may reorder them. An empty chunk
+/// with the index after the last one marks the end. Returns the number of chunks written.
+pub fn write_indexed_chunks(
+    content: &[u8],
+    chunk_size: usize,
+    mut writer: impl Write,
+) -> std::io::Result<usize> {
+    let mut count = 0;
+    for chunk in content.chunks(chunk_size.max(1)) {
+        writer.write_all(&(count as u32).to_be_bytes())?;
+        writer.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        writer.write_all(chunk)?;
+        writer.flush()?;
+        count += 1;
+    }
+    writer.write_all(&(count as u32).to_be_bytes())?;
+    writer.write_all(&0u32.to_be_bytes())?;
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Reassembles the indexed chunks in the order of their indices. The chunks arriving before the
+/// ones preceding them are buffered until the content is contiguous again.
+#[derive(Default)]
+pub struct ChunkAssembler {
+    content: Vec<u8>,
+    next_index: u32,
+    pending: BTreeMap<u32, Vec<u8>>,
+}
+
+impl ChunkAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of the bytes held, of the reassembled content and the buffered chunks.
+    pub fn len_bytes(&self) -> usize {
+        self.content.len() + self.pending.values().map(Vec::len).sum::<usize>()
+    }
+
+    pub fn push(&mut self, index: u32, data: Vec<u8>) -> Result<()> {
+        if index < self.next_index || self.pending.contains_key(&index) {
+            bail!("Duplicate chunk {index}");
+        }
+        if index > self.next_index {
+            self.pending.insert(index, data);
+            return Ok(());
+        }
+        self.content.extend(data);
+        self.next_index += 1;
+        while let Some(data) = self.pending.remove(&self.next_index) {
+            self.content.extend(data);
+            self.next_index += 1;
+        }
+        Ok(())
+    }
+
+    /// The reassembled content of the `count` chunks. It fails if any of them is missing.
+    pub fn finish(self, count: u32) -> Result<Vec<u8>> {
+        if let Some(index) = self.pending.keys().find(|i| **i >= count) {
+            bail!("Chunk {index} is after the end of {count} chunks");
+        }
+        if self.next_index < count {
+            bail!("Missing chunk {}", self.next_index);
+        }
+        Ok(self.content)
+    }
+}
+
+/// Read the chunks written by `write_indexed_chunks` until the end marker, and reassemble them.
+/// The content is limited to `u32::MAX` bytes like a section, see `reassemble_chunks_with_limit`.
+pub fn reassemble_chunks(reader: impl Read) -> Result<Vec<u8>> {
+    reassemble_chunks_with_limit(reader, u32::MAX as usize)
+}
+
+/// Same as `reassemble_chunks`, but it fails once the chunks received add up to more than
+/// `max_size` bytes. Every chunk is checked before its buffer is allocated.
+pub fn reassemble_chunks_with_limit(mut reader: impl Read, max_size: usize) -> Result<Vec<u8>> {
+    let mut assembler = ChunkAssembler::new();
+    loop {
+        let mut header = [0u8; 8];
+        reader
+            .read_exact(&mut header)
+            .context("Failed to read the chunk header, the end marker may be missing")?;
+        let index = u32::from_be_bytes(header[..4].try_into().unwrap());
+        let len = u32::from_be_bytes(header[4..].try_into().unwrap());
+        if len == 0 {
+            return assembler.finish(index);
+        }
+        let len = len as usize;
+        let held = assembler.len_bytes();
+        if held + len > max_size {
+            bail!(
+                "Chunk {index} of {len} bytes exceeds the limit {max_size}, {held} bytes are received"
+            );
+        }
+        let mut data = Vec::new();
+        data.try_reserve_exact(len)
+            .map_err(|_| anyhow::anyhow!("Failed to allocate {len} bytes for chunk {index}"))?;
+        data.resize(len, 0);
+        reader
+            .read_exact(&mut data)
+            .with_context(|| format!("Failed to read chunk {index}"))?;
+        assembler.push(index, data)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_chunks() {
+        let content: Vec<u8> = (0..10).collect();
+
+        let mut out = Vec::new();
+        assert_eq!(write_chunks(&content, 4, false, &mut out).unwrap(), 3);
+        assert_eq!(out, content);
+
+        let mut out = Vec::new();
+        assert_eq!(write_chunks(&content, 4, true, &mut out).unwrap(), 3);
+        #[rustfmt::skip]
+        assert_eq!(
+            out,
+            [0, 0, 0, 4, 0, 1, 2, 3,
+            0, 0, 0, 4, 4, 5, 6, 7,
+            0, 0, 0, 2, 8, 9]
+        );
+
+        // Exact multiple of the chunk size
+        let mut out = Vec::new();
+        assert_eq!(write_chunks(&content, 5, true, &mut out).unwrap(), 2);
+        assert_eq!(out.len(), 10 + 2 * 4);
+
+        // Nothing to write
+        let mut out = Vec::new();
+        assert_eq!(write_chunks(&[], 4, true, &mut out).unwrap(), 0);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_reassemble_chunks_in_order() {
+        let content: Vec<u8> = (0..10).collect();
+        let mut out = Vec::new();
+        assert_eq!(write_indexed_chunks(&content, 4, &mut out).unwrap(), 3);
+        #[rustfmt::skip]
+        assert_eq!(
+            out,
+            [0, 0, 0, 0, 0, 0, 0, 4, 0, 1, 2, 3,
+            0, 0, 0, 1, 0, 0, 0, 4, 4, 5, 6, 7,
+            0, 0, 0, 2, 0, 0, 0, 2, 8, 9,
+            0, 0, 0, 3, 0, 0, 0, 0]
+        );
+        assert_eq!(reassemble_chunks(&out[..]).unwrap(), content);
+
+        let mut out = Vec::new();
+        assert_eq!(write_indexed_chunks(&[], 4, &mut out).unwrap(), 0);
+        assert!(reassemble_chunks(&out[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reassemble_chunks_out_of_order() {
+        #[rustfmt::skip]
+        let buf = [
+            0, 0, 0, 2, 0, 0, 0, 2, 8, 9,
+            0, 0, 0, 0, 0, 0, 0, 4, 0, 1, 2, 3,
+            0, 0, 0, 3, 0, 0, 0, 0,
+            ];
+        let mut assembler = ChunkAssembler::new();
+        assembler.push(2, vec![8, 9]).unwrap();
+        assembler.push(0, vec![0, 1, 2, 3]).unwrap();
+        assembler.push(1, vec![4, 5, 6, 7]).unwrap();
+        assert_eq!(assembler.finish(3).unwrap(), (0..10).collect::<Vec<u8>>());
+
+        // Chunk 1 never arrives
+        let e = reassemble_chunks(&buf[..]).unwrap_err();
+        assert_eq!(e.to_string(), "Missing chunk 1");
+    }
+
+    #[test]
+    fn test_reassemble_chunks_errors() {
+        let mut assembler = ChunkAssembler::new();
+        assembler.push(0, vec![1]).unwrap();
+        assembler.push(2, vec![3]).unwrap();
+        assert!(assembler.push(0, vec![1]).is_err());
+        assert!(assembler.push(2, vec![3]).is_err());
+        let e = assembler.finish(2).unwrap_err();
+        assert_eq!(e.to_string(), "Chunk 2 is after the end of 2 chunks");
+
+        // No end marker
+        #[rustfmt::skip]
+        let buf = [0, 0, 0, 0, 0, 0, 0, 2, 8, 9];
+        assert!(reassemble_chunks(&buf[..]).is_err());
+        // Truncated chunk
+        assert!(reassemble_chunks(&buf[..9]).is_err());
+    }
+
+    #[test]
+    fn test_reassemble_chunks_with_limit() {
+        let content: Vec<u8> = (0..10).collect();
+        let mut out = Vec::new();
+        write_indexed_chunks(&content, 4, &mut out).unwrap();
+        assert_eq!(reassemble_chunks_with_limit(&out[..], 10).unwrap(), content);
+        let e = reassemble_chunks_with_limit(&out[..], 9).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Chunk 2 of 2 bytes exceeds the limit 9, 8 bytes are received"
+        );
+
+        // Checked before the data arrives
+        let buf = [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff];
+        let e = reassemble_chunks_with_limit(&buf[..], 1024).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Chunk 0 of 4294967295 bytes exceeds the limit 1024, 0 bytes are received"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{Result, bail};
+use std::str::FromStr;
+
+/// The result of `detect_encoding`.
+#[derive(Debug, PartialEq)]
+pub struct EncodingGuess {
+    /// The WHATWG name of the encoding.
+    pub name: &'static str,
+    /// From 0 to 1.
+    pub confidence: f32,
+}
+
+/// Guess the encoding of the text with simple heuristics: byte order marks, UTF-8 validity, and
+/// the distribution of null bytes for UTF-16. Invalid UTF-8 with high bytes is assumed to be
+/// one of the Latin-1 family, which is the most common case in practice but only a guess.
+pub fn detect_encoding(content: &[u8]) -> EncodingGuess {
+    let guess = |name, confidence| EncodingGuess { name, confidence };
+    for (bom, name) in [
+        (&[0xEF, 0xBB, 0xBF][..], "UTF-8"),
+        (&[0xFF, 0xFE], "UTF-16LE"),
+        (&[0xFE, 0xFF], "UTF-16BE"),
+    ] {
+        if content.starts_with(bom) {
+            return guess(name, 1.0);
+        }
+    }
+
+    // Mostly ASCII text in UTF-16 has a null byte in every other position
+    if content.len() >= 2 {
+        let nulls_at = |parity: usize| {
+            content
+                .iter()
+                .skip(parity)
+                .step_by(2)
+                .filter(|b| **b == 0)
+                .count() as f32
+                / (content.len() / 2) as f32
+        };
+        let (even, odd) = (nulls_at(0), nulls_at(1));
+        if odd > 0.5 && even < 0.1 {
+            return guess("UTF-16LE", odd.min(0.9));
+        }
+        if even > 0.5 && odd < 0.1 {
+            return guess("UTF-16BE", even.min(0.9));
+        }
+    }
+
+    if content.is_ascii() {
+        return guess("US-ASCII", 1.0);
+    }
+    if let Ok(text) = std::str::from_utf8(content) {
+        // Random bytes are rarely valid UTF-8, the more multi-byte chars the more likely
+        let multi_byte = text.chars().filter(|c| !c.is_ascii()).count();
+        return guess("UTF-8", (0.8 + multi_byte as f32 * 0.05).min(0.99));
+    }
+    // 0x80 - 0x9F are control characters in ISO-8859-1, but printable in windows-1252
+    if content.iter().any(|b| (0x80..0xA0).contains(b)) {
+        guess("windows-1252", 0.6)
+    } else {
+        guess("ISO-8859-1", 0.6)
+    }
+}
+
+/// A single-byte encoding the UTF-8 text can be converted to with `encode_text`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputEncoding {
+    Ascii,
+    Latin1,
+}
+
+impl OutputEncoding {
+    /// The highest code point the encoding can represent, which maps to the byte of the same value.
+    fn max_char(self) -> char {
+        match self {
+            OutputEncoding::Ascii => '\x7f',
+            OutputEncoding::Latin1 => '\u{ff}',
+        }
+    }
+}
+
+impl FromStr for OutputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ascii" | "us-ascii" => Ok(OutputEncoding::Ascii),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(OutputEncoding::Latin1),
+            _ => Err(format!("Unsupported encoding '{s}', use ascii or latin-1")),
+        }
+    }
+}
+
+/// Convert the UTF-8 text to the encoding. Characters which can't be represented, and invalid
+/// UTF-8, are errors, or replaced with `?` if `replace` is true.
+pub fn encode_text(content: &[u8], encoding: OutputEncoding, replace: bool) -> Result<Vec<u8>> {
+    let text = match std::str::from_utf8(content) {
+        Ok(text) => std::borrow::Cow::Borrowed(text),
+        Err(e) if !replace => bail!("The text content is not valid UTF-8, {e}"),
+        Err(_) => String::from_utf8_lossy(content),
+    };
+    let mut ret = Vec::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        if c <= encoding.max_char() {
+            ret.push(c as u8);
+        } else if replace {
+            ret.push(b'?');
+        } else {
+            bail!("Character '{c}' at byte {i} can't be represented in {encoding:?}");
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_encoding() {
+        assert_eq!(detect_encoding(b"GOOD").name, "US-ASCII");
+        assert_eq!(detect_encoding(b"").name, "US-ASCII");
+
+        let utf8 = detect_encoding("café crème".as_bytes());
+        assert_eq!(utf8.name, "UTF-8");
+        assert!(utf8.confidence > 0.8);
+
+        let latin1 = detect_encoding(b"caf\xe9 cr\xe8me");
+        assert_eq!(latin1.name, "ISO-8859-1");
+        assert!(latin1.confidence < utf8.confidence);
+        assert_eq!(detect_encoding(b"\x93quoted\x94").name, "windows-1252");
+
+        assert_eq!(detect_encoding(b"\xef\xbb\xbfGOOD").name, "UTF-8");
+        assert_eq!(detect_encoding(b"\xff\xfeG\0").name, "UTF-16LE");
+        assert_eq!(detect_encoding(b"G\0O\0O\0D\0").name, "UTF-16LE");
+        assert_eq!(detect_encoding(b"\0G\0O\0O\0D").name, "UTF-16BE");
+    }
+
+    #[test]
+    fn test_encode_text() {
+        let latin1 = OutputEncoding::from_str("Latin-1").unwrap();
+        assert_eq!(latin1, OutputEncoding::Latin1);
+        assert!(OutputEncoding::from_str("utf-16").is_err());
+
+        let text = "café crème".as_bytes();
+        assert_eq!(
+            encode_text(text, latin1, false).unwrap(),
+            b"caf\xe9 cr\xe8me"
+        );
+        assert_eq!(
+            encode_text(b"GOOD", OutputEncoding::Ascii, false).unwrap(),
+            b"GOOD"
+        );
+
+        // Unrepresentable characters
+        let err = encode_text(text, OutputEncoding::Ascii, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Character 'é' at byte 3 can't be represented in Ascii"
+        );
+        assert_eq!(
+            encode_text(text, OutputEncoding::Ascii, true).unwrap(),
+            b"caf? cr?me"
+        );
+        let text = "€5".as_bytes();
+        assert!(encode_text(text, latin1, false).is_err());
+        assert_eq!(encode_text(text, latin1, true).unwrap(), b"?5");
+
+        // Invalid UTF-8
+        assert!(encode_text(b"caf\xe9", latin1, false).is_err());
+        assert_eq!(encode_text(b"caf\xe9", latin1, true).unwrap(), b"caf?");
+    }
+}
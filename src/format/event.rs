@@ -0,0 +1,75 @@
+use super::base64;
+use crate::protocol::SourceDataItem;
+use serde_json::{Value, json};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// In chars
+const PREVIEW_LEN: usize = 80;
+
+impl SourceDataItem {
+    /// A JSON event describing the item, for streaming clipboard changes to other services:
+    /// `{"event": ..., "timestamp": ..., "mimeTypes": [...], "sizeBytes": ..., "preview": ...}`.
+    /// The timestamp is in milliseconds since the UNIX epoch. The preview is the beginning of
+    /// the text content, or null for other contents.
+    /// The content itself is left out for privacy, see `to_clipboard_event_json_with_content`.
+    pub fn to_clipboard_event_json(&self, event_type: &str) -> Value {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let is_text = self.is_text();
+        let preview = match std::str::from_utf8(&self.content) {
+            Ok(text) if is_text => Value::from(text.chars().take(PREVIEW_LEN).collect::<String>()),
+            _ => Value::Null,
+        };
+        json!({
+            "event": event_type,
+            "timestamp": timestamp,
+            "mimeTypes": self.mime_type,
+            "sizeBytes": self.content.len(),
+            "preview": preview,
+        })
+    }
+
+    /// Same as `to_clipboard_event_json`, with the content encoded in base64 as `content`.
+    pub fn to_clipboard_event_json_with_content(&self, event_type: &str) -> Value {
+        let mut event = self.to_clipboard_event_json(event_type);
+        event["content"] = Value::from(base64::encode(&self.content));
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_clipboard_event_json() {
+        let item = SourceDataItem::new(
+            vec!["text/plain".to_string(), "TEXT".to_string()],
+            "GOOD".repeat(30).into_bytes(),
+        );
+        let event = item.to_clipboard_event_json("copy");
+        assert_eq!(event["event"], "copy");
+        assert!(event["timestamp"].as_u64().unwrap() > 0);
+        assert_eq!(event["mimeTypes"], json!(["text/plain", "TEXT"]));
+        assert_eq!(event["sizeBytes"], 120);
+        assert_eq!(event["preview"].as_str().unwrap().len(), PREVIEW_LEN);
+        assert!(event.get("content").is_none());
+
+        let event = item.to_clipboard_event_json_with_content("copy");
+        assert_eq!(
+            event["content"].as_str().unwrap(),
+            base64::encode(&item.content)
+        );
+    }
+
+    #[test]
+    fn test_to_clipboard_event_json_binary() {
+        let item = SourceDataItem::new(vec!["image/png".to_string()], b"\x89PNG".to_vec());
+        let event = item.to_clipboard_event_json("paste");
+        assert_eq!(event["event"], "paste");
+        assert_eq!(event["sizeBytes"], 4);
+        assert!(event["preview"].is_null());
+    }
+}
@@ -0,0 +1,320 @@
+use super::json::pretty_json;
+use std::io::Write;
+use termcolor::{Ansi, Color, ColorSpec, WriteColor};
+
+/// Whether the output should be colored. A non-empty `NO_COLOR` or a dumb terminal disables it.
+pub fn colors_enabled() -> bool {
+    colors_enabled_by(
+        std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()),
+        std::env::var("TERM").is_ok_and(|t| t == "dumb"),
+    )
+}
+
+fn colors_enabled_by(no_color: bool, dumb_term: bool) -> bool {
+    !no_color && !dumb_term
+}
+
+#[derive(Debug, PartialEq)]
+enum Language {
+    Json,
+    Markup,
+    Code,
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Key,
+    String,
+    Number,
+    Keyword,
+    Tag,
+    Attribute,
+    Comment,
+}
+
+impl Token {
+    fn color(self) -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        match self {
+            Token::Key => spec.set_fg(Some(Color::Blue)).set_bold(true),
+            Token::String => spec.set_fg(Some(Color::Green)),
+            Token::Number => spec.set_fg(Some(Color::Cyan)),
+            Token::Keyword => spec.set_fg(Some(Color::Magenta)),
+            Token::Tag => spec.set_fg(Some(Color::Blue)),
+            Token::Attribute => spec.set_fg(Some(Color::Yellow)),
+            Token::Comment => spec.set_fg(Some(Color::Black)).set_intense(true),
+        };
+        spec
+    }
+}
+
+const CODE_MIME_SUFFIXES: &[&str] = &[
+    "script",
+    "x-rust",
+    "x-python",
+    "x-csrc",
+    "x-c++src",
+    "x-java",
+    "x-go",
+    "x-sh",
+    "x-shellscript",
+];
+
+const KEYWORDS: &[&str] = &[
+    "fn", "let", "mut", "pub", "impl", "struct", "enum", "match", "use", "if", "else", "for",
+    "while", "loop", "return", "def", "class", "import", "from", "function", "const", "var",
+    "true", "false", "null", "None", "True", "False",
+];
+
+fn detect_language(mime_type: &str, text: &str) -> Option<Language> {
+    let mime_type = mime_type.to_ascii_lowercase();
+    if mime_type.contains("json") {
+        return Some(Language::Json);
+    }
+    if mime_type.contains("html") || mime_type.contains("xml") {
+        return Some(Language::Markup);
+    }
+    let subtype = mime_type.rsplit('/').next().unwrap_or_default();
+    if CODE_MIME_SUFFIXES.iter().any(|s| subtype.ends_with(s)) {
+        return Some(Language::Code);
+    }
+    // Guess by the content for plain text
+    let trimmed = text.trim();
+    if trimmed.starts_with(['{', '[']) && pretty_json(trimmed.as_bytes()).is_ok() {
+        Some(Language::Json)
+    } else if trimmed.starts_with('<') && trimmed.ends_with('>') {
+        Some(Language::Markup)
+    } else {
+        None
+    }
+}
+
+/// Highlight the text with ANSI colors if the mime-type, or the content for plain text, tells it
+/// is JSON, HTML/XML or source code. `None` is returned for other contents, including binary,
+/// and when the colors are disabled, see `colors_enabled`.
+pub fn highlight(content: &[u8], mime_type: &str) -> Option<Vec<u8>> {
+    highlight_with(content, mime_type, colors_enabled())
+}
+
+fn highlight_with(content: &[u8], mime_type: &str, colors_enabled: bool) -> Option<Vec<u8>> {
+    if !colors_enabled {
+        return None;
+    }
+    let text = std::str::from_utf8(content).ok()?;
+    let language = detect_language(mime_type, text)?;
+    let mut out = Ansi::new(Vec::with_capacity(content.len() * 2));
+    match language {
+        Language::Json => highlight_json(text, &mut out),
+        Language::Markup => highlight_markup(text, &mut out),
+        Language::Code => highlight_code(text, &mut out),
+    }
+    Some(out.into_inner())
+}
+
+// Writing to a Vec never fails.
+fn write(out: &mut Ansi<Vec<u8>>, text: &str, token: Option<Token>) {
+    match token {
+        Some(token) => {
+            out.set_color(&token.color()).unwrap();
+            out.write_all(text.as_bytes()).unwrap();
+            out.reset().unwrap();
+        }
+        None => out.write_all(text.as_bytes()).unwrap(),
+    }
+}
+
+// The end of the quoted string starting at `start`, skipping the escaped quotes. An unclosed
+// string runs to the end.
+fn string_end(text: &str, start: usize) -> usize {
+    let quote = text.as_bytes()[start];
+    let mut escaped = false;
+    for (i, b) in text.bytes().enumerate().skip(start + 1) {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            _ if b == quote => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+fn char_end(text: &str, start: usize) -> usize {
+    start + text[start..].chars().next().map_or(1, char::len_utf8)
+}
+
+fn word_end(text: &str, start: usize, is_word: impl Fn(u8) -> bool) -> usize {
+    text.bytes()
+        .skip(start)
+        .position(|b| !is_word(b))
+        .map_or(text.len(), |n| start + n)
+}
+
+fn highlight_json(text: &str, out: &mut Ansi<Vec<u8>>) {
+    let bytes = text.as_bytes();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let (end, token) = match bytes[pos] {
+            b'"' => {
+                let end = string_end(text, pos);
+                let is_key = text[end..].trim_start().starts_with(':');
+                (end, Some(if is_key { Token::Key } else { Token::String }))
+            }
+            b'-' | b'0'..=b'9' => (
+                word_end(text, pos + 1, |b| {
+                    b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-')
+                }),
+                Some(Token::Number),
+            ),
+            b'a'..=b'z' => (
+                word_end(text, pos, |b| b.is_ascii_alphabetic()),
+                Some(Token::Keyword),
+            ),
+            _ => (char_end(text, pos), None),
+        };
+        write(out, &text[pos..end], token);
+        pos = end;
+    }
+}
+
+fn highlight_markup(text: &str, out: &mut Ansi<Vec<u8>>) {
+    let mut pos = 0;
+    while pos < text.len() {
+        if text[pos..].starts_with("<!--") {
+            let end = text[pos..].find("-->").map_or(text.len(), |n| pos + n + 3);
+            write(out, &text[pos..end], Some(Token::Comment));
+            pos = end;
+        } else if text[pos..].starts_with('<') {
+            pos = highlight_tag(text, pos, out);
+        } else {
+            let end = text[pos..].find('<').map_or(text.len(), |n| pos + n);
+            write(out, &text[pos..end], None);
+            pos = end;
+        }
+    }
+}
+
+// Highlight the tag starting at `start`, returns where it ends.
+fn highlight_tag(text: &str, start: usize, out: &mut Ansi<Vec<u8>>) -> usize {
+    let is_name = |b: u8| b.is_ascii_alphanumeric() || matches!(b, b'-' | b':' | b'_' | b'.');
+    let bytes = text.as_bytes();
+    let mut pos = start + 1;
+    while matches!(bytes.get(pos), Some(b'/' | b'!' | b'?')) {
+        pos += 1;
+    }
+    let name_end = word_end(text, pos, is_name);
+    write(out, &text[start..name_end], Some(Token::Tag));
+    pos = name_end;
+    while pos < bytes.len() {
+        let (end, token) = match bytes[pos] {
+            b'>' => {
+                write(out, ">", Some(Token::Tag));
+                return pos + 1;
+            }
+            b'"' | b'\'' => (string_end(text, pos), Some(Token::String)),
+            b if is_name(b) => (word_end(text, pos, is_name), Some(Token::Attribute)),
+            _ => (char_end(text, pos), None),
+        };
+        write(out, &text[pos..end], token);
+        pos = end;
+    }
+    pos
+}
+
+fn highlight_code(text: &str, out: &mut Ansi<Vec<u8>>) {
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let bytes = text.as_bytes();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let at_word_start = pos == 0 || !is_word(bytes[pos - 1]);
+        let line_end = text[pos..].find('\n').map_or(text.len(), |n| pos + n);
+        let (end, token) = match bytes[pos] {
+            b'"' | b'\'' => (string_end(text, pos).min(line_end), Some(Token::String)),
+            b'/' if text[pos..].starts_with("//") => (line_end, Some(Token::Comment)),
+            b'#' if pos == 0 || bytes[pos - 1].is_ascii_whitespace() => {
+                (line_end, Some(Token::Comment))
+            }
+            b'0'..=b'9' if at_word_start => (word_end(text, pos, is_word), Some(Token::Number)),
+            b if is_word(b) && at_word_start => {
+                let end = word_end(text, pos, is_word);
+                let is_keyword = KEYWORDS.contains(&&text[pos..end]);
+                (end, is_keyword.then_some(Token::Keyword))
+            }
+            _ => (char_end(text, pos), None),
+        };
+        write(out, &text[pos..end], token);
+        pos = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_ansi(content: &[u8]) -> String {
+        let text = String::from_utf8(content.to_vec()).unwrap();
+        let mut ret = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                ret.push(c);
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn test_highlight_json() {
+        let json = br#"{"key": "value", "n": -1.5e3, "ok": true}"#;
+        let r = highlight_with(json, "application/json", true).unwrap();
+        let text = String::from_utf8(r.clone()).unwrap();
+        assert!(text.contains("\x1b[1m\x1b[34m\"key\"\x1b[0m"));
+        assert!(text.contains("\x1b[32m\"value\"\x1b[0m"));
+        assert!(text.contains("\x1b[36m-1.5e3\x1b[0m"));
+        assert!(text.contains("\x1b[35mtrue\x1b[0m"));
+        assert_eq!(strip_ansi(&r).as_bytes(), json);
+
+        // Detected by the content
+        assert!(highlight_with(json, "", true).is_some());
+        assert!(highlight_with(json, "text/plain", true).is_some());
+    }
+
+    #[test]
+    fn test_highlight_markup_and_code() {
+        let html = b"<!-- c --><a href=\"x\">link</a>";
+        let r = highlight_with(html, "text/html", true).unwrap();
+        assert!(String::from_utf8_lossy(&r).contains("\x1b[33mhref\x1b[0m"));
+        assert_eq!(strip_ansi(&r).as_bytes(), html);
+
+        let code = "fn main() {\n    // comment\n    let s = \"str\";\n}";
+        let r = highlight_with(code.as_bytes(), "text/x-rust", true).unwrap();
+        assert!(String::from_utf8_lossy(&r).contains("\x1b[35mfn\x1b[0m"));
+        assert_eq!(strip_ansi(&r), code);
+    }
+
+    #[test]
+    fn test_highlight_plain() {
+        assert!(highlight_with(b"plain text", "text/plain", true).is_none());
+        assert!(highlight_with(b"{not json", "", true).is_none());
+        assert!(highlight_with(b"\x89PNG\xff", "image/png", true).is_none());
+        assert!(highlight_with(b"\xff{}", "application/json", true).is_none());
+        // Non-ASCII outside of strings
+        let r = highlight_with("<p é=ü>ö</p>".as_bytes(), "text/html", true).unwrap();
+        assert_eq!(strip_ansi(&r), "<p é=ü>ö</p>");
+        let r = highlight_with("{\"a\":é}".as_bytes(), "application/json", true).unwrap();
+        assert_eq!(strip_ansi(&r), "{\"a\":é}");
+    }
+
+    #[test]
+    fn test_colors_enabled() {
+        assert!(colors_enabled_by(false, false));
+        assert!(!colors_enabled_by(true, false));
+        assert!(!colors_enabled_by(false, true));
+
+        // NO_COLOR is set
+        let enabled = colors_enabled_by(true, false);
+        assert!(highlight_with(b"{\"a\": 1}", "application/json", enabled).is_none());
+    }
+}
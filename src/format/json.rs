@@ -0,0 +1,256 @@
+use anyhow::{Result, bail};
+
+const INDENT: &[u8] = b"  ";
+
+/// Validate the JSON text and print it indented by two spaces, followed by a line feed.
+/// Numbers and strings are kept as they are written, and the order of the keys is kept.
+pub fn pretty_json(input: &[u8]) -> Result<Vec<u8>> {
+    let mut printer = Printer {
+        input,
+        pos: 0,
+        out: Vec::with_capacity(input.len() * 2),
+    };
+    printer.skip_whitespace();
+    printer.value(0)?;
+    printer.skip_whitespace();
+    if printer.pos < input.len() {
+        printer.fail("end of input")?;
+    }
+    printer.out.push(b'\n');
+    Ok(printer.out)
+}
+
+struct Printer<'a> {
+    input: &'a [u8],
+    pos: usize,
+    out: Vec<u8>,
+}
+
+impl Printer<'_> {
+    fn fail(&self, expected: &str) -> Result<()> {
+        match self.input.get(self.pos) {
+            Some(c) => bail!(
+                "Invalid JSON at byte {}: expected {expected} but found '{}'",
+                self.pos,
+                c.escape_ascii()
+            ),
+            None => bail!("Invalid JSON: expected {expected} but reached the end"),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn newline(&mut self, depth: usize) {
+        self.out.push(b'\n');
+        for _ in 0..depth {
+            self.out.extend(INDENT);
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<()> {
+        match self.peek() {
+            Some(b'{') => self.container(depth, b'}'),
+            Some(b'[') => self.container(depth, b']'),
+            Some(b'"') => self.string(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => {
+                for literal in [&b"true"[..], b"false", b"null"] {
+                    if self.input[self.pos..].starts_with(literal) {
+                        self.out.extend(literal);
+                        self.pos += literal.len();
+                        return Ok(());
+                    }
+                }
+                self.fail("a value")
+            }
+        }
+    }
+
+    fn container(&mut self, depth: usize, close: u8) -> Result<()> {
+        self.out.push(self.input[self.pos]);
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.peek() == Some(close) {
+            self.out.push(close);
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            self.newline(depth + 1);
+            if close == b'}' {
+                if self.peek() != Some(b'"') {
+                    return self.fail("a key");
+                }
+                self.string()?;
+                self.skip_whitespace();
+                if self.peek() != Some(b':') {
+                    return self.fail("':'");
+                }
+                self.pos += 1;
+                self.out.extend(b": ");
+                self.skip_whitespace();
+            }
+            self.value(depth + 1)?;
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => {
+                    self.pos += 1;
+                    self.out.push(b',');
+                    self.skip_whitespace();
+                }
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    self.newline(depth);
+                    self.out.push(close);
+                    return Ok(());
+                }
+                _ => {
+                    return self.fail(if close == b'}' {
+                        "',' or '}'"
+                    } else {
+                        "',' or ']'"
+                    });
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<()> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => {
+                            self.pos += 1
+                        }
+                        Some(b'u') => {
+                            self.pos += 1;
+                            for _ in 0..4 {
+                                if !self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                                    return self.fail("a hex digit");
+                                }
+                                self.pos += 1;
+                            }
+                        }
+                        _ => return self.fail("an escape character"),
+                    }
+                }
+                Some(0..0x20) | None => return self.fail("'\"'"),
+                Some(_) => self.pos += 1,
+            }
+        }
+        self.pos += 1;
+        if std::str::from_utf8(&self.input[start..self.pos]).is_err() {
+            bail!("Invalid JSON at byte {start}: the string is not valid UTF-8");
+        }
+        self.out.extend(&self.input[start..self.pos]);
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<()> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return self.fail("a digit"),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return self.fail("a digit");
+            }
+            self.digits();
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return self.fail("a digit");
+            }
+            self.digits();
+        }
+        self.out.extend(&self.input[start..self.pos]);
+        Ok(())
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pretty_json() {
+        let input = br#" {"b":[1,-2.5e+3,{}],"a":{"x":null,"y":[]},"s":"q\"\u00e9 ok","t":true} "#;
+        let expected = r#"{
+  "b": [
+    1,
+    -2.5e+3,
+    {}
+  ],
+  "a": {
+    "x": null,
+    "y": []
+  },
+  "s": "q\"\u00e9 ok",
+  "t": true
+}
+"#;
+        assert_eq!(
+            String::from_utf8(pretty_json(input).unwrap()).unwrap(),
+            expected
+        );
+        assert_eq!(pretty_json(b"\"text\"").unwrap(), b"\"text\"\n");
+        // Already indented
+        assert_eq!(
+            pretty_json(expected.as_bytes()).unwrap(),
+            expected.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        for input in [
+            &b""[..],
+            b"{",
+            b"[1,]",
+            b"{\"a\" 1}",
+            b"{a: 1}",
+            b"01",
+            b"1.",
+            b"\"unclosed",
+            b"\"bad \\x\"",
+            b"tru",
+            b"1 2",
+        ] {
+            assert!(pretty_json(input).is_err(), "{}", input.escape_ascii());
+        }
+        let e = pretty_json(b"[1,]").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Invalid JSON at byte 3: expected a value but found ']'"
+        );
+    }
+}
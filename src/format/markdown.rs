@@ -0,0 +1,289 @@
+use crate::protocol::SourceDataItem;
+
+/// Render the Markdown content of the item as a `text/html` item.
+pub fn render_markdown_item(item: &SourceDataItem) -> SourceDataItem {
+    let html = markdown_to_html(&String::from_utf8_lossy(&item.content));
+    SourceDataItem::new(vec!["text/html".to_string()], html.into_bytes())
+}
+
+/// A minimal Markdown renderer which supports headings, paragraphs, lists, block quotes, fenced
+/// code blocks, horizontal rules, and inline code, emphasis and links.
+/// Anything it doesn't understand, including unclosed inline markers, is kept as plain text, so
+/// malformed Markdown still produces reasonable HTML.
+pub fn markdown_to_html(markdown: &str) -> String {
+    let mut out = String::new();
+    let mut block = Block::None;
+    let mut lines = markdown.lines();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+
+        if let Some(fence) = trimmed.strip_prefix("```") {
+            block.close(&mut out);
+            let lang = fence.trim();
+            if lang.is_empty() {
+                out.push_str("<pre><code>");
+            } else {
+                out.push_str(&format!(
+                    "<pre><code class=\"language-{}\">",
+                    escape_html(lang)
+                ));
+            }
+            // An unclosed fence runs to the end
+            for code in lines.by_ref() {
+                if code.trim() == "```" {
+                    break;
+                }
+                out.push_str(&escape_html(code));
+                out.push('\n');
+            }
+            out.push_str("</code></pre>\n");
+        } else if trimmed.is_empty() {
+            block.close(&mut out);
+        } else if let Some((level, text)) = heading(trimmed) {
+            block.close(&mut out);
+            out.push_str(&format!("<h{level}>{}</h{level}>\n", render_inline(text)));
+        } else if is_rule(trimmed) {
+            block.close(&mut out);
+            out.push_str("<hr>\n");
+        } else if let Some(text) = trimmed.strip_prefix('>') {
+            block.switch(Block::Quote, &mut out);
+            out.push_str(&format!("<p>{}</p>\n", render_inline(text.trim())));
+        } else if let Some(text) = unordered_item(trimmed) {
+            block.switch(Block::UnorderedList, &mut out);
+            out.push_str(&format!("<li>{}</li>\n", render_inline(text)));
+        } else if let Some(text) = ordered_item(trimmed) {
+            block.switch(Block::OrderedList, &mut out);
+            out.push_str(&format!("<li>{}</li>\n", render_inline(text)));
+        } else if block == Block::Paragraph {
+            out.push('\n');
+            out.push_str(&render_inline(trimmed));
+        } else {
+            block.switch(Block::Paragraph, &mut out);
+            out.push_str(&render_inline(trimmed));
+        }
+    }
+    block.close(&mut out);
+    out
+}
+
+#[derive(PartialEq)]
+enum Block {
+    None,
+    Paragraph,
+    Quote,
+    UnorderedList,
+    OrderedList,
+}
+
+impl Block {
+    fn switch(&mut self, to: Block, out: &mut String) {
+        if *self == to {
+            return;
+        }
+        self.close(out);
+        out.push_str(match to {
+            Block::None => "",
+            Block::Paragraph => "<p>",
+            Block::Quote => "<blockquote>\n",
+            Block::UnorderedList => "<ul>\n",
+            Block::OrderedList => "<ol>\n",
+        });
+        *self = to;
+    }
+
+    fn close(&mut self, out: &mut String) {
+        out.push_str(match self {
+            Block::None => "",
+            Block::Paragraph => "</p>\n",
+            Block::Quote => "</blockquote>\n",
+            Block::UnorderedList => "</ul>\n",
+            Block::OrderedList => "</ol>\n",
+        });
+        *self = Block::None;
+    }
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.bytes().take_while(|b| *b == b'#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+fn is_rule(line: &str) -> bool {
+    let line: String = line.chars().filter(|c| *c != ' ').collect();
+    line.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|m| line.chars().all(|c| c == *m))
+}
+
+fn unordered_item(line: &str) -> Option<&str> {
+    ["- ", "* ", "+ "].iter().find_map(|m| line.strip_prefix(m))
+}
+
+fn ordered_item(line: &str) -> Option<&str> {
+    let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 {
+        return None;
+    }
+    line[digits..]
+        .strip_prefix(". ")
+        .or_else(|| line[digits..].strip_prefix(") "))
+}
+
+fn render_inline(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let after = &rest[c.len_utf8()..];
+        match c {
+            '\\' if after.starts_with(|c: char| c.is_ascii_punctuation()) => {
+                let escaped = after.chars().next().unwrap();
+                out.push_str(&escape_html(&escaped.to_string()));
+                rest = &after[1..];
+                continue;
+            }
+            '`' => {
+                if let Some(end) = after.find('`') {
+                    out.push_str(&format!("<code>{}</code>", escape_html(&after[..end])));
+                    rest = &after[end + 1..];
+                    continue;
+                }
+            }
+            '*' | '_' => {
+                let strong = if c == '*' { "**" } else { "__" };
+                if let Some(inner) = rest.strip_prefix(strong)
+                    && let Some(end) = inner.find(strong)
+                    && end > 0
+                {
+                    out.push_str(&format!(
+                        "<strong>{}</strong>",
+                        render_inline(&inner[..end])
+                    ));
+                    rest = &inner[end + 2..];
+                    continue;
+                }
+                if let Some(end) = after.find(c)
+                    && end > 0
+                {
+                    out.push_str(&format!("<em>{}</em>", render_inline(&after[..end])));
+                    rest = &after[end + 1..];
+                    continue;
+                }
+            }
+            '[' => {
+                if let Some((label, url, len)) = link(rest) {
+                    out.push_str(&format!(
+                        "<a href=\"{}\">{}</a>",
+                        escape_html(url),
+                        render_inline(label)
+                    ));
+                    rest = &rest[len..];
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push_str(&escape_html(&rest[..c.len_utf8()]));
+        rest = after;
+    }
+    out
+}
+
+// "[label](url)" at the start of the text, returns the label, the url and the length of the link.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let label_end = text.find("](")?;
+    let url_len = text[label_end + 2..].find(')')?;
+    let label = &text[1..label_end];
+    let url = &text[label_end + 2..label_end + 2 + url_len];
+    Some((label, url.trim(), label_end + 2 + url_len + 1))
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown_item() {
+        let text = SourceDataItem::new(
+            vec!["text/plain".to_string()],
+            b"# Title\n\nSome **bold** text".to_vec(),
+        );
+        let html = render_markdown_item(&text);
+        assert_eq!(html.mime_type, vec!["text/html"]);
+        assert_eq!(
+            String::from_utf8_lossy(&html.content),
+            "<h1>Title</h1>\n<p>Some <strong>bold</strong> text</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_markdown_to_html() {
+        let md = "\
+## Sub *title* ##
+first line
+second `a<b` line
+
+- [link](https://x.org)
+- two
+1. one
+> quote
+---
+```rust
+let a = 1 < 2;
+```";
+        let html = "\
+<h2>Sub <em>title</em></h2>
+<p>first line
+second <code>a&lt;b</code> line</p>
+<ul>
+<li><a href=\"https://x.org\">link</a></li>
+<li>two</li>
+</ul>
+<ol>
+<li>one</li>
+</ol>
+<blockquote>
+<p>quote</p>
+</blockquote>
+<hr>
+<pre><code class=\"language-rust\">let a = 1 &lt; 2;
+</code></pre>
+";
+        assert_eq!(markdown_to_html(md), html);
+    }
+
+    #[test]
+    fn test_malformed_markdown() {
+        assert_eq!(
+            markdown_to_html("**unclosed and [link](x"),
+            "<p>**unclosed and [link](x</p>\n"
+        );
+        assert_eq!(markdown_to_html("#no-space \\*"), "<p>#no-space *</p>\n");
+        assert_eq!(
+            markdown_to_html("```\n<code>"),
+            "<pre><code>&lt;code&gt;\n</code></pre>\n"
+        );
+        assert_eq!(markdown_to_html(""), "");
+    }
+}
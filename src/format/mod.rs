@@ -0,0 +1,42 @@
+mod base64;
+mod chunk;
+mod encoding;
+#[cfg(feature = "serde")]
+mod event;
+#[cfg(feature = "terminal-ui")]
+mod highlight;
+mod json;
+#[cfg(feature = "markdown")]
+mod markdown;
+mod multipart;
+mod osc52;
+#[cfg(feature = "serde")]
+mod slack;
+#[cfg(feature = "serde")]
+mod split;
+mod text;
+#[cfg(feature = "image")]
+mod thumbnail;
+
+pub use base64::{decode_url as base64url_decode, encode_url as base64url_encode};
+pub use chunk::{
+    ChunkAssembler, reassemble_chunks, reassemble_chunks_with_limit, write_chunks,
+    write_indexed_chunks,
+};
+pub use encoding::{EncodingGuess, OutputEncoding, detect_encoding, encode_text};
+#[cfg(feature = "terminal-ui")]
+pub use highlight::{colors_enabled, highlight};
+pub use json::pretty_json;
+#[cfg(feature = "markdown")]
+pub use markdown::{markdown_to_html, render_markdown_item};
+pub use multipart::write_multipart;
+pub use osc52::{in_tmux, osc52_sequence};
+#[cfg(feature = "serde")]
+pub use split::{MANIFEST_FILE, read_split_dir, write_split_dir};
+pub use text::{
+    TextStats, check_terminal_output, ensure_trailing_newline, number_lines, parse_duration,
+    parse_separator, printable_only, redelimit_fields, shell_assignment, text_stats, uniq_lines,
+    write_contents,
+};
+#[cfg(feature = "image")]
+pub use thumbnail::{THUMBNAIL_MIME_TYPE, THUMBNAIL_SIZE, add_thumbnails, png_thumbnail};
@@ -0,0 +1,96 @@
+use super::base64;
+use crate::protocol::SourceDataItem;
+use std::io::Write;
+
+// RFC 2045 limits the encoded lines to 76 characters.
+const BASE64_LINE_LEN: usize = 76;
+
+/// Write all the items as a `multipart/related` MIME document. Every item becomes one part, with
+/// its first mime-type as the `Content-Type` and the content encoded in base64.
+pub fn write_multipart(items: &[SourceDataItem], mut writer: impl Write) -> std::io::Result<()> {
+    let boundary = choose_boundary(items);
+    write!(writer, "MIME-Version: 1.0\r\n")?;
+    write!(
+        writer,
+        "Content-Type: multipart/related; boundary=\"{boundary}\"\r\n\r\n"
+    )?;
+    for item in items {
+        let Some(mime_type) = item.mime_type.first() else {
+            continue;
+        };
+        write!(writer, "--{boundary}\r\n")?;
+        write!(writer, "Content-Type: {mime_type}\r\n")?;
+        write!(writer, "Content-Transfer-Encoding: base64\r\n\r\n")?;
+        let encoded = base64::encode(&item.content);
+        for line in encoded.as_bytes().chunks(BASE64_LINE_LEN) {
+            writer.write_all(line)?;
+            write!(writer, "\r\n")?;
+        }
+    }
+    write!(writer, "--{boundary}--\r\n")?;
+    writer.flush()
+}
+
+// The bodies are base64 which never contains '-', so only the headers could collide.
+fn choose_boundary(items: &[SourceDataItem]) -> String {
+    let mut n = 0;
+    loop {
+        let boundary = format!("richclip-boundary-{n}");
+        if !items
+            .iter()
+            .flat_map(|item| item.mime_type.iter())
+            .any(|mime_type| mime_type.contains(&boundary))
+        {
+            return boundary;
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PROTOCAL_VER, receive_data_bulk};
+
+    #[test]
+    fn test_write_multipart() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 10, b't', b'e', b'x', b't', b'/', b'p', b'l', b'a', b'i', b'n',
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'M', 0, 0, 0, 9, b't', b'e', b'x', b't', b'/', b'h', b't', b'm', b'l',
+            b'C', 0, 0, 0, 3, b'B', b'A', b'D',
+            ];
+        let items = receive_data_bulk(&mut &buf[..]).unwrap();
+        let mut out = Vec::new();
+        write_multipart(&items, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "MIME-Version: 1.0\r\n\
+            Content-Type: multipart/related; boundary=\"richclip-boundary-0\"\r\n\
+            \r\n\
+            --richclip-boundary-0\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            R09PRA==\r\n\
+            --richclip-boundary-0\r\n\
+            Content-Type: text/html\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            QkFE\r\n\
+            --richclip-boundary-0--\r\n"
+        );
+    }
+
+    #[test]
+    fn test_choose_boundary() {
+        let items = vec![SourceDataItem::new(
+            vec!["text/richclip-boundary-0".to_string()],
+            vec![],
+        )];
+        assert_eq!(choose_boundary(&items), "richclip-boundary-1");
+    }
+}
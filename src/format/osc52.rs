@@ -0,0 +1,45 @@
+/// The OSC 52 escape sequence which asks the terminal to set its clipboard, or the primary
+/// selection, to the content. It works over SSH since the terminal does the copying.
+/// With `tmux`, the sequence is wrapped in the tmux passthrough, so tmux forwards it to the
+/// outer terminal instead of handling it. tmux requires `allow-passthrough` to be on.
+pub fn osc52_sequence(content: &[u8], primary: bool, tmux: bool) -> Vec<u8> {
+    let selection = if primary { 'p' } else { 'c' };
+    let sequence = format!("\x1b]52;{selection};{}\x07", super::base64::encode(content));
+    if !tmux {
+        return sequence.into_bytes();
+    }
+    // ESC in the passthrough data has to be doubled
+    let mut ret = b"\x1bPtmux;".to_vec();
+    for b in sequence.bytes() {
+        if b == 0x1b {
+            ret.push(0x1b);
+        }
+        ret.push(b);
+    }
+    ret.extend_from_slice(b"\x1b\\");
+    ret
+}
+
+/// Whether it runs inside tmux, which sets `$TMUX` for its clients.
+pub fn in_tmux() -> bool {
+    std::env::var_os("TMUX").is_some_and(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52_sequence() {
+        assert_eq!(
+            osc52_sequence(b"GOOD", false, false),
+            b"\x1b]52;c;R09PRA==\x07"
+        );
+        assert_eq!(osc52_sequence(b"", true, false), b"\x1b]52;p;\x07");
+
+        let wrapped = osc52_sequence(b"GOOD", false, true);
+        assert_eq!(wrapped, b"\x1bPtmux;\x1b\x1b]52;c;R09PRA==\x07\x1b\\");
+        assert!(wrapped.starts_with(b"\x1bPtmux;"));
+        assert!(wrapped.ends_with(b"\x1b\\"));
+    }
+}
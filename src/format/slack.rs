@@ -0,0 +1,151 @@
+use crate::protocol::SourceDataItem;
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+
+// Slack's limit of a section text, in chars
+const SECTION_TEXT_LEN: usize = 3000;
+// The code block fences and their newlines
+const FENCE_LEN: usize = 8;
+// In chars
+const FALLBACK_LEN: usize = 80;
+
+impl SourceDataItem {
+    /// A Slack Block Kit message of the item, for `chat.postMessage`. Text is posted as code
+    /// blocks, split into sections to fit Slack's limit.
+    /// Block Kit can't carry the bytes of images and other binary contents, they have to be
+    /// uploaded as files. For them, the message only describes the content, and `upload` holds
+    /// the `filename`, `length` and, for images, `alt_txt` parameters of
+    /// `files.getUploadURLExternal` to upload it with.
+    pub fn to_slack_message(&self) -> Value {
+        if self.is_text()
+            && let Ok(text) = std::str::from_utf8(&self.content)
+        {
+            return text_message(text);
+        }
+
+        let mime_type = self.mime_type.first().map_or("", |t| t.as_str());
+        let is_image = mime_type.starts_with("image/");
+        let filename = format!("clipboard.{}", file_extension(mime_type));
+        let description = format!(
+            "{} `{}` ({} bytes)",
+            if is_image { "Image" } else { "File" },
+            filename,
+            self.content.len()
+        );
+        let mut upload = json!({
+            "filename": filename,
+            "length": self.content.len(),
+        });
+        if is_image {
+            upload["alt_txt"] = Value::from("Clipboard image");
+        }
+        json!({
+            "text": description,
+            "blocks": [{
+                "type": "context",
+                "elements": [{"type": "mrkdwn", "text": description}],
+            }],
+            "upload": upload,
+        })
+    }
+
+    /// `to_slack_message` serialized, to post as the request body.
+    pub fn to_slack_payload_string(&self) -> Result<String> {
+        serde_json::to_string(&self.to_slack_message()).context("Failed to serialize Slack message")
+    }
+}
+
+fn text_message(text: &str) -> Value {
+    // Slack only requires these to be escaped in mrkdwn
+    let escaped = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let chars: Vec<char> = escaped.chars().collect();
+    let blocks: Vec<Value> = chars
+        .chunks(SECTION_TEXT_LEN - FENCE_LEN)
+        .map(|chunk| {
+            let code: String = chunk.iter().collect();
+            json!({
+                "type": "section",
+                "text": {"type": "mrkdwn", "text": format!("```\n{code}\n```")},
+            })
+        })
+        .collect();
+    json!({
+        "text": text.chars().take(FALLBACK_LEN).collect::<String>(),
+        "blocks": blocks,
+    })
+}
+
+// `png` for `image/png`, `svg` for `image/svg+xml`.
+fn file_extension(mime_type: &str) -> &str {
+    let subtype = mime_type
+        .split_once('/')
+        .map_or("", |(_, s)| s.split(['+', ';']).next().unwrap_or(""));
+    match subtype {
+        "jpeg" => "jpg",
+        "" | "octet-stream" => "bin",
+        s if s.chars().all(|c| c.is_ascii_alphanumeric()) => s,
+        _ => "bin",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_slack_message_text() {
+        let item = SourceDataItem::new(
+            vec!["text/plain".to_string()],
+            b"fn main() {}\nif a < b && c {}".to_vec(),
+        );
+        let message = item.to_slack_message();
+        assert_eq!(message["blocks"].as_array().unwrap().len(), 1);
+        assert_eq!(message["blocks"][0]["type"], "section");
+        assert_eq!(
+            message["blocks"][0]["text"]["text"],
+            "```\nfn main() {}\nif a &lt; b &amp;&amp; c {}\n```"
+        );
+        assert!(message.get("upload").is_none());
+
+        // Split into sections
+        let item = SourceDataItem::new(vec!["TEXT".to_string()], "é".repeat(4000).into_bytes());
+        let message = item.to_slack_message();
+        let blocks = message["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        for block in blocks {
+            assert!(block["text"]["text"].as_str().unwrap().chars().count() <= SECTION_TEXT_LEN);
+        }
+        assert_eq!(
+            message["text"].as_str().unwrap().chars().count(),
+            FALLBACK_LEN
+        );
+    }
+
+    #[test]
+    fn test_to_slack_message_binary() {
+        let item = SourceDataItem::new(vec!["image/png".to_string()], b"\x89PNG".to_vec());
+        let message = item.to_slack_message();
+        assert_eq!(message["text"], "Image `clipboard.png` (4 bytes)");
+        assert_eq!(
+            message["upload"],
+            json!({"filename": "clipboard.png", "length": 4, "alt_txt": "Clipboard image"})
+        );
+
+        let item = SourceDataItem::new(vec!["application/octet-stream".to_string()], vec![0, 1, 2]);
+        let message = item.to_slack_message();
+        assert_eq!(
+            message["upload"],
+            json!({"filename": "clipboard.bin", "length": 3})
+        );
+        // Invalid UTF-8 text is a file too
+        let item = SourceDataItem::new(vec!["text/plain".to_string()], vec![0xff]);
+        assert!(item.to_slack_message().get("upload").is_some());
+
+        let payload = item.to_slack_payload_string().unwrap();
+        let parsed: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(parsed, item.to_slack_message());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::clipboard::mime_type_for_extension;
+use crate::protocol::{SourceDataItem, fingerprint_content};
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::path::Path;
+
+/// The name of the manifest file in the directory written by `write_split_dir`.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Write the content of every item to its own file `item-<index>` in `dir`, and describe them
+/// in `manifest.json`, which is also returned:
+/// `{"items": [{"file": ..., "mimeTypes": [...], "sizeBytes": ..., "fingerprint": ...}]}`.
+/// The fingerprint is `SourceDataItem::fingerprint` in 16 hex digits. It is not cryptographic,
+/// but enough to check a file is the one described.
+/// `dir` is created if it doesn't exist. It fails if `dir` is not empty, so no stale files are
+/// mixed in.
+pub fn write_split_dir(items: &[SourceDataItem], dir: &Path) -> Result<Value> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create the directory '{}'", dir.display()))?;
+    if std::fs::read_dir(dir)?.next().is_some() {
+        bail!("The directory '{}' is not empty", dir.display());
+    }
+
+    let mut entries = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        let file = format!("item-{i}");
+        std::fs::write(dir.join(&file), item.content.as_slice())
+            .with_context(|| format!("Failed to write '{file}'"))?;
+        entries.push(json!({
+            "file": file,
+            "mimeTypes": item.mime_type,
+            "sizeBytes": item.content.len(),
+            "fingerprint": format!("{:016x}", item.fingerprint()),
+        }));
+    }
+    let manifest = json!({ "items": entries });
+    let mut text = serde_json::to_string_pretty(&manifest)?;
+    text.push('\n');
+    std::fs::write(dir.join(MANIFEST_FILE), text)
+        .with_context(|| format!("Failed to write '{MANIFEST_FILE}'"))?;
+    Ok(manifest)
+}
+
+/// Read every file in `dir` as an item, the inverse of `write_split_dir`. The mime-type of a
+/// file is inferred from its extension, `application/octet-stream` if it is not known.
+/// If there is a `manifest.json`, the files in its `items` come first in its order, with the
+/// mime-types of their `mimeTypes` if there are. Their `fingerprint` is checked if there is one.
+/// The other files follow in the order of their names. The hidden files and the subdirectories
+/// are skipped.
+pub fn read_split_dir(dir: &Path) -> Result<Vec<SourceDataItem>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read the directory '{}'", dir.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name == MANIFEST_FILE || !entry.file_type()?.is_file() {
+            continue;
+        }
+        files.push(name);
+    }
+    files.sort();
+
+    let mut items = vec![];
+    let manifest_path = dir.join(MANIFEST_FILE);
+    if manifest_path.exists() {
+        let text = std::fs::read_to_string(&manifest_path)
+            .with_context(|| format!("Failed to read '{MANIFEST_FILE}'"))?;
+        let manifest: Value = serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse '{MANIFEST_FILE}'"))?;
+        let Some(entries) = manifest["items"].as_array() else {
+            bail!("'{MANIFEST_FILE}' has no items");
+        };
+        for entry in entries {
+            let Some(file) = entry["file"].as_str() else {
+                bail!("Item without a file in '{MANIFEST_FILE}'");
+            };
+            let Some(i) = files.iter().position(|f| f == file) else {
+                bail!("'{file}' in '{MANIFEST_FILE}' is not a file in the directory");
+            };
+            files.remove(i);
+            let content = read_item_file(dir, file)?;
+            if let Some(expected) = entry["fingerprint"].as_str() {
+                let fingerprint = format!("{:016x}", fingerprint_content(&content));
+                if fingerprint != expected {
+                    bail!("The fingerprint of '{file}' is {fingerprint}, not {expected}");
+                }
+            }
+            let mime_type = match entry["mimeTypes"].as_array() {
+                Some(types) => types
+                    .iter()
+                    .map(|t| t.as_str().map(|t| t.to_string()))
+                    .collect::<Option<Vec<_>>>()
+                    .with_context(|| format!("Invalid mimeTypes of '{file}'"))?,
+                None => vec![file_mime_type(file).to_string()],
+            };
+            items.push(SourceDataItem::new(mime_type, content));
+        }
+    }
+    for file in files {
+        let content = read_item_file(dir, &file)?;
+        items.push(SourceDataItem::new(
+            vec![file_mime_type(&file).to_string()],
+            content,
+        ));
+    }
+    Ok(items)
+}
+
+fn read_item_file(dir: &Path, file: &str) -> Result<Vec<u8>> {
+    std::fs::read(dir.join(file)).with_context(|| format!("Failed to read '{file}'"))
+}
+
+fn file_mime_type(file: &str) -> &'static str {
+    let extension = Path::new(file)
+        .extension()
+        .map_or("".into(), |e| e.to_string_lossy());
+    mime_type_for_extension(&extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_split_dir() {
+        let dir = std::env::temp_dir().join(format!("richclip-{}-split", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let items = vec![
+            SourceDataItem::new(
+                vec!["text/plain".to_string(), "TEXT".to_string()],
+                b"GOOD".to_vec(),
+            ),
+            SourceDataItem::new(vec!["image/png".to_string()], b"\x89PNG\0\0".to_vec()),
+        ];
+        let manifest = write_split_dir(&items, &dir).unwrap();
+
+        let text = std::fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap();
+        let written: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(written, manifest);
+        let entries = written["items"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        for (entry, item) in entries.iter().zip(&items) {
+            let content = std::fs::read(dir.join(entry["file"].as_str().unwrap())).unwrap();
+            assert_eq!(content, *item.content);
+            assert_eq!(entry["mimeTypes"], json!(item.mime_type));
+            assert_eq!(entry["sizeBytes"], content.len());
+            let fingerprint = SourceDataItem::new(vec![], content).fingerprint();
+            assert_eq!(entry["fingerprint"], format!("{fingerprint:016x}"));
+        }
+        // Only the items and the manifest
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+
+        // Not empty anymore
+        assert!(write_split_dir(&items, &dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_split_dir() {
+        let dir = std::env::temp_dir().join(format!("richclip-{}-assemble", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("b.png"), b"\x89PNG").unwrap();
+        std::fs::write(dir.join("a.html"), b"<b>GOOD</b>").unwrap();
+        std::fs::write(dir.join("c.xyz"), b"\0\x01").unwrap();
+        std::fs::write(dir.join(".hidden"), b"BAD").unwrap();
+        let r = read_split_dir(&dir).unwrap();
+        let parts: Vec<_> = r
+            .iter()
+            .map(|item| (item.mime_type.clone(), item.content.to_vec()))
+            .collect();
+        assert_eq!(
+            parts,
+            [
+                (vec!["text/html".to_string()], b"<b>GOOD</b>".to_vec()),
+                (vec!["image/png".to_string()], b"\x89PNG".to_vec()),
+                (
+                    vec!["application/octet-stream".to_string()],
+                    b"\0\x01".to_vec()
+                ),
+            ]
+        );
+
+        // The manifest overrides the order and the types
+        let manifest = json!({"items": [
+            {"file": "c.xyz", "mimeTypes": ["x-special/data", "DATA"]},
+            {"file": "b.png"},
+        ]});
+        std::fs::write(dir.join(MANIFEST_FILE), manifest.to_string()).unwrap();
+        let r = read_split_dir(&dir).unwrap();
+        let types: Vec<_> = r.iter().map(|item| item.mime_type.clone()).collect();
+        assert_eq!(
+            types,
+            [
+                vec!["x-special/data", "DATA"],
+                vec!["image/png"],
+                vec!["text/html"]
+            ]
+        );
+
+        let manifest = json!({"items": [{"file": "missing"}]});
+        std::fs::write(dir.join(MANIFEST_FILE), manifest.to_string()).unwrap();
+        assert!(read_split_dir(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Round trip
+        let items = vec![
+            SourceDataItem::new(vec!["text/plain".to_string()], b"GOOD".to_vec()),
+            SourceDataItem::new(vec!["image/png".to_string()], b"\x89PNG".to_vec()),
+        ];
+        write_split_dir(&items, &dir).unwrap();
+        let r = read_split_dir(&dir).unwrap();
+        assert_eq!(r.len(), 2);
+        for (a, b) in r.iter().zip(&items) {
+            assert_eq!(a.mime_type, b.mime_type);
+            assert_eq!(a.content, b.content);
+        }
+        // The fingerprint is checked
+        std::fs::write(dir.join("item-0"), b"BAD").unwrap();
+        assert!(read_split_dir(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
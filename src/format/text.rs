@@ -0,0 +1,351 @@
+use crate::clipboard::is_text_mime_type;
+use crate::protocol::SourceDataItem;
+use anyhow::{Result, bail};
+use std::io::Write;
+use std::time::Duration;
+
+/// Write the contents of all the items as they are, one after another.
+pub fn write_contents(items: &[SourceDataItem], mut writer: impl Write) -> std::io::Result<()> {
+    for item in items {
+        writer.write_all(&item.content)?;
+    }
+    writer.flush()
+}
+
+/// Append a line feed unless the content is empty or already ends with one.
+pub fn ensure_trailing_newline(mut content: Vec<u8>) -> Vec<u8> {
+    if content.last().is_some_and(|b| *b != b'\n') {
+        content.push(b'\n');
+    }
+    content
+}
+
+/// Prefix every line with its number, the same way as `cat -n` does.
+pub fn number_lines(content: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(content.len() + content.len() / 8);
+    for (i, line) in content.split_inclusive(|b| *b == b'\n').enumerate() {
+        ret.extend(format!("{:6}\t", i + 1).as_bytes());
+        ret.extend(line);
+    }
+    ret
+}
+
+/// Make a shell assignment of the content to the variable, `NAME='content'` followed by a line
+/// feed, for `eval`. The content is single-quoted, so nothing in it gets expanded.
+pub fn shell_assignment(name: &str, content: &[u8]) -> Result<Vec<u8>> {
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        bail!("Invalid shell variable name '{name}'");
+    }
+    if content.contains(&0) {
+        bail!("Content with null bytes cannot be stored in a shell variable");
+    }
+    let mut ret = Vec::with_capacity(name.len() + content.len() + 4);
+    ret.extend(name.as_bytes());
+    ret.extend(b"='");
+    for b in content {
+        if *b == b'\'' {
+            // Close the quote, add an escaped quote, and open the quote again
+            ret.extend(b"'\\''");
+        } else {
+            ret.push(*b);
+        }
+    }
+    ret.extend(b"'\n");
+    Ok(ret)
+}
+
+/// Replace the `ifs` field separator with `ofs` on every line. The lines are split first, so a
+/// separator is never matched across a newline.
+pub fn redelimit_fields(content: &[u8], ifs: &[u8], ofs: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(content.len());
+    for (i, line) in content.split(|b| *b == b'\n').enumerate() {
+        if i > 0 {
+            ret.push(b'\n');
+        }
+        let mut rest = line;
+        while let Some(pos) = find(rest, ifs) {
+            ret.extend_from_slice(&rest[..pos]);
+            ret.extend_from_slice(ofs);
+            rest = &rest[pos + ifs.len()..];
+        }
+        ret.extend_from_slice(rest);
+    }
+    ret
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Parse a field separator given on the command line, where `\t`, `\n` and `\\` stand for a tab,
+/// a newline and a backslash.
+pub fn parse_separator(s: &str) -> Result<String, String> {
+    let mut ret = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => ret.push('\t'),
+            Some('n') => ret.push('\n'),
+            Some('\\') => ret.push('\\'),
+            Some(c) => return Err(format!("Unknown escape '\\{c}'")),
+            None => return Err("Trailing backslash".to_string()),
+        }
+    }
+    if ret.is_empty() {
+        return Err("The separator can't be empty".to_string());
+    }
+    Ok(ret)
+}
+
+/// Parse a duration given on the command line, a number with the unit `ms`, `s`, `m` or `h`,
+/// e.g. `500ms` or `1.5m`. A number without a unit is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{s}'"))?;
+    let secs = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("Unknown duration unit '{unit}', use ms, s, m or h")),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("Invalid duration '{s}': {e}"))
+}
+
+/// Remove the duplicate lines like `uniq`, only the consecutive ones unless `all` is true. The
+/// first occurrence of a line is kept, along with the trailing newline of the text if any.
+pub fn uniq_lines(content: &[u8], all: bool) -> Vec<u8> {
+    let (body, trailing) = match content.strip_suffix(b"\n") {
+        Some(body) => (body, true),
+        None => (content, false),
+    };
+    let mut seen = std::collections::HashSet::new();
+    let mut last = None;
+    let mut ret = Vec::with_capacity(content.len());
+    let mut first = true;
+    for line in body.split(|b| *b == b'\n') {
+        let duplicate = if all {
+            !seen.insert(line)
+        } else {
+            last == Some(line)
+        };
+        last = Some(line);
+        if duplicate {
+            continue;
+        }
+        if !first {
+            ret.push(b'\n');
+        }
+        first = false;
+        ret.extend_from_slice(line);
+    }
+    if trailing {
+        ret.push(b'\n');
+    }
+    ret
+}
+
+/// The counts of the text like `wc`.
+#[derive(Debug, PartialEq)]
+pub struct TextStats {
+    /// The number of newlines, so a last line without one is not counted.
+    pub lines: usize,
+    /// Separated by Unicode whitespace, invalid UTF-8 counts as word characters.
+    pub words: usize,
+    pub bytes: usize,
+}
+
+pub fn text_stats(content: &[u8]) -> TextStats {
+    TextStats {
+        lines: content.iter().filter(|b| **b == b'\n').count(),
+        words: String::from_utf8_lossy(content).split_whitespace().count(),
+        bytes: content.len(),
+    }
+}
+
+/// Remove the control characters except tab and line feed, or replace them with `replacement`,
+/// so the text is safe to display. Invalid UTF-8 is replaced with U+FFFD.
+pub fn printable_only(content: &[u8], replacement: Option<char>) -> Vec<u8> {
+    let text = String::from_utf8_lossy(content);
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        if !c.is_control() || c == '\t' || c == '\n' {
+            ret.push(c);
+        } else if let Some(r) = replacement {
+            ret.push(r);
+        }
+    }
+    ret.into_bytes()
+}
+
+// If the content looks binary, i.e. it isn't of a text mime-type, or isn't valid UTF-8 and has
+// control characters.
+fn looks_binary(content: &[u8], mime_type: &str) -> bool {
+    !is_text_mime_type(mime_type)
+        || (std::str::from_utf8(content).is_err()
+            && content
+                .iter()
+                .any(|b| b.is_ascii_control() && !b"\t\n\r".contains(b)))
+}
+
+/// Refuse to write binary content to a terminal, which would garble the screen, like `grep`
+/// does with binary files. The content is fine if it goes somewhere else.
+pub fn check_terminal_output(content: &[u8], mime_type: &str, is_terminal: bool) -> Result<()> {
+    if is_terminal && looks_binary(content, mime_type) {
+        bail!(
+            "Refuse to write binary content to the terminal, use '--binary' to write it anyway or \
+            redirect the output"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_contents() {
+        let items = vec![
+            SourceDataItem::new(vec!["text/plain".to_string()], b"GOOD".to_vec()),
+            SourceDataItem::new(vec!["text/html".to_string()], b"BAD".to_vec()),
+        ];
+        let mut out = Vec::new();
+        write_contents(&items, &mut out).unwrap();
+        assert_eq!(out, b"GOODBAD");
+    }
+
+    #[test]
+    fn test_ensure_trailing_newline() {
+        assert_eq!(ensure_trailing_newline(b"text".to_vec()), b"text\n");
+        assert_eq!(ensure_trailing_newline(b"text\n".to_vec()), b"text\n");
+        assert_eq!(ensure_trailing_newline(b"text\n\n".to_vec()), b"text\n\n");
+        assert_eq!(ensure_trailing_newline(b"".to_vec()), b"");
+    }
+
+    #[test]
+    fn test_number_lines() {
+        assert_eq!(
+            number_lines(b"a\nb\n\nc"),
+            b"     1\ta\n     2\tb\n     3\t\n     4\tc"
+        );
+        assert_eq!(number_lines(b"a\n"), b"     1\ta\n");
+        assert_eq!(number_lines(b""), b"");
+    }
+
+    #[test]
+    fn test_shell_assignment() {
+        let r = shell_assignment("CLIP", b"it's a \"test\"\n$HOME `x`\n").unwrap();
+        assert_eq!(
+            String::from_utf8(r).unwrap(),
+            "CLIP='it'\\''s a \"test\"\n$HOME `x`\n'\n"
+        );
+        assert_eq!(shell_assignment("_a1", b"").unwrap(), b"_a1=''\n");
+
+        assert!(shell_assignment("1A", b"").is_err());
+        assert!(shell_assignment("A-B", b"").is_err());
+        assert!(shell_assignment("", b"").is_err());
+        assert!(shell_assignment("A", b"\0").is_err());
+    }
+
+    #[test]
+    fn test_uniq_lines() {
+        let text = b"a\na\nb\na\nc\nc\n";
+        assert_eq!(uniq_lines(text, false), b"a\nb\na\nc\n");
+        assert_eq!(uniq_lines(text, true), b"a\nb\nc\n");
+        // Without the trailing newline, and with empty lines
+        assert_eq!(uniq_lines(b"b\n\n\na\nb", true), b"b\n\na");
+        assert_eq!(uniq_lines(b"\n\nx", false), b"\nx");
+        assert_eq!(uniq_lines(b"", true), b"");
+        assert_eq!(uniq_lines(b"\n", true), b"\n");
+    }
+
+    #[test]
+    fn test_redelimit_fields() {
+        let tsv = b"name\tage\tcity\nalice\t30\tParis\n\nbob\t\tRome";
+        assert_eq!(
+            redelimit_fields(tsv, b"\t", b","),
+            b"name,age,city\nalice,30,Paris\n\nbob,,Rome"
+        );
+        assert_eq!(redelimit_fields(b"a::b:c\n", b"::", b" | "), b"a | b:c\n");
+        assert_eq!(redelimit_fields(b"no fields", b"\t", b","), b"no fields");
+
+        assert_eq!(parse_separator("\\t").unwrap(), "\t");
+        assert_eq!(parse_separator(", \\\\").unwrap(), ", \\");
+        assert!(parse_separator("").is_err());
+        assert!(parse_separator("\\x").is_err());
+        assert!(parse_separator("\\").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration("1.2.3s").is_err());
+    }
+
+    #[test]
+    fn test_text_stats() {
+        let stats = |lines, words, bytes| TextStats {
+            lines,
+            words,
+            bytes,
+        };
+        assert_eq!(
+            text_stats("Some text\nthé  second\tline\n\nlast".as_bytes()),
+            stats(3, 6, 33)
+        );
+        assert_eq!(text_stats(b""), stats(0, 0, 0));
+        assert_eq!(text_stats(b" \n "), stats(1, 0, 3));
+        assert_eq!(text_stats(b"\xff\xfe bin"), stats(0, 2, 6));
+    }
+
+    #[test]
+    fn test_printable_only() {
+        let text = "a\tb\r\n\x1b[31mred\x1b[0m\x07\u{85}é\n".as_bytes();
+        assert_eq!(printable_only(text, None), "a\tb\n[31mred[0mé\n".as_bytes());
+        assert_eq!(
+            printable_only(text, Some('?')),
+            "a\tb?\n?[31mred?[0m??é\n".as_bytes()
+        );
+        // Tab and newline only
+        assert_eq!(printable_only(b"\t\n\t", None), b"\t\n\t");
+        assert_eq!(printable_only(b"a\xffb\0", None), "a\u{fffd}b".as_bytes());
+    }
+
+    #[test]
+    fn test_check_terminal_output() {
+        assert!(!looks_binary(b"GOOD\r\n", "text/plain"));
+        assert!(!looks_binary("é\x1b[0m".as_bytes(), ""));
+        // Latin-1 text without control characters
+        assert!(!looks_binary(b"caf\xe9", "TEXT"));
+        assert!(looks_binary(b"caf\xe9\0", "TEXT"));
+        assert!(looks_binary(b"GOOD", "image/png"));
+
+        let err = check_terminal_output(b"\x89PNG\r\n\x1a\n", "image/png", true).unwrap_err();
+        assert!(err.to_string().contains("--binary"));
+        assert!(check_terminal_output(b"\xff\x01", "", true).is_err());
+        assert!(check_terminal_output(b"GOOD", "text/plain", true).is_ok());
+        // Not to a terminal
+        assert!(check_terminal_output(b"\x89PNG\r\n\x1a\n", "image/png", false).is_ok());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::protocol::SourceDataItem;
+use anyhow::{Context, Result};
+use std::io::Cursor;
+
+/// The mime-type of the thumbnails added by `add_thumbnails`.
+pub const THUMBNAIL_MIME_TYPE: &str = "image/png;thumbnail=1";
+/// The default maximum width and height of the thumbnails, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+fn is_image(item: &SourceDataItem) -> bool {
+    item.mime_type
+        .iter()
+        .any(|t| t.to_ascii_lowercase().starts_with("image/"))
+}
+
+/// Decode the image and downscale it to fit in `size` x `size` pixels, keeping the aspect
+/// ratio, as a PNG. Images smaller than that are not scaled up.
+pub fn png_thumbnail(content: &[u8], size: u32) -> Result<Vec<u8>> {
+    let image = image::load_from_memory(content).context("Failed to decode the image")?;
+    let thumbnail = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+    let mut png = Cursor::new(Vec::new());
+    thumbnail
+        .write_to(&mut png, image::ImageFormat::Png)
+        .context("Failed to encode the thumbnail")?;
+    Ok(png.into_inner())
+}
+
+/// Add a thumbnail item as `THUMBNAIL_MIME_TYPE` after every image item, for the apps which
+/// prefer a small preview. The other items, and the images which can't be decoded, are kept
+/// without a thumbnail with a warning.
+pub fn add_thumbnails(items: Vec<SourceDataItem>, size: u32) -> Vec<SourceDataItem> {
+    let mut ret = Vec::with_capacity(items.len() * 2);
+    for item in items {
+        if !is_image(&item) {
+            log::warn!(
+                "Skip the thumbnail of the non-image content {:?}",
+                item.mime_type
+            );
+            ret.push(item);
+            continue;
+        }
+        let thumbnail = png_thumbnail(&item.content, size);
+        ret.push(item);
+        match thumbnail {
+            Ok(png) => ret.push(SourceDataItem::new(
+                vec![THUMBNAIL_MIME_TYPE.to_string()],
+                png,
+            )),
+            Err(e) => log::warn!("Skip the thumbnail: {e:#}"),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = image::RgbImage::from_pixel(width, height, image::Rgb([200, 10, 10]));
+        let mut buf = Cursor::new(Vec::new());
+        image.write_to(&mut buf, image::ImageFormat::Png).unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn test_add_thumbnails() {
+        let full = png(600, 300);
+        let items = vec![
+            SourceDataItem::new(vec!["image/png".to_string()], full.clone()),
+            SourceDataItem::new(vec!["text/plain".to_string()], b"GOOD".to_vec()),
+        ];
+        let r = add_thumbnails(items, THUMBNAIL_SIZE);
+        assert_eq!(r.len(), 3);
+        // The full image is kept
+        assert_eq!(r[0].mime_type, ["image/png"]);
+        assert_eq!(r[0].content.as_slice(), full.as_slice());
+        assert_eq!(r[1].mime_type, [THUMBNAIL_MIME_TYPE]);
+        let thumbnail = image::load_from_memory(&r[1].content).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+        // Non-image content is skipped
+        assert_eq!(r[2].mime_type, ["text/plain"]);
+
+        // Small images are not scaled up, broken ones are skipped
+        let items = vec![
+            SourceDataItem::new(vec!["image/png".to_string()], png(10, 20)),
+            SourceDataItem::new(vec!["image/png".to_string()], b"\x89PNG".to_vec()),
+        ];
+        let r = add_thumbnails(items, THUMBNAIL_SIZE);
+        assert_eq!(r.len(), 3);
+        let thumbnail = image::load_from_memory(&r[1].content).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (10, 20));
+    }
+}
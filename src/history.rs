@@ -0,0 +1,471 @@
+use crate::protocol::{ProtocolWriter, SourceDataItem, fingerprint_items, receive_data_bulk};
+use anyhow::{Context, Result, bail};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::canonical_combining_class;
+
+pub const DEFAULT_HISTORY_DEPTH: usize = 16;
+
+/// The magic of the files written by `ClipboardHistory::export_to_file_bulk_protocol`.
+const EXPORT_MAGIC: [u8; 4] = *b"RCHX";
+
+pub struct HistoryEntry {
+    pub items: Vec<SourceDataItem>,
+    hash: u64,
+    // Unlike the position, it doesn't change when older entries are dropped
+    id: u64,
+}
+
+/// A match found by `ClipboardHistory::search`. The offset and the length are in bytes of the
+/// original content of the item.
+#[derive(Debug, PartialEq)]
+pub struct HistoryMatch {
+    /// The position of the entry in `ClipboardHistory::iter`.
+    pub entry_index: usize,
+    pub item_index: usize,
+    pub match_offset: usize,
+    pub match_len: usize,
+}
+
+/// Recently copied contents, oldest first. Copying a content which is already in the history
+/// moves it to the top instead of storing a duplicate.
+pub struct ClipboardHistory {
+    entries: VecDeque<HistoryEntry>,
+    depth: usize,
+    next_id: u64,
+    // Normalized words of the text items to `(entry id, item index)`
+    word_index: BTreeMap<String, Vec<(u64, usize)>>,
+}
+
+fn same_items(a: &[SourceDataItem], b: &[SourceDataItem]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| a.mime_type == b.mime_type && a.content == b.content)
+}
+
+impl ClipboardHistory {
+    pub fn new(depth: usize) -> Self {
+        ClipboardHistory {
+            entries: VecDeque::with_capacity(depth),
+            depth,
+            next_id: 0,
+            word_index: BTreeMap::new(),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Change the depth, the oldest entries are dropped if there are too many.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.entries.len() > depth {
+            self.pop_oldest();
+        }
+    }
+
+    pub fn push(&mut self, items: Vec<SourceDataItem>) {
+        if self.depth == 0 {
+            return;
+        }
+        let hash = fingerprint_items(&items);
+        if let Some(pos) = self
+            .entries
+            .iter()
+            .position(|e| e.hash == hash && same_items(&e.items, &items))
+        {
+            log::debug!("Content is already in the history, move it to the top");
+            let entry = self.entries.remove(pos).unwrap();
+            self.entries.push_back(entry);
+            return;
+        }
+        if self.entries.len() == self.depth {
+            self.pop_oldest();
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        for (item_index, item) in items.iter().enumerate() {
+            for word in item_words(item) {
+                self.word_index
+                    .entry(word)
+                    .or_default()
+                    .push((id, item_index));
+            }
+        }
+        self.entries.push_back(HistoryEntry { items, hash, id });
+    }
+
+    fn pop_oldest(&mut self) {
+        let Some(entry) = self.entries.pop_front() else {
+            return;
+        };
+        for (item_index, item) in entry.items.iter().enumerate() {
+            for word in item_words(item) {
+                if let Some(refs) = self.word_index.get_mut(&word) {
+                    refs.retain(|r| *r != (entry.id, item_index));
+                    if refs.is_empty() {
+                        self.word_index.remove(&word);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the text items containing the query. The search is case-insensitive, and both the
+    /// query and the contents are compared in Unicode NFC form, so a composed `é` matches a
+    /// decomposed one. A match has to start at the beginning of a word.
+    /// The matches are returned from the latest entry to the oldest.
+    pub fn search(&self, query: &str) -> Vec<HistoryMatch> {
+        let query: Vec<char> = normalize(query).into_iter().map(|(c, _)| c).collect();
+        let query_words = split_words(&query);
+        if query_words.is_empty() {
+            return vec![];
+        }
+
+        // Every word of the query must be the prefix of a word in the item
+        let mut candidates: Option<BTreeSet<(u64, usize)>> = None;
+        for word in &query_words {
+            let refs: BTreeSet<(u64, usize)> = self
+                .word_index
+                .range(word.clone()..)
+                .take_while(|(w, _)| w.starts_with(word.as_str()))
+                .flat_map(|(_, refs)| refs.iter().copied())
+                .collect();
+            candidates = Some(match candidates {
+                Some(c) => c.intersection(&refs).copied().collect(),
+                None => refs,
+            });
+        }
+
+        let positions: HashMap<u64, usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.id, i))
+            .collect();
+        let mut ret = Vec::new();
+        for (id, item_index) in candidates.unwrap_or_default() {
+            let entry_index = positions[&id];
+            let item = &self.entries[entry_index].items[item_index];
+            // Only valid UTF-8 text gets indexed
+            let text = normalize(std::str::from_utf8(&item.content).unwrap());
+            for start in 0..text.len() {
+                let is_word_start = start == 0 || !text[start - 1].0.is_alphanumeric();
+                let matched = is_word_start
+                    && text.len() - start >= query.len()
+                    && text[start..start + query.len()]
+                        .iter()
+                        .map(|(c, _)| c)
+                        .eq(query.iter());
+                if matched {
+                    let begin = text[start].1.start;
+                    let end = text[start + query.len() - 1].1.end;
+                    ret.push(HistoryMatch {
+                        entry_index,
+                        item_index,
+                        match_offset: begin,
+                        match_len: end - begin,
+                    });
+                }
+            }
+        }
+        ret.sort_by_key(|m| (Reverse(m.entry_index), m.item_index, m.match_offset));
+        ret
+    }
+
+    /// The entries from the oldest to the latest.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the entries to the file, from the oldest to the latest. The file starts with
+    /// `RCHX` and the number of the entries as big-endian uint32. Every entry follows as its
+    /// size in big-endian uint32 and a stream of the bulk protocol, see `receive_data_bulk`.
+    pub fn export_to_file_bulk_protocol(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(&EXPORT_MAGIC)?;
+        out.write_all(&(self.entries.len() as u32).to_be_bytes())?;
+        for entry in &self.entries {
+            let mut writer = ProtocolWriter::new(Vec::new())?;
+            for item in &entry.items {
+                writer.write_item(item)?;
+            }
+            let frame = writer.into_inner();
+            let size = u32::try_from(frame.len())
+                .with_context(|| format!("History entry of {} bytes is too large", frame.len()))?;
+            out.write_all(&size.to_be_bytes())?;
+            out.write_all(&frame)?;
+        }
+        out.flush()
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Read the file written by `export_to_file_bulk_protocol`. The depth is large enough for
+    /// all the entries and at least `DEFAULT_HISTORY_DEPTH`.
+    pub fn import_from_file_bulk_protocol(path: &Path) -> Result<ClipboardHistory> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .context("Failed to read history header")?;
+        if magic != EXPORT_MAGIC {
+            bail!("Not a history export: {:x?}", magic);
+        }
+        let count = read_u32(&mut reader).context("Failed to read history header")?;
+
+        // The count is not trusted for the allocation, the depth grows with the entries read
+        let mut history = ClipboardHistory::new(DEFAULT_HISTORY_DEPTH);
+        for i in 0..count {
+            let size = read_u32(&mut reader)
+                .with_context(|| format!("Failed to read history entry {i}"))?;
+            let mut frame = (&mut reader).take(size as u64);
+            let items = receive_data_bulk(&mut frame)
+                .with_context(|| format!("Failed to read history entry {i}"))?;
+            if frame.limit() != 0 {
+                bail!("History entry {i} is truncated");
+            }
+            if history.len() == history.depth() {
+                history.set_depth(history.depth() + 1);
+            }
+            history.push(items);
+        }
+        if reader.read(&mut [0u8])? != 0 {
+            bail!("Unexpected data after {count} history entries");
+        }
+        Ok(history)
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+/// The lowercased NFC form of the text, each char along with the byte range of the original text
+/// it comes from.
+/// A base char and the combining marks following it are normalized together, which gives the
+/// same result as normalizing the whole text in practice.
+fn normalize(text: &str) -> Vec<(char, std::ops::Range<usize>)> {
+    let mut ret = Vec::with_capacity(text.len());
+    let mut push_segment = |range: std::ops::Range<usize>| {
+        let segment: String = text[range.clone()]
+            .chars()
+            .flat_map(char::to_lowercase)
+            .collect();
+        ret.extend(segment.nfc().map(|c| (c, range.clone())));
+    };
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if i > start && canonical_combining_class(c) == 0 {
+            push_segment(start..i);
+            start = i;
+        }
+    }
+    if start < text.len() {
+        push_segment(start..text.len());
+    }
+    ret
+}
+
+fn split_words(text: &[char]) -> Vec<String> {
+    text.split(|c| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.iter().collect())
+        .collect()
+}
+
+/// The distinct normalized words of a text item, nothing for other items.
+fn item_words(item: &SourceDataItem) -> BTreeSet<String> {
+    if !item.is_text() {
+        return BTreeSet::new();
+    }
+    let Ok(text) = std::str::from_utf8(&item.content) else {
+        return BTreeSet::new();
+    };
+    let chars: Vec<char> = normalize(text).into_iter().map(|(c, _)| c).collect();
+    split_words(&chars).into_iter().collect()
+}
+
+impl Default for ClipboardHistory {
+    fn default() -> Self {
+        ClipboardHistory::new(DEFAULT_HISTORY_DEPTH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn text_items(content: &[u8]) -> Vec<SourceDataItem> {
+        vec![SourceDataItem::new(
+            vec!["text/plain".to_string()],
+            Rc::new(content.to_vec()),
+        )]
+    }
+
+    fn contents(history: &ClipboardHistory) -> Vec<&[u8]> {
+        history
+            .iter()
+            .map(|e| e.items[0].content.as_slice())
+            .collect()
+    }
+
+    #[test]
+    fn test_push_dedup() {
+        let mut history = ClipboardHistory::default();
+        history.push(text_items(b"A"));
+        history.push(text_items(b"B"));
+        history.push(text_items(b"A"));
+        assert_eq!(contents(&history), [b"B", b"A"]);
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut history = ClipboardHistory::new(2);
+        history.push(text_items(b"A"));
+        history.push(text_items(b"B"));
+        history.push(text_items(b"C"));
+        assert_eq!(contents(&history), [b"B", b"C"]);
+
+        history.set_depth(1);
+        assert_eq!(contents(&history), [b"C"]);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut history = ClipboardHistory::default();
+        history.push(text_items("Hello World, hello again".as_bytes()));
+        history.push(vec![SourceDataItem::new(
+            vec!["image/png".to_string()],
+            Rc::new(b"hello".to_vec()),
+        )]);
+        history.push(text_items("say HELLO".as_bytes()));
+
+        let matches = history.search("hello");
+        let found: Vec<(usize, usize, usize)> = matches
+            .iter()
+            .map(|m| (m.entry_index, m.match_offset, m.match_len))
+            .collect();
+        assert_eq!(found, [(2, 4, 5), (0, 0, 5), (0, 13, 5)]);
+
+        // Prefix of a word, and a phrase
+        assert_eq!(history.search("wor").len(), 1);
+        assert_eq!(history.search("hello wo").len(), 1);
+        // Not at the beginning of a word
+        assert!(history.search("ello").is_empty());
+        assert!(history.search("missing").is_empty());
+        assert!(history.search(" ").is_empty());
+    }
+
+    #[test]
+    fn test_search_non_ascii() {
+        let mut history = ClipboardHistory::default();
+        // "café" with a decomposed "é"
+        history.push(text_items("Un CAFE\u{301} noir".as_bytes()));
+        history.push(text_items("Größe ÜBER".as_bytes()));
+
+        let matches = history.search("café");
+        assert_eq!(
+            matches,
+            [HistoryMatch {
+                entry_index: 0,
+                item_index: 0,
+                match_offset: 3,
+                match_len: 6,
+            }]
+        );
+        let matches = history.search("über");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].entry_index, 1);
+        assert_eq!(matches[0].match_offset, 8);
+        assert_eq!(history.search("grö").len(), 1);
+    }
+
+    #[test]
+    fn test_search_index_updated() {
+        let mut history = ClipboardHistory::new(2);
+        history.push(text_items(b"first"));
+        history.push(text_items(b"second"));
+        history.push(text_items(b"first"));
+        // Moved to the top
+        assert_eq!(history.search("first")[0].entry_index, 1);
+
+        history.push(text_items(b"third"));
+        assert!(history.search("second").is_empty());
+        assert_eq!(history.search("third")[0].entry_index, 1);
+        assert!(!history.word_index.contains_key("second"));
+    }
+
+    #[test]
+    fn test_export_import_file() {
+        let path = std::env::temp_dir().join(format!("richclip-{}-history", std::process::id()));
+        let mut history = ClipboardHistory::new(4);
+        history.push(text_items(b"first"));
+        history.push(vec![
+            SourceDataItem::new(vec!["text/plain".to_string()], Rc::new(b"GOOD".to_vec())),
+            SourceDataItem::new(
+                vec!["image/png".to_string(), "PNG".to_string()],
+                Rc::new(b"\x89PNG".to_vec()),
+            ),
+        ]);
+        history.push(text_items(b""));
+        history.export_to_file_bulk_protocol(&path).unwrap();
+
+        let imported = ClipboardHistory::import_from_file_bulk_protocol(&path).unwrap();
+        assert_eq!(imported.len(), 3);
+        assert_eq!(imported.depth(), DEFAULT_HISTORY_DEPTH);
+        for (a, b) in imported.iter().zip(history.iter()) {
+            assert!(same_items(&a.items, &b.items));
+        }
+        assert_eq!(imported.search("first")[0].entry_index, 0);
+
+        // Truncated
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert!(ClipboardHistory::import_from_file_bulk_protocol(&path).is_err());
+        std::fs::write(&path, [&data[..], b"x"].concat()).unwrap();
+        assert!(ClipboardHistory::import_from_file_bulk_protocol(&path).is_err());
+        std::fs::write(&path, b"GOOD").unwrap();
+        assert!(ClipboardHistory::import_from_file_bulk_protocol(&path).is_err());
+        // A huge count without the entries
+        std::fs::write(&path, [&EXPORT_MAGIC[..], &[0xff; 4]].concat()).unwrap();
+        assert!(ClipboardHistory::import_from_file_bulk_protocol(&path).is_err());
+
+        // More entries than the default depth
+        let mut history = ClipboardHistory::new(DEFAULT_HISTORY_DEPTH + 2);
+        for i in 0..DEFAULT_HISTORY_DEPTH + 2 {
+            history.push(text_items(format!("entry {i}").as_bytes()));
+        }
+        history.export_to_file_bulk_protocol(&path).unwrap();
+        let imported = ClipboardHistory::import_from_file_bulk_protocol(&path).unwrap();
+        assert_eq!(imported.len(), DEFAULT_HISTORY_DEPTH + 2);
+        assert_eq!(imported.depth(), DEFAULT_HISTORY_DEPTH + 2);
+
+        ClipboardHistory::new(0)
+            .export_to_file_bulk_protocol(&path)
+            .unwrap();
+        let imported = ClipboardHistory::import_from_file_bulk_protocol(&path).unwrap();
+        assert!(imported.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
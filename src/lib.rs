@@ -0,0 +1,11 @@
+extern crate log;
+
+pub mod clipboard;
+pub mod compat;
+pub mod daemon;
+pub mod format;
+pub mod history;
+pub mod protocol;
+#[cfg(unix)]
+pub mod serve;
+pub mod store;
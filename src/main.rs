@@ -3,18 +3,25 @@ extern crate daemonize;
 extern crate log;
 extern crate simplelog;
 
-mod clipboard;
-mod protocol;
-
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use clap::{ArgAction, Args, Parser, Subcommand};
 #[cfg(target_os = "linux")]
 use daemonize::Daemonize;
+#[cfg(target_os = "linux")]
+use richclip::daemon::Daemon;
+#[cfg(target_os = "linux")]
+use richclip::serve;
+use richclip::{clipboard, compat, format, protocol};
 use std::env;
 #[cfg(target_os = "linux")]
 use std::fs::File;
-use std::io::{stdin, stdout};
+use std::io::{IsTerminal, Write, stdin, stdout};
+#[cfg(any(target_os = "linux", feature = "serde"))]
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use std::time::Instant;
 
 /// Clipboard utility for multiple platforms
 #[derive(Parser)]
@@ -41,6 +48,76 @@ struct CopyArgs {
     #[arg(long = "type", short = 't', num_args = 0..=1,
         value_name = "mime-type", default_missing_value = "TEXT", action = ArgAction::Append )]
     mime_types: Option<Vec<String>>,
+    /// Reassemble the content from the indexed chunks of 'paste --chunk-index' on stdin, and
+    /// implicitly enable one-shot copy mode
+    #[arg(long = "chunk-index", num_args = 0)]
+    chunk_index: bool,
+    /// Copy the Markdown text from stdin as both 'text/plain' and rendered 'text/html'
+    #[cfg(feature = "markdown")]
+    #[arg(
+        long = "markdown",
+        num_args = 0,
+        conflicts_with_all = ["oneshot", "mime_types", "import_export", "from_dir"]
+    )]
+    markdown: bool,
+    /// Copy the output of the export command from stdin, an export without items clears the
+    /// clipboard
+    #[arg(
+        long = "import-export",
+        num_args = 0,
+        conflicts_with_all = ["oneshot", "mime_types", "chunk_index"]
+    )]
+    import_export: bool,
+    /// Copy every file in the directory as an item instead of reading stdin, with the mime-type
+    /// of its extension. A 'manifest.json' like the one of 'export --split-dir' can set the order
+    /// and the mime-types
+    #[cfg(feature = "serde")]
+    #[arg(
+        long = "from-dir",
+        value_name = "dir",
+        num_args = 1,
+        conflicts_with_all = ["oneshot", "mime_types", "import_export", "chunk_index"]
+    )]
+    from_dir: Option<PathBuf>,
+    /// Strip the leading and trailing whitespace from the text contents
+    #[arg(long = "trim", num_args = 0)]
+    trim: bool,
+    /// Remove the consecutive duplicate lines from the text contents, like `uniq`
+    #[arg(long = "uniq", num_args = 0)]
+    uniq: bool,
+    /// Remove all the duplicate lines from the text contents, keeping the first ones
+    #[arg(long = "uniq-all", num_args = 0, conflicts_with = "uniq")]
+    uniq_all: bool,
+    /// Also offer the text contents as the mime-type of their language, e.g. 'text/x-python',
+    /// when a shebang or an editor modeline tells it
+    #[arg(long = "sniff-code", num_args = 0)]
+    sniff_code: bool,
+    /// Also offer a PNG thumbnail of the images as 'image/png;thumbnail=1', for the apps which
+    /// prefer a small preview
+    #[cfg(feature = "image")]
+    #[arg(long = "with-thumbnail", num_args = 0)]
+    with_thumbnail: bool,
+    /// Decode the received contents from URL safe base64, e.g. of 'paste --base64url'
+    #[arg(long = "base64url", num_args = 0)]
+    base64url: bool,
+    /// Drop the contents larger than the given size, so only the ones that fit are offered
+    #[arg(long = "max-size", value_name = "bytes", num_args = 1)]
+    max_size: Option<usize>,
+    /// Keep serving the selection for the duration only, e.g. `30s`, then exit whether it has
+    /// been pasted or not
+    #[arg(long = "hold-for", value_name = "duration", num_args = 1,
+        value_parser = format::parse_duration)]
+    hold_for: Option<Duration>,
+    /// Also write the copied content to stdout
+    #[arg(long = "echo", num_args = 0)]
+    echo: bool,
+    /// Write the first content as an OSC 52 escape sequence to stdout, for the terminal to copy
+    /// it, instead of using the clipboard
+    #[arg(long = "osc52", num_args = 0, conflicts_with = "echo")]
+    osc52: bool,
+    /// Wrap the OSC 52 sequence in the tmux passthrough, the default when `$TMUX` is set
+    #[arg(long = "tmux", num_args = 0, requires = "osc52")]
+    tmux: bool,
     /// For testing X INCR mode
     #[arg(
         long = "chunk-size",
@@ -71,6 +148,231 @@ struct PasteArgs {
     #[cfg(target_os = "linux")]
     #[arg(long = "primary", short = 'p', num_args = 0)]
     primary: bool,
+    /// Paste all the offered contents as a 'multipart/related' MIME document
+    #[arg(long = "multipart", num_args = 0, conflicts_with_all = ["list_types", "type_"])]
+    multipart: bool,
+    /// Print the size of the content in bytes only, as it is pasted before any other processing
+    #[arg(long = "size", num_args = 0, conflicts_with_all = ["list_types", "multipart"])]
+    size: bool,
+    /// Print the line, word and byte counts of the text content like `wc`, or only the byte count
+    /// if it is not text
+    #[arg(long = "stats", num_args = 0, conflicts_with_all = ["list_types", "multipart", "size"])]
+    stats: bool,
+    /// Paste only if the fingerprint of the content differs from the given one in hex, and print
+    /// the new fingerprint to stderr. Exit with code 3 without output if it doesn't
+    #[arg(long = "changed-since", value_name = "hash", num_args = 1,
+        conflicts_with_all = ["list_types", "multipart"],
+        value_parser = protocol::parse_fingerprint)]
+    changed_since: Option<u64>,
+    /// Append a newline to text content if it doesn't end with one
+    #[arg(long = "newline", num_args = 0)]
+    newline: bool,
+    /// Remove the control characters from text content, except tab and newline
+    #[arg(long = "printable-only", num_args = 0)]
+    printable_only: bool,
+    /// Replace the control characters with the given character instead of removing them
+    #[arg(
+        long = "replacement",
+        value_name = "char",
+        num_args = 1,
+        requires = "printable_only"
+    )]
+    replacement: Option<char>,
+    /// Re-delimit the fields on every line of text content, from '--ifs' to '--ofs'
+    #[arg(long = "fields", num_args = 0)]
+    fields: bool,
+    /// The input field separator of '--fields', `\t`, `\n` and `\\` are unescaped
+    #[arg(long = "ifs", value_name = "separator", num_args = 1, default_value = "\\t",
+        requires = "fields", value_parser = format::parse_separator)]
+    ifs: String,
+    /// The output field separator of '--fields'
+    #[arg(long = "ofs", value_name = "separator", num_args = 1, default_value = ",",
+        requires = "fields", value_parser = format::parse_separator)]
+    ofs: String,
+    /// Number the lines of text content
+    #[arg(long = "number", num_args = 0)]
+    number: bool,
+    /// Print the text content as a shell variable assignment, e.g. `eval "$(richclip paste --env VAR)"`
+    #[arg(long = "env", value_name = "name", num_args = 1)]
+    env: Option<String>,
+    /// Print the JSON text content indented, fail if it is not valid JSON
+    #[arg(
+        long = "pretty-json",
+        num_args = 0,
+        conflicts_with = "pretty_json_lenient"
+    )]
+    pretty_json: bool,
+    /// Same as '--pretty-json', but print the content as it is if it is not valid JSON
+    #[arg(long = "pretty-json-lenient", num_args = 0)]
+    pretty_json_lenient: bool,
+    /// Print the detected encoding of the text content to stderr
+    #[arg(long = "detect-encoding", num_args = 0)]
+    detect_encoding: bool,
+    /// Convert the text content from UTF-8 to the encoding, ascii or latin-1
+    #[arg(long = "output-encoding", value_name = "encoding", num_args = 1)]
+    output_encoding: Option<format::OutputEncoding>,
+    /// Replace the characters the output encoding can't represent with `?` instead of failing
+    #[arg(long = "replace", num_args = 0, requires = "output_encoding")]
+    replace: bool,
+    /// Write the content to the terminal even if it looks binary
+    #[arg(long = "binary", num_args = 0)]
+    binary: bool,
+    /// Encode the content as URL safe base64 without padding, to be embedded in URLs
+    #[arg(long = "base64url", num_args = 0, conflicts_with = "multipart")]
+    base64url: bool,
+    /// Highlight JSON, HTML and source code with colors, unless `NO_COLOR` is set
+    #[cfg(feature = "terminal-ui")]
+    #[arg(long = "color", num_args = 0)]
+    color: bool,
+    /// Write the content in chunks of the given size
+    #[arg(long = "chunk-size", value_name = "bytes", num_args = 1,
+        value_parser = clap::value_parser!(u32).range(1..))]
+    chunk_size: Option<u32>,
+    /// Prefix every chunk with its length as big-endian uint32
+    #[arg(long = "length-prefix", num_args = 0, requires = "chunk_size")]
+    length_prefix: bool,
+    /// Prefix every chunk with its index and length, and end with an empty chunk, so they can
+    /// be reassembled in order
+    #[arg(
+        long = "chunk-index",
+        num_args = 0,
+        requires = "chunk_size",
+        conflicts_with = "length_prefix"
+    )]
+    chunk_index: bool,
+}
+
+/// Arguments for serve command
+#[cfg(target_os = "linux")]
+#[derive(Args)]
+struct ServeArgs {
+    /// Path of the Unix socket to listen on. Every connection sends the data to be copied with the
+    /// bulk protocol, or a command like `touch`, and closes its writing side when done
+    #[arg(long = "socket", value_name = "path", num_args = 1)]
+    socket: PathBuf,
+    /// Permission mode of the socket in octal
+    #[arg(long = "socket-mode", value_name = "mode", num_args = 1, default_value = "0600",
+        value_parser = serve::parse_socket_mode)]
+    socket_mode: u32,
+    /// Group of the socket, use it with a mode like 0660 to allow the group members to copy
+    #[arg(long = "socket-group", value_name = "group", num_args = 1)]
+    socket_group: Option<String>,
+    /// Use the 'primary' clipboard
+    #[arg(long = "primary", short = 'p', num_args = 0)]
+    primary: bool,
+    /// Send a heartbeat to the client when nothing has been sent to it for the duration, e.g.
+    /// `10s`, while its connection is open
+    #[arg(long = "heartbeat", value_name = "duration", num_args = 1,
+        value_parser = format::parse_duration)]
+    heartbeat: Option<Duration>,
+    /// Close the connection of a client which sends nothing for the duration instead of waiting
+    /// for the rest of its stream forever. The connections are handled one by one, so the other
+    /// clients and `--reassert` wait for it meanwhile
+    #[arg(long = "stall-timeout", value_name = "duration", num_args = 1, default_value = "30s",
+        value_parser = format::parse_duration)]
+    stall_timeout: Duration,
+    /// Re-offer the held content every duration, e.g. `5m`, for the compositors which drop
+    /// selections not asserted for a while
+    #[arg(long = "reassert", value_name = "duration", num_args = 1,
+        value_parser = format::parse_duration)]
+    reassert: Option<Duration>,
+    /// Keep the contents in a content-addressed store in the directory instead of in memory, they
+    /// are read from it when pasted
+    #[arg(long = "store-dir", value_name = "dir", num_args = 1)]
+    store_dir: Option<PathBuf>,
+}
+
+/// Arguments for touch command
+#[cfg(target_os = "linux")]
+#[derive(Args)]
+struct TouchArgs {
+    /// Path of the Unix socket the server listens on
+    #[arg(long = "socket", value_name = "path", num_args = 1)]
+    socket: PathBuf,
+}
+
+/// Arguments for export command
+#[derive(Args)]
+struct ExportArgs {
+    /// Use the 'primary' clipboard
+    #[cfg(target_os = "linux")]
+    #[arg(long = "primary", short = 'p', num_args = 0)]
+    primary: bool,
+    /// Fail instead of warning if any text content is not valid UTF-8
+    #[arg(long = "strict", num_args = 0)]
+    strict: bool,
+    /// Write the content of every item to its own file in the directory, described by a JSON
+    /// manifest, instead of to the output
+    #[cfg(feature = "serde")]
+    #[arg(long = "split-dir", value_name = "dir", num_args = 1)]
+    split_dir: Option<PathBuf>,
+}
+
+/// Arguments for debug command
+#[derive(Args)]
+struct DebugArgs {
+    /// Print the mime-types offered by every item only
+    #[arg(long = "common", num_args = 0)]
+    common: bool,
+    /// Print every item as a JSON clipboard event of the given type
+    #[cfg(feature = "serde")]
+    #[arg(
+        long = "event-json",
+        value_name = "event-type",
+        num_args = 1,
+        conflicts_with = "common"
+    )]
+    event_json: Option<String>,
+    /// Include the base64 encoded content in the JSON events
+    #[cfg(feature = "serde")]
+    #[arg(long = "include-content", num_args = 0, requires = "event_json")]
+    include_content: bool,
+    /// Check the items offer all the comma separated mime-types between them, and fail listing
+    /// the missing ones instead of printing the items
+    #[arg(
+        long = "require-schema",
+        value_name = "mime-types",
+        num_args = 1,
+        value_delimiter = ',',
+        conflicts_with = "common"
+    )]
+    require_schema: Option<Vec<String>>,
+}
+
+impl PasteArgs {
+    // If the content must be checked before writing it, so binary isn't written to the terminal.
+    // The filters which make it safe to display are fine.
+    fn checks_binary(&self) -> bool {
+        !self.binary
+            && !self.base64url
+            && !self.printable_only
+            && !self.list_types
+            && stdout().is_terminal()
+    }
+
+    // If the pasted content needs to be processed before writing to the output
+    fn filters_output(&self) -> bool {
+        #[cfg(feature = "terminal-ui")]
+        if self.color && !self.list_types {
+            return true;
+        }
+        !self.list_types
+            && (self.newline
+                || self.number
+                || self.printable_only
+                || self.fields
+                || self.pretty_json
+                || self.pretty_json_lenient
+                || self.detect_encoding
+                || self.env.is_some()
+                || self.output_encoding.is_some()
+                || self.base64url
+                || self.checks_binary()
+                || self.chunk_size.is_some()
+                || self.changed_since.is_some()
+                || self.size
+                || self.stats)
+    }
 }
 
 #[derive(Subcommand)]
@@ -79,6 +381,16 @@ enum Commands {
     Copy(CopyArgs),
     /// Paste the data from clipboard to the output
     Paste(PasteArgs),
+    /// Listen on a Unix socket and copy the data received from the clients
+    #[cfg(target_os = "linux")]
+    Serve(ServeArgs),
+    /// Make the server re-offer the content it holds, to refresh the clipboard ownership
+    #[cfg(target_os = "linux")]
+    Touch(TouchArgs),
+    /// Write all the contents of the clipboard to the output, for `copy --import-export`
+    Export(ExportArgs),
+    /// Parse the data received from stdin and print the items in it
+    Debug(DebugArgs),
     /// Print version info
     Version,
 }
@@ -117,11 +429,17 @@ fn init_logger() -> Result<()> {
 fn main() -> Result<()> {
     init_logger()?;
 
-    let cli = Cli::parse();
+    let cli = Cli::parse_from(compat::translate_args(env::args_os().collect())?);
 
     match cli.command {
         Commands::Copy(copy_args) => do_copy(&copy_args)?,
         Commands::Paste(paste_args) => do_paste(&paste_args)?,
+        #[cfg(target_os = "linux")]
+        Commands::Serve(serve_args) => do_serve(&serve_args)?,
+        #[cfg(target_os = "linux")]
+        Commands::Touch(touch_args) => serve::send_command(&touch_args.socket, serve::TOUCH)?,
+        Commands::Export(export_args) => do_export(&export_args)?,
+        Commands::Debug(debug_args) => do_debug(&debug_args)?,
         Commands::Version => {
             let ver = env!("CARGO_PKG_VERSION");
             let git_desc = env!("VERGEN_GIT_DESCRIBE");
@@ -134,6 +452,19 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+// Offer the text item first as the mime-type of its language if it is code.
+fn sniff_code_item(item: protocol::SourceDataItem) -> protocol::SourceDataItem {
+    match clipboard::sniff_code_mime_type(&item.content) {
+        Some(code_type) if item.is_text() && !item.mime_type.iter().any(|t| t == code_type) => {
+            log::debug!("Sniffed code mime-type {code_type}");
+            let mut mime_type = vec![code_type.to_string()];
+            mime_type.extend(item.mime_type.iter().cloned());
+            protocol::SourceDataItem::new(mime_type, item.content)
+        }
+        _ => item,
+    }
+}
+
 fn do_copy(copy_args: &CopyArgs) -> Result<()> {
     const TEXT_TYPES: [&str; 5] = [
         "text/plain",
@@ -143,17 +474,130 @@ fn do_copy(copy_args: &CopyArgs) -> Result<()> {
         "UTF8_STRING",
     ];
     let stdin = stdin();
-    let oneshot = copy_args.oneshot || copy_args.mime_types.is_some();
+    let oneshot = copy_args.oneshot || copy_args.mime_types.is_some() || copy_args.chunk_index;
+    #[cfg(feature = "markdown")]
+    let oneshot = oneshot || copy_args.markdown;
+
+    #[cfg(feature = "serde")]
+    let from_dir = match &copy_args.from_dir {
+        Some(dir) => {
+            let items = format::read_split_dir(dir)?;
+            if items.is_empty() {
+                bail!("No files to copy in '{}'", dir.display());
+            }
+            Some(items)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "serde"))]
+    let from_dir = None;
 
-    let source_data = if oneshot {
+    let source_data = if let Some(items) = from_dir {
+        items
+    } else if oneshot {
         let mime_types = match &copy_args.mime_types {
             Some(types) => types.to_vec(),
             _ => TEXT_TYPES.iter().map(|s| s.to_string()).collect(),
         };
-        protocol::receive_data_oneshot(&stdin, &mime_types)?
+        if copy_args.chunk_index {
+            // Not larger than a section, or the content would be dropped by `--max-size` anyway
+            let limit = copy_args.max_size.unwrap_or(u32::MAX as usize);
+            let content = format::reassemble_chunks_with_limit(&stdin, limit)?;
+            protocol::receive_data_oneshot(&content[..], &mime_types)?
+        } else {
+            protocol::receive_data_oneshot(&stdin, &mime_types)?
+        }
+    } else if copy_args.import_export {
+        let mut items = vec![];
+        if clipboard::import_export(&stdin, &mut items)? == 0 {
+            log::debug!("Imported an empty export, clear the clipboard");
+        }
+        items
     } else {
         protocol::receive_data_bulk(&stdin)?
     };
+    let source_data = if copy_args.base64url {
+        source_data
+            .into_iter()
+            .map(|item| {
+                let content = format::base64url_decode(&item.content)
+                    .context("Failed to decode the content from base64url")?;
+                Ok(protocol::SourceDataItem::new(item.mime_type, content))
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        source_data
+    };
+    let source_data = if copy_args.trim {
+        source_data.iter().map(|item| item.trim_content()).collect()
+    } else {
+        source_data
+    };
+    let source_data = if copy_args.uniq || copy_args.uniq_all {
+        source_data
+            .into_iter()
+            .map(|item| {
+                if item.is_text() {
+                    let content = format::uniq_lines(&item.content, copy_args.uniq_all);
+                    protocol::SourceDataItem::new(item.mime_type, content)
+                } else {
+                    item
+                }
+            })
+            .collect()
+    } else {
+        source_data
+    };
+    let source_data = if copy_args.sniff_code {
+        source_data.into_iter().map(sniff_code_item).collect()
+    } else {
+        source_data
+    };
+    #[cfg(feature = "image")]
+    let source_data = if copy_args.with_thumbnail {
+        format::add_thumbnails(source_data, format::THUMBNAIL_SIZE)
+    } else {
+        source_data
+    };
+    #[cfg(feature = "markdown")]
+    let source_data = if copy_args.markdown {
+        let mut items = source_data;
+        items.push(format::render_markdown_item(&items[0]));
+        items
+    } else {
+        source_data
+    };
+
+    let source_data = match copy_args.max_size {
+        Some(max_size) if !source_data.is_empty() => {
+            let items = protocol::drop_oversized(source_data, max_size);
+            if items.is_empty() {
+                bail!("No content fits in {max_size} bytes");
+            }
+            items
+        }
+        _ => source_data,
+    };
+
+    if copy_args.echo {
+        // Must be done before stdout gets redirected by daemonizing
+        format::write_contents(&source_data, stdout()).context("Failed to write to the output")?;
+    }
+    if copy_args.osc52 {
+        let Some(item) = source_data.first() else {
+            bail!("Nothing to copy with OSC 52");
+        };
+        #[cfg(target_os = "linux")]
+        let use_primary = copy_args.primary;
+        #[cfg(not(target_os = "linux"))]
+        let use_primary = false;
+        let tmux = copy_args.tmux || format::in_tmux();
+        let mut out = stdout();
+        return out
+            .write_all(&format::osc52_sequence(&item.content, use_primary, tmux))
+            .and_then(|_| out.flush())
+            .context("Failed to write to the output");
+    }
 
     #[cfg(target_os = "linux")]
     {
@@ -177,6 +621,16 @@ fn do_copy(copy_args: &CopyArgs) -> Result<()> {
         }
     }
 
+    if let Some(hold) = copy_args.hold_for {
+        // Started after daemonizing, the forked child doesn't inherit the threads. Exiting
+        // releases the selection.
+        std::thread::spawn(move || {
+            std::thread::sleep(hold);
+            log::debug!("Held the selection for {hold:?}, exit");
+            std::process::exit(0);
+        });
+    }
+
     let copy_config = clipboard::CopyConfig {
         source_data: Box::new(source_data),
         #[cfg(target_os = "linux")]
@@ -191,18 +645,239 @@ fn do_copy(copy_args: &CopyArgs) -> Result<()> {
 }
 
 fn do_paste(paste_args: &PasteArgs) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    let use_primary = paste_args.primary;
+    #[cfg(not(target_os = "linux"))]
+    let use_primary = false;
+
+    if paste_args.multipart {
+        let backend = clipboard::create_backend()?;
+        let items = clipboard::read_all(backend.as_ref(), use_primary)
+            .context("Failed to paste from clipboard")?;
+        return format::write_multipart(&items, stdout()).context("Failed to write to the output");
+    }
+
+    let buffer = clipboard::SharedBuffer::default();
     let cfg = clipboard::PasteConfig {
         list_types_only: paste_args.list_types,
-        #[cfg(target_os = "linux")]
-        use_primary: paste_args.primary,
-        #[cfg(not(target_os = "linux"))]
-        use_primary: false,
-        writter: Box::new(stdout()),
+        use_primary,
+        writter: if paste_args.filters_output() {
+            Box::new(buffer.clone())
+        } else {
+            Box::new(stdout())
+        },
         expected_mime_type: paste_args.type_.clone(),
     };
     clipboard::create_backend()?
         .paste(cfg)
-        .context("Failed to paste from clipboard")
+        .context("Failed to paste from clipboard")?;
+
+    if !paste_args.filters_output() {
+        return Ok(());
+    }
+    let content = buffer.take();
+    if let Some(since) = paste_args.changed_since {
+        let fingerprint = protocol::fingerprint_content(&content);
+        if fingerprint == since {
+            log::debug!("Content is not changed since {since:016x}");
+            std::process::exit(UNCHANGED_EXIT_CODE);
+        }
+        eprintln!("{fingerprint:016x}");
+    }
+    if paste_args.size {
+        println!("{}", content.len());
+    } else if paste_args.stats {
+        if clipboard::is_text_mime_type(&paste_args.type_) {
+            let stats = format::text_stats(&content);
+            println!("{} {} {}", stats.lines, stats.words, stats.bytes);
+        } else {
+            println!("{}", content.len());
+        }
+    } else {
+        if paste_args.checks_binary() {
+            format::check_terminal_output(&content, &paste_args.type_, true)?;
+        }
+        let content = filter_output(paste_args, content)?;
+        let mut out = stdout();
+        match paste_args.chunk_size {
+            Some(chunk_size) if paste_args.chunk_index => {
+                format::write_indexed_chunks(&content, chunk_size as usize, out).map(|_| ())
+            }
+            Some(chunk_size) => {
+                format::write_chunks(&content, chunk_size as usize, paste_args.length_prefix, out)
+                    .map(|_| ())
+            }
+            None => out.write_all(&content).and_then(|_| out.flush()),
+        }
+        .context("Failed to write to the output")?;
+    }
+    Ok(())
+}
+
+/// The exit code of `paste --changed-since` when the content is not changed.
+const UNCHANGED_EXIT_CODE: i32 = 3;
+
+fn filter_output(paste_args: &PasteArgs, mut content: Vec<u8>) -> Result<Vec<u8>> {
+    let is_text = clipboard::is_text_mime_type(&paste_args.type_);
+    if paste_args.detect_encoding {
+        if is_text {
+            let guess = format::detect_encoding(&content);
+            eprintln!(
+                "Detected encoding: {} (confidence {:.2})",
+                guess.name, guess.confidence
+            );
+        } else {
+            log::warn!("--detect-encoding only works with text content");
+        }
+    }
+    if paste_args.printable_only {
+        if !is_text {
+            bail!("--printable-only only works with text content");
+        }
+        content = format::printable_only(&content, paste_args.replacement);
+    }
+    if paste_args.fields {
+        if !is_text {
+            bail!("--fields only works with text content");
+        }
+        content = format::redelimit_fields(
+            &content,
+            paste_args.ifs.as_bytes(),
+            paste_args.ofs.as_bytes(),
+        );
+    }
+    if paste_args.pretty_json || paste_args.pretty_json_lenient {
+        match format::pretty_json(&content) {
+            Ok(pretty) if is_text => content = pretty,
+            Err(e) if paste_args.pretty_json => return Err(e),
+            Ok(_) if paste_args.pretty_json => bail!("--pretty-json only works with text content"),
+            _ => log::debug!("Content is not JSON text, print it as it is"),
+        }
+    }
+    #[cfg(feature = "terminal-ui")]
+    if paste_args.color
+        && is_text
+        && let Some(colored) = format::highlight(&content, &paste_args.type_)
+    {
+        content = colored;
+    }
+    if paste_args.newline && is_text {
+        content = format::ensure_trailing_newline(content);
+    }
+    if paste_args.number && is_text {
+        content = format::number_lines(&content);
+    }
+    if let Some(name) = &paste_args.env {
+        if !is_text {
+            bail!("--env only works with text content");
+        }
+        content = format::shell_assignment(name, &content)?;
+    }
+    if let Some(encoding) = paste_args.output_encoding {
+        if !is_text {
+            bail!("--output-encoding only works with text content");
+        }
+        content = format::encode_text(&content, encoding, paste_args.replace)?;
+    }
+    if paste_args.base64url {
+        content = format::base64url_encode(&content).into_bytes();
+    }
+    Ok(content)
+}
+
+#[cfg(target_os = "linux")]
+fn do_serve(serve_args: &ServeArgs) -> Result<()> {
+    let listener = serve::bind_socket(
+        &serve_args.socket,
+        Some(serve_args.socket_mode),
+        serve_args.socket_group.as_deref(),
+    )?;
+    // Let the exited children of the sink be reaped automatically
+    unsafe { libc::signal(libc::SIGCHLD, libc::SIG_IGN) };
+    let options = serve::ConnectionOptions {
+        heartbeat: serve_args.heartbeat,
+        stall_timeout: Some(serve_args.stall_timeout),
+    };
+    let mut daemon = Daemon::new(serve::ForkingSink {
+        use_primary: serve_args.primary,
+    });
+    if let Some(dir) = &serve_args.store_dir {
+        daemon = daemon.with_content_store(dir)?;
+    }
+    let mut next_touch = serve_args
+        .reassert
+        .map(|interval| Instant::now() + interval);
+
+    loop {
+        serve::handle_next(
+            &listener,
+            &options,
+            &mut daemon,
+            serve_args.reassert,
+            &mut next_touch,
+        );
+    }
+}
+
+fn do_export(export_args: &ExportArgs) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    let use_primary = export_args.primary;
+    #[cfg(not(target_os = "linux"))]
+    let use_primary = false;
+
+    let source = clipboard::BackendSource {
+        backend: clipboard::create_backend()?,
+        use_primary,
+    };
+    #[cfg(feature = "serde")]
+    if let Some(dir) = &export_args.split_dir {
+        use clipboard::ClipboardSource;
+        let items = source.read()?;
+        protocol::check_utf8_text(&items, export_args.strict)?;
+        format::write_split_dir(&items, dir)?;
+        log::debug!("Exported {} items to '{}'", items.len(), dir.display());
+        return Ok(());
+    }
+    let count = clipboard::export_items(&source, stdout().lock(), export_args.strict)?;
+    log::debug!("Exported {count} items");
+    Ok(())
+}
+
+fn do_debug(debug_args: &DebugArgs) -> Result<()> {
+    let items = protocol::receive_data_bulk(&stdin())?;
+    if let Some(required) = &debug_args.require_schema {
+        let missing = protocol::missing_types(&items, required);
+        if !missing.is_empty() {
+            bail!("Missing required mime-types: {}", missing.join(", "));
+        }
+        return Ok(());
+    }
+    let mut out = stdout();
+    #[cfg(feature = "serde")]
+    if let Some(event_type) = &debug_args.event_json {
+        for item in &items {
+            let event = if debug_args.include_content {
+                item.to_clipboard_event_json_with_content(event_type)
+            } else {
+                item.to_clipboard_event_json(event_type)
+            };
+            writeln!(out, "{event}")?;
+        }
+        return Ok(());
+    }
+    if debug_args.common {
+        for mime_type in protocol::common_types(&items) {
+            writeln!(out, "{mime_type}")?;
+        }
+    } else {
+        for (i, item) in items.iter().enumerate() {
+            writeln!(out, "item {i}: {} bytes", item.content.len())?;
+            for mime_type in &item.mime_type {
+                writeln!(out, "  {mime_type}")?;
+            }
+        }
+    }
+    Ok(())
 }
 
 #[cfg(target_os = "linux")]
@@ -214,6 +889,7 @@ fn ignore_sighub() {
     }
 
     const SIGHUB: i32 = 1;
+    #[allow(clippy::manual_dangling_ptr)]
     const SIG_IGN: *const c_void = 1 as *const c_void;
     unsafe {
         signal(SIGHUB, SIG_IGN);
@@ -0,0 +1,241 @@
+use super::recv::{
+    PROTOCAL_VER_COUNTED, RecvConfig, TrailingData, check_item_count, check_section_size,
+    decode_u32, default_alloc, read_header_with_count, read_section, trailing_data_error,
+};
+use super::source_data::SourceDataItem;
+use anyhow::{Result, bail};
+use bytes::{Buf, BytesMut};
+use std::collections::HashMap;
+
+// Flag and length
+const SECTION_HEADER_LEN: usize = 5;
+
+/// Same as `receive_data_bulk`, but the stream arrives as `BytesMut` chunks, e.g. from a tokio
+/// codec. The chunks are taken over instead of being copied into a buffer, joined with
+/// `BytesMut::unsplit`, which is free when they are contiguous. Every section is parsed once it
+/// is complete, and its content is copied into the item, which owns a `Vec`.
+pub fn receive_data_bulk_from_bytes_mut_stream(
+    chunks: impl IntoIterator<Item = BytesMut>,
+) -> Result<Vec<SourceDataItem>> {
+    receive_data_bulk_from_bytes_mut_stream_with_config(chunks, &RecvConfig::default())
+}
+
+/// Same as `receive_data_bulk_from_bytes_mut_stream`, with the given config. The size of a
+/// section is checked as soon as its header arrives, so the chunks of a section over the limits
+/// are not buffered. The timing limits don't apply, since the caller does the reading.
+pub fn receive_data_bulk_from_bytes_mut_stream_with_config(
+    chunks: impl IntoIterator<Item = BytesMut>,
+    config: &RecvConfig,
+) -> Result<Vec<SourceDataItem>> {
+    let mut parser = BytesParser {
+        config,
+        buf: BytesMut::new(),
+        header_read: false,
+        count: None,
+        type_list: vec![],
+        extensions: HashMap::new(),
+        first: true,
+        items: vec![],
+    };
+    for chunk in chunks {
+        parser.push(chunk)?;
+    }
+    parser.finish()
+}
+
+struct BytesParser<'a> {
+    config: &'a RecvConfig,
+    buf: BytesMut,
+    header_read: bool,
+    count: Option<u32>,
+    type_list: Vec<String>,
+    extensions: HashMap<String, Vec<u8>>,
+    // If no section of the item is read yet
+    first: bool,
+    items: Vec<SourceDataItem>,
+}
+
+impl BytesParser<'_> {
+    fn push(&mut self, chunk: BytesMut) -> Result<()> {
+        if self.buf.is_empty() {
+            self.buf = chunk;
+        } else {
+            self.buf.unsplit(chunk);
+        }
+        if !self.header_read {
+            // With the item count of `PROTOCAL_VER_COUNTED`
+            let len = match self.buf.get(4) {
+                Some(&ver) if ver == PROTOCAL_VER_COUNTED => 9,
+                _ => 5,
+            };
+            if self.buf.len() < len {
+                return Ok(());
+            }
+            self.count = read_header_with_count(&mut &self.buf[..len])?;
+            self.buf.advance(len);
+            self.header_read = true;
+        }
+        while let Some(header) = self.buf.get(..SECTION_HEADER_LEN) {
+            let flag = header[0];
+            let len = decode_u32(header[1..].try_into().unwrap(), self.config.byte_order) as usize;
+            if self.buf.len() < SECTION_HEADER_LEN + len {
+                // Don't wait for a section over the limits
+                check_section_size(flag, len, self.config)?;
+                break;
+            }
+            log::debug!("Read block flag '{flag}' of {len} bytes");
+            let mut section = &self.buf[1..SECTION_HEADER_LEN + len];
+            let item = read_section(
+                &mut section,
+                flag,
+                self.config,
+                &mut default_alloc,
+                &mut self.type_list,
+                &mut self.extensions,
+            )
+            .map_err(|e| {
+                trailing_data_error(e, flag, self.first && !self.items.is_empty(), self.config)
+            })?;
+            self.buf.advance(SECTION_HEADER_LEN + len);
+            self.first = item.is_some();
+            self.items.extend(item);
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<SourceDataItem>> {
+        if !self.header_read {
+            bail!("Failed to read magic header");
+        }
+        if !self.buf.is_empty() {
+            let e = anyhow::anyhow!(
+                "The stream ended in the middle of a section, {} bytes left",
+                self.buf.len()
+            );
+            if self.config.reject_trailing_data && self.first && !self.items.is_empty() {
+                return Err(e.context(TrailingData { byte: self.buf[0] }));
+            }
+            return Err(e);
+        }
+        check_item_count(self.count, self.items.len())?;
+        Ok(self.items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{NamedSections, PROTOCAL_VER};
+
+    fn stream_data() -> Vec<u8> {
+        #[rustfmt::skip]
+        let buf =
+            vec![0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'H', 0, 0, 0, 0,
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L',
+            b'C', 0, 0, 0, 3, b'B', b'A', b'D',
+            ];
+        buf
+    }
+
+    #[test]
+    fn test_receive_data_bulk_from_bytes_mut_stream() {
+        let buf = stream_data();
+        for chunk_size in [1, 3, 7, buf.len()] {
+            let chunks = buf.chunks(chunk_size).map(BytesMut::from);
+            let r = receive_data_bulk_from_bytes_mut_stream(chunks).unwrap();
+            assert_eq!(r.len(), 2, "chunk size {chunk_size}");
+            assert_eq!(r[0].mime_type, vec!["TEXT"]);
+            assert_eq!(r[0].content.as_slice(), b"GOOD");
+            assert_eq!(r[1].mime_type, vec!["HTML"]);
+            assert_eq!(r[1].content.as_slice(), b"BAD");
+        }
+
+        // Contiguous chunks split off the same buffer are joined without copying
+        let mut all = BytesMut::from(&buf[..]);
+        let mut chunks = vec![];
+        while !all.is_empty() {
+            chunks.push(all.split_to(all.len().min(6)));
+        }
+        let r = receive_data_bulk_from_bytes_mut_stream(chunks).unwrap();
+        assert_eq!(r.len(), 2);
+    }
+
+    #[test]
+    fn test_receive_data_bulk_from_bytes_mut_stream_errors() {
+        let buf = stream_data();
+        let r = receive_data_bulk_from_bytes_mut_stream([BytesMut::from(&buf[..buf.len() - 1])]);
+        assert!(r.is_err());
+        let r = receive_data_bulk_from_bytes_mut_stream([BytesMut::from(&buf[1..])]);
+        assert!(r.is_err());
+        let r = receive_data_bulk_from_bytes_mut_stream([BytesMut::from(&buf[..3])]);
+        assert!(r.is_err());
+        // Content without mime-type
+        let r = receive_data_bulk_from_bytes_mut_stream([
+            BytesMut::from(&buf[..5]),
+            BytesMut::from(&buf[19..28]),
+        ]);
+        assert!(r.is_err());
+
+        // Header only, no items
+        let r = receive_data_bulk_from_bytes_mut_stream([BytesMut::from(&buf[..5])]).unwrap();
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn test_receive_data_bulk_from_bytes_mut_stream_with_config() {
+        let mut writer = crate::protocol::ProtocolWriter::with_item_count(Vec::new(), 1).unwrap();
+        writer.write_named("source", b"terminal").unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+        let chunks = || buf.chunks(3).map(BytesMut::from);
+        assert!(receive_data_bulk_from_bytes_mut_stream(chunks()).is_err());
+        let config = RecvConfig {
+            named_sections: NamedSections::Collect,
+            ..Default::default()
+        };
+        let r = receive_data_bulk_from_bytes_mut_stream_with_config(chunks(), &config).unwrap();
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert_eq!(r[0].extensions["source"], b"terminal");
+        // The item count of the header is checked
+        let r = receive_data_bulk_from_bytes_mut_stream_with_config(
+            [BytesMut::from(&buf[..9])],
+            &config,
+        );
+        assert_eq!(
+            r.unwrap_err().to_string(),
+            "Expected 1 items, but received 0"
+        );
+
+        // The declared size is checked before the section arrives
+        let config = RecvConfig {
+            max_section_bytes: 1024,
+            ..Default::default()
+        };
+        let head = [
+            0x20,
+            0x09,
+            0x02,
+            0x14,
+            PROTOCAL_VER,
+            b'H',
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+        ];
+        let r = receive_data_bulk_from_bytes_mut_stream_with_config(
+            std::iter::once(BytesMut::from(&head[..])).chain(std::iter::repeat_with(|| {
+                panic!("Waiting for the section over the limit")
+            })),
+            &config,
+        );
+        assert_eq!(
+            r.unwrap_err().to_string(),
+            "Content size 4294967295 exceeds the limit 1024"
+        );
+    }
+}
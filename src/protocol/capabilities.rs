@@ -0,0 +1,278 @@
+use super::recv::{
+    RecvConfig, default_alloc, read_content_with, read_counted_sections, read_header_with_count,
+};
+use super::source_data::SourceDataItem;
+use anyhow::{Context, Result, bail};
+use std::io::Read;
+
+const COMPRESSION: &str = "compression";
+const DIGEST: &str = "digest";
+const LENGTH64: &str = "length64";
+/// The limit of the `N` section length, far longer than all the known names together.
+const MAX_CAPABILITIES_LEN: usize = 1024;
+
+/// The optional section types a peer understands, announced in an `N` (negotiation) section
+/// before the items. The server replies with the `intersection` of the client's and its own,
+/// and both only use what is in it.
+/// The section data is the names of the capabilities separated by `,`, e.g.
+/// `compression,digest`. Unknown names are ignored, so newer peers can announce more.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Capabilities {
+    /// `Z` sections, see the `compression` feature
+    pub compression: bool,
+    /// Content digests
+    pub digest: bool,
+    /// 64-bit section lengths
+    pub length64: bool,
+}
+
+impl Capabilities {
+    /// What this build of richclip understands.
+    pub fn supported() -> Self {
+        Capabilities {
+            compression: cfg!(feature = "compression"),
+            digest: false,
+            length64: false,
+        }
+    }
+
+    /// The capabilities both sides understand.
+    pub fn intersection(&self, other: &Capabilities) -> Self {
+        Capabilities {
+            compression: self.compression && other.compression,
+            digest: self.digest && other.digest,
+            length64: self.length64 && other.length64,
+        }
+    }
+
+    /// The data of the `N` section.
+    pub fn encode(&self) -> Vec<u8> {
+        let names: Vec<&str> = [
+            (self.compression, COMPRESSION),
+            (self.digest, DIGEST),
+            (self.length64, LENGTH64),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect();
+        names.join(",").into_bytes()
+    }
+
+    /// Parse the data of the `N` section.
+    pub fn parse(data: &[u8]) -> Self {
+        let mut ret = Capabilities::default();
+        for name in String::from_utf8_lossy(data).split(',') {
+            match name.trim() {
+                COMPRESSION => ret.compression = true,
+                DIGEST => ret.digest = true,
+                LENGTH64 => ret.length64 = true,
+                name => log::debug!("Ignored unknown capability '{name}'"),
+            }
+        }
+        ret
+    }
+}
+
+/// Read the `N` section at the current position, which is right after the magic header and the
+/// protocol version for a stream starting with the handshake.
+pub fn read_capabilities(reader: &mut impl Read) -> Result<Capabilities> {
+    let mut flag = [0u8; 1];
+    reader
+        .read_exact(&mut flag)
+        .context("Failed to read flag")?;
+    if flag[0] != b'N' {
+        bail!("Expected a negotiation section but found flag {}", flag[0]);
+    }
+    read_capabilities_data(reader)
+}
+
+// Read the length and the data of the `N` section after its flag.
+fn read_capabilities_data(reader: &mut impl Read) -> Result<Capabilities> {
+    let data = read_content_with(reader, MAX_CAPABILITIES_LEN, &mut default_alloc)
+        .context("Failed to read capabilities")?;
+    Ok(Capabilities::parse(&data))
+}
+
+/// Same as `receive_data_bulk_with_config`, on the server side of the handshake. If the stream
+/// starts with an `N` section, `negotiate` is called with the capabilities of the client and
+/// returns the ones to use, usually the `intersection` after sending it back in an `N` section
+/// of the reply. The rest of the stream is parsed with them as `RecvConfig::capabilities`. A
+/// stream without the handshake is parsed with the config as it is.
+pub fn receive_data_bulk_negotiated(
+    mut reader: impl Read,
+    config: &RecvConfig,
+    negotiate: impl FnOnce(Capabilities) -> Result<Capabilities>,
+) -> Result<Vec<SourceDataItem>> {
+    let (count, config, flag) = read_handshake(&mut reader, config, negotiate)?;
+    // The flag after the handshake is parsed as the first section
+    let flag = flag.as_slice();
+    read_counted_sections(&mut flag.chain(reader), count, &config)
+}
+
+/// Read the header and the handshake if there is one, see `receive_data_bulk_negotiated`. The
+/// item count of the header, the config to parse the rest with, and the flag of the section
+/// after the handshake are returned. The flag is `None` at EOF.
+pub(super) fn read_handshake(
+    reader: &mut impl Read,
+    config: &RecvConfig,
+    negotiate: impl FnOnce(Capabilities) -> Result<Capabilities>,
+) -> Result<(Option<u32>, RecvConfig, Option<u8>)> {
+    let count = read_header_with_count(reader)?;
+    let mut config = config.clone();
+    let mut flag = [0u8; 1];
+    if reader.read(&mut flag).context("Failed to read flag")? == 0 {
+        return Ok((count, config, None));
+    }
+    if flag[0] == b'N' {
+        let agreed = negotiate(read_capabilities_data(reader)?)?;
+        log::debug!("Negotiated capabilities {agreed:?}");
+        config.capabilities = Some(agreed);
+        if reader.read(&mut flag).context("Failed to read flag")? == 0 {
+            return Ok((count, config, None));
+        }
+    }
+    Ok((count, config, Some(flag[0])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PROTOCAL_VER, ProtocolWriter, receive_data_bulk};
+    use std::cell::Cell;
+
+    #[test]
+    fn test_capabilities_encode_parse() {
+        let caps = Capabilities {
+            compression: true,
+            digest: false,
+            length64: true,
+        };
+        assert_eq!(caps.encode(), b"compression,length64");
+        assert_eq!(Capabilities::parse(&caps.encode()), caps);
+        assert_eq!(
+            Capabilities::parse(b"digest, unknown,,\xff"),
+            Capabilities {
+                digest: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(Capabilities::parse(b""), Capabilities::default());
+        assert!(Capabilities::default().encode().is_empty());
+    }
+
+    #[test]
+    fn test_capabilities_intersection() {
+        let client = Capabilities {
+            compression: true,
+            digest: true,
+            length64: false,
+        };
+        let server = Capabilities {
+            compression: true,
+            digest: false,
+            length64: true,
+        };
+        let common = client.intersection(&server);
+        assert_eq!(
+            common,
+            Capabilities {
+                compression: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(common, server.intersection(&client));
+    }
+
+    #[test]
+    fn test_read_capabilities() {
+        let caps = Capabilities {
+            digest: true,
+            ..Default::default()
+        };
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_capabilities(&caps).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+        #[rustfmt::skip]
+        let expected =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'N', 0, 0, 0, 6, b'd', b'i', b'g', b'e', b's', b't',
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        assert_eq!(buf, expected);
+
+        let mut reader = &buf[5..];
+        assert_eq!(read_capabilities(&mut reader).unwrap(), caps);
+        assert!(read_capabilities(&mut reader).is_err());
+
+        // Skipped by the bulk parsing
+        let r = receive_data_bulk(&buf[..]).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+    }
+
+    #[test]
+    fn test_read_capabilities_limit() {
+        let mut buf = vec![b'N', 0, 0, 0x04, 0x01];
+        buf.resize(buf.len() + 1025, b',');
+        let e = read_capabilities(&mut &buf[..]).unwrap_err();
+        assert!(format!("{e:#}").contains("Content size 1025 exceeds the limit 1024"));
+    }
+
+    #[test]
+    fn test_receive_data_bulk_negotiated() {
+        let client = Capabilities {
+            compression: true,
+            digest: true,
+            length64: false,
+        };
+        let server = Capabilities {
+            digest: true,
+            ..Default::default()
+        };
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_capabilities(&client).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+
+        let announced = Cell::new(None);
+        let negotiate = |caps: Capabilities| {
+            announced.set(Some(caps));
+            Ok(caps.intersection(&server))
+        };
+        let r = receive_data_bulk_negotiated(&buf[..], &RecvConfig::default(), negotiate).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert_eq!(announced.get(), Some(client));
+
+        // Compressed sections need the compression to be agreed
+        let mut compressed = buf[..buf.len() - 9].to_vec();
+        compressed.extend_from_slice(&[b'Z', 0, 0, 0, 1, 0]);
+        let e = receive_data_bulk_negotiated(&compressed[..], &RecvConfig::default(), |caps| {
+            Ok(caps.intersection(&server))
+        })
+        .unwrap_err();
+        assert!(e.to_string().contains("compression is not negotiated"));
+
+        // Without the handshake
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let r = receive_data_bulk_negotiated(
+            &writer.into_inner()[..],
+            &RecvConfig::default(),
+            |_| unreachable!(),
+        )
+        .unwrap();
+        assert_eq!(r[0].mime_type, ["TEXT"]);
+
+        let header = [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER];
+        let r =
+            receive_data_bulk_negotiated(&header[..], &RecvConfig::default(), |_| unreachable!())
+                .unwrap();
+        assert!(r.is_empty());
+    }
+}
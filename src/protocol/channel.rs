@@ -0,0 +1,86 @@
+use super::recv::receive_data_bulk;
+use super::source_data::SourceDataItem;
+use anyhow::Result;
+use std::io::Read;
+use std::sync::mpsc::Receiver;
+
+/// A `Read` of the chunks received from a channel. The chunks are read in the order they are
+/// sent, and the channel being closed by all the senders is the EOF.
+pub struct ChannelReader {
+    rx: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    pub fn new(rx: Receiver<Vec<u8>>) -> Self {
+        ChannelReader {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Empty chunks are skipped, they would look like EOF otherwise
+        while self.pos >= self.chunk.len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                // `RecvError` only means all the senders are gone
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Same as `receive_data_bulk`, but the data is received from the channel, see `ChannelReader`.
+/// It returns after all the senders are dropped. A stream which is incomplete by then fails as
+/// it would with a truncated `Read`.
+pub fn receive_data_bulk_from_channel(rx: Receiver<Vec<u8>>) -> Result<Vec<SourceDataItem>> {
+    receive_data_bulk(ChannelReader::new(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PROTOCAL_VER;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_receive_data_bulk_from_channel() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        let (tx, rx) = channel();
+        let sender = std::thread::spawn(move || {
+            // Chunks not aligned with the sections
+            for chunk in buf.chunks(3) {
+                tx.send(chunk.to_vec()).unwrap();
+            }
+            tx.send(Vec::new()).unwrap();
+        });
+        let r = receive_data_bulk_from_channel(rx).unwrap();
+        sender.join().unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].mime_type, vec!["TEXT"]);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+
+        // Closed in the middle
+        let (tx, rx) = channel();
+        tx.send(buf[..20].to_vec()).unwrap();
+        drop(tx);
+        assert!(receive_data_bulk_from_channel(rx).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use super::recv::receive_data_bulk;
+use super::source_data::SourceDataItem;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Converts the received content of a mime-type, e.g. from WebP to PNG.
+pub trait ContentCodec {
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>>;
+    /// The mime-type of the decoded content, if it is different from the original one. It
+    /// replaces the original mime-type of the item.
+    fn output_mime_type(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// The codecs used by `receive_data_bulk_with_codecs`, keyed by mime-type.
+#[derive(Default)]
+pub struct CodecRegistry {
+    codecs: HashMap<String, Box<dyn ContentCodec>>,
+}
+
+impl CodecRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the codec for the mime-type, which is case-insensitive. The codec registered
+    /// before for the same mime-type is replaced.
+    pub fn register(&mut self, mime_type: &str, codec: Box<dyn ContentCodec>) {
+        self.codecs.insert(mime_type.to_ascii_lowercase(), codec);
+    }
+
+    pub fn get(&self, mime_type: &str) -> Option<&dyn ContentCodec> {
+        self.codecs
+            .get(&mime_type.to_ascii_lowercase())
+            .map(|c| c.as_ref())
+    }
+}
+
+/// Same as `receive_data_bulk`, and then decode the content of every item with the codec
+/// registered for its mime-types. Only the first mime-type with a codec is used for an item.
+pub fn receive_data_bulk_with_codecs(
+    reader: impl Read,
+    registry: &CodecRegistry,
+) -> Result<Vec<SourceDataItem>> {
+    let mut items = receive_data_bulk(reader)?;
+    for item in &mut items {
+        let Some((idx, codec)) = item
+            .mime_type
+            .iter()
+            .enumerate()
+            .find_map(|(i, t)| registry.get(t).map(|c| (i, c)))
+        else {
+            continue;
+        };
+        log::debug!("Decode the content of '{}'", item.mime_type[idx]);
+        let content = codec.decode(&item.content).with_context(|| {
+            format!("Failed to decode the content of '{}'", item.mime_type[idx])
+        })?;
+        item.content = content.into();
+        if let Some(mime_type) = codec.output_mime_type() {
+            item.mime_type[idx] = mime_type.to_string();
+        }
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PROTOCAL_VER;
+    use anyhow::bail;
+
+    struct UpperCaseCodec;
+
+    impl ContentCodec for UpperCaseCodec {
+        fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.to_ascii_uppercase())
+        }
+        fn output_mime_type(&self) -> Option<&str> {
+            Some("text/upper")
+        }
+    }
+
+    struct FailingCodec;
+
+    impl ContentCodec for FailingCodec {
+        fn decode(&self, _data: &[u8]) -> Result<Vec<u8>> {
+            bail!("Bad data")
+        }
+    }
+
+    #[test]
+    fn test_receive_data_bulk_with_codecs() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'M', 0, 0, 0, 5, b'L', b'o', b'w', b'e', b'r',
+            b'C', 0, 0, 0, 4, b'g', b'o', b'o', b'd',
+            b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L',
+            b'C', 0, 0, 0, 4, b'h', b't', b'm', b'l',
+            ];
+        let mut registry = CodecRegistry::new();
+        registry.register("lower", Box::new(UpperCaseCodec));
+        let r = receive_data_bulk_with_codecs(&buf[..], &registry).unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].mime_type, vec!["TEXT", "text/upper"]);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        // No codec for it
+        assert_eq!(r[1].mime_type, vec!["HTML"]);
+        assert_eq!(r[1].content.as_slice(), b"html");
+
+        registry.register("html", Box::new(FailingCodec));
+        let r = receive_data_bulk_with_codecs(&buf[..], &registry);
+        assert!(r.is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result, bail};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::io::{Read, Write};
+
+pub(super) fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish().context("Failed to compress content")
+}
+
+/// Decompress the zlib data, fails if the result would be larger than `max_size`.
+pub(super) fn decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut ret = Vec::new();
+    // Read one more byte than allowed to tell if the limit is exceeded
+    ZlibDecoder::new(data)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut ret)
+        .context("Failed to decompress content")?;
+    if ret.len() > max_size {
+        bail!(
+            "Decompressed size of the {} bytes content exceeds the limit {}",
+            data.len(),
+            max_size
+        );
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        ProtocolWriter, RecvConfig, receive_data_bulk, receive_data_bulk_with_config,
+    };
+
+    #[test]
+    fn test_decompress() {
+        let data = b"Some content to compress".repeat(10);
+        let compressed = compress(&data).unwrap();
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+        assert!(decompress(b"Not zlib", 1024).is_err());
+    }
+
+    #[test]
+    fn test_compressed_section_limit() {
+        // 16 MiB of zeros compresses into a few KiB
+        let data = vec![0u8; 16 * 1024 * 1024];
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("application/octet-stream").unwrap();
+        writer.write_compressed_content(&data).unwrap();
+        let buf = writer.into_inner();
+        assert!(buf.len() < 64 * 1024);
+        assert_eq!(buf[5 + 5 + 24], b'Z');
+
+        let config = RecvConfig {
+            max_section_bytes: 64 * 1024,
+            ..Default::default()
+        };
+        let r = receive_data_bulk_with_config(&buf[..], &config);
+        assert!(r.is_err());
+
+        let r = receive_data_bulk(&buf[..]).unwrap();
+        assert_eq!(r[0].content.len(), data.len());
+    }
+
+    #[test]
+    fn test_skip_incompressible() {
+        let data: Vec<u8> = (0..=255).collect::<Vec<_>>().repeat(16);
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("image/jpeg").unwrap();
+        writer.write_compressed_content(&data).unwrap();
+        let buf = writer.into_inner();
+        assert_eq!(buf[5 + 5 + 10], b'C');
+
+        let r = receive_data_bulk(&buf[..]).unwrap();
+        assert_eq!(r[0].content.as_slice(), data);
+    }
+}
@@ -0,0 +1,104 @@
+use super::recv::receive_data_bulk;
+use super::source_data::SourceDataItem;
+use anyhow::Result;
+use std::io::{Error, ErrorKind, Read};
+use std::time::Instant;
+
+/// Returned by `receive_data_bulk_with_deadline` when the whole stream couldn't be parsed before
+/// the deadline.
+#[derive(Debug)]
+pub struct DeadlineExceeded;
+
+impl std::fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Deadline exceeded while receiving data")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+struct DeadlineReader<R: Read> {
+    inner: R,
+    deadline: Instant,
+    exceeded: bool,
+}
+
+impl<R: Read> Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if Instant::now() >= self.deadline {
+            self.exceeded = true;
+            return Err(Error::new(ErrorKind::TimedOut, DeadlineExceeded));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Same as `receive_data_bulk`, but gives up with `DeadlineExceeded` if the parsing is not
+/// finished before `deadline`.
+/// The deadline is checked before every read, so it guards against a sender which keeps
+/// dripping bytes slowly. It cannot interrupt a single blocking read though, a per-read timeout
+/// on the underlying reader is still needed for that.
+pub fn receive_data_bulk_with_deadline(
+    reader: impl Read,
+    deadline: Instant,
+) -> Result<Vec<SourceDataItem>> {
+    let mut reader = DeadlineReader {
+        inner: reader,
+        deadline,
+        exceeded: false,
+    };
+    match receive_data_bulk(&mut reader) {
+        Err(e) if reader.exceeded => {
+            log::debug!("{e:#}");
+            Err(DeadlineExceeded.into())
+        }
+        r => r,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PROTOCAL_VER;
+    use std::time::Duration;
+
+    // Returns one byte for every read after sleeping a while
+    struct SlowReader<'a> {
+        data: &'a [u8],
+        delay: Duration,
+    }
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            std::thread::sleep(self.delay);
+            let n = self.data.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_receive_data_bulk_with_deadline() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+
+        // Finished in time
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let r = receive_data_bulk_with_deadline(&buf[..], deadline).unwrap();
+        assert_eq!(r.len(), 1);
+
+        // Slow reader
+        let reader = SlowReader {
+            data: &buf,
+            delay: Duration::from_millis(10),
+        };
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let r = receive_data_bulk_with_deadline(reader, deadline);
+        assert!(r.unwrap_err().downcast_ref::<DeadlineExceeded>().is_some());
+    }
+}
@@ -0,0 +1,91 @@
+use super::source_data::SourceDataItem;
+use termcolor::{Ansi, Color, ColorSpec, NoColor, WriteColor};
+
+const PREVIEW_CHARS: usize = 64;
+
+impl SourceDataItem {
+    /// Render the item as one line for terminals: the mime-types, the content size and a preview
+    /// of the content. The colors are omitted if `NO_COLOR` is set or the terminal is dumb.
+    pub fn to_ansi_colored_display(&self) -> String {
+        self.render_display(crate::format::colors_enabled())
+    }
+
+    fn render_display(&self, color: bool) -> String {
+        if color {
+            let mut out = Ansi::new(Vec::new());
+            self.write_display(&mut out);
+            String::from_utf8_lossy(&out.into_inner()).to_string()
+        } else {
+            let mut out = NoColor::new(Vec::new());
+            self.write_display(&mut out);
+            String::from_utf8_lossy(&out.into_inner()).to_string()
+        }
+    }
+
+    // Writing to a Vec never fails.
+    fn write_display(&self, out: &mut impl WriteColor) {
+        out.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Blue)))
+            .unwrap();
+        write!(out, "{}", self.mime_type.join(", ")).unwrap();
+        out.reset().unwrap();
+
+        out.set_color(ColorSpec::new().set_fg(Some(Color::Green)))
+            .unwrap();
+        write!(out, " [{} bytes] ", self.content.len()).unwrap();
+        out.reset().unwrap();
+
+        match std::str::from_utf8(&self.content) {
+            Ok(text) => {
+                let is_json = self
+                    .mime_type
+                    .iter()
+                    .any(|t| t.to_ascii_lowercase().contains("json"));
+                if is_json {
+                    out.set_color(
+                        ColorSpec::new()
+                            .set_fg(Some(Color::White))
+                            .set_intense(true),
+                    )
+                    .unwrap();
+                }
+                let mut preview: String = text
+                    .chars()
+                    .take(PREVIEW_CHARS)
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .collect();
+                if text.chars().nth(PREVIEW_CHARS).is_some() {
+                    preview.push_str("...");
+                }
+                write!(out, "{preview}").unwrap();
+                out.reset().unwrap();
+            }
+            Err(_) => write!(out, "<binary>").unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_display() {
+        let item = SourceDataItem::new(
+            vec!["application/json".to_string(), "TEXT".to_string()],
+            b"{\"a\":\n1}".to_vec(),
+        );
+        assert_eq!(
+            item.render_display(false),
+            "application/json, TEXT [8 bytes] {\"a\": 1}"
+        );
+        assert_eq!(
+            item.render_display(true),
+            "\x1b[0m\x1b[1m\x1b[34mapplication/json, TEXT\x1b[0m\
+            \x1b[0m\x1b[32m [8 bytes] \x1b[0m\
+            \x1b[0m\x1b[38;5;15m{\"a\": 1}\x1b[0m"
+        );
+
+        let item = SourceDataItem::new(vec!["image/png".to_string()], vec![0x89, 0x50, 0xff]);
+        assert_eq!(item.render_display(false), "image/png [3 bytes] <binary>");
+    }
+}
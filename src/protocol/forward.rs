@@ -0,0 +1,157 @@
+use super::recv::{
+    NamedSections, RecvConfig, check_item_count, default_alloc, read_header_with_count,
+    read_next_item,
+};
+use super::send::ProtocolWriter;
+use crate::clipboard::ClipboardSink;
+use anyhow::Result;
+use std::io::{Read, Write};
+
+/// Receive the items like `receive_data_bulk`, forward every one of them to `forward` as soon as
+/// it is parsed, then offer them all to `apply`, e.g. for a proxy which copies the data locally
+/// and passes it on to the next service.
+/// The items are re-encoded instead of copying the bytes as they are, so the forwarded stream
+/// only has complete items. The `K` (named) sections are accepted and forwarded with the item
+/// they come before, the ones after the last content are dropped. If the stream fails in the
+/// middle, the items before are forwarded as a valid stream, but nothing is applied and the error
+/// is returned. The items are applied after the stream ends since a sink like `BackendSink` may
+/// not return until the clipboard is taken over by others.
+/// The item count of a `PROTOCAL_VER_COUNTED` header is checked before applying the items, as
+/// a mismatch is another failure. The forwarded stream never has the count, so the items already
+/// forwarded are still valid in this case.
+pub fn receive_and_forward(
+    mut reader: impl Read,
+    apply: &mut impl ClipboardSink,
+    forward: impl Write,
+) -> Result<()> {
+    let config = RecvConfig {
+        named_sections: NamedSections::Collect,
+        ..Default::default()
+    };
+    let count = read_header_with_count(&mut reader)?;
+    let mut writer = ProtocolWriter::new(forward)?;
+    let mut items = vec![];
+    while let Some(item) =
+        read_next_item(&mut reader, &config, &mut default_alloc, !items.is_empty())?
+    {
+        writer.write_item_extended(&item)?;
+        writer.flush()?;
+        items.push(item);
+    }
+    log::debug!("Forwarded {} items", items.len());
+    check_item_count(count, items.len())?;
+    apply.offer(&items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{SourceDataItem, receive_data_bulk};
+
+    #[derive(Default)]
+    struct MockSink {
+        offered: Vec<Vec<SourceDataItem>>,
+    }
+
+    impl ClipboardSink for MockSink {
+        fn offer_with_priority(&mut self, items: &[(SourceDataItem, u16)]) -> Result<()> {
+            self.offered
+                .push(items.iter().map(|(item, _)| item.clone()).collect());
+            Ok(())
+        }
+    }
+
+    fn parts(items: &[SourceDataItem]) -> Vec<(Vec<String>, Vec<u8>)> {
+        items
+            .iter()
+            .map(|item| (item.mime_type.clone(), item.content.to_vec()))
+            .collect()
+    }
+
+    fn stream_data() -> Vec<u8> {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("text/plain").unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_heartbeat().unwrap();
+        writer.write_mime("image/png").unwrap();
+        writer.write_content(b"\x89PNG").unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn test_receive_and_forward() {
+        let buf = stream_data();
+        let mut sink = MockSink::default();
+        let mut forwarded = vec![];
+        receive_and_forward(&buf[..], &mut sink, &mut forwarded).unwrap();
+
+        let expected = receive_data_bulk(&buf[..]).unwrap();
+        assert_eq!(sink.offered.len(), 1);
+        assert_eq!(parts(&sink.offered[0]), parts(&expected));
+        // The forwarded stream parses back to the same items, without the heartbeat
+        let r = receive_data_bulk(&forwarded[..]).unwrap();
+        assert_eq!(parts(&r), parts(&expected));
+        assert!(forwarded.len() < buf.len());
+    }
+
+    #[test]
+    fn test_receive_and_forward_extensions() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_named("source", b"terminal").unwrap();
+        writer.write_named("app", b"shell").unwrap();
+        writer.write_mime("text/plain").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+        let mut sink = MockSink::default();
+        let mut forwarded = vec![];
+        receive_and_forward(&buf[..], &mut sink, &mut forwarded).unwrap();
+
+        assert_eq!(sink.offered[0][0].extensions["source"], b"terminal");
+        let r = crate::protocol::receive_data_bulk_extended(&forwarded[..]).unwrap();
+        assert_eq!(r[0].extensions.len(), 2);
+        assert_eq!(r[0].extensions["source"], b"terminal");
+        assert_eq!(r[0].extensions["app"], b"shell");
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+    }
+
+    #[test]
+    fn test_receive_and_forward_counted() {
+        let mut writer = ProtocolWriter::with_item_count(Vec::new(), 2).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_mime("image/png").unwrap();
+        writer.write_content(b"\x89PNG").unwrap();
+        let buf = writer.into_inner();
+        let mut sink = MockSink::default();
+        let mut forwarded = vec![];
+        receive_and_forward(&buf[..], &mut sink, &mut forwarded).unwrap();
+        let expected = receive_data_bulk(&buf[..]).unwrap();
+        assert_eq!(parts(&sink.offered[0]), parts(&expected));
+        let r = receive_data_bulk(&forwarded[..]).unwrap();
+        assert_eq!(parts(&r), parts(&expected));
+
+        // The count mismatch is not applied, the complete item is still forwarded
+        let mut sink = MockSink::default();
+        let mut forwarded = vec![];
+        let cut = buf.len() - 23;
+        let err = receive_and_forward(&buf[..cut], &mut sink, &mut forwarded).unwrap_err();
+        assert_eq!(err.to_string(), "Expected 2 items, but received 1");
+        assert!(sink.offered.is_empty());
+        let r = receive_data_bulk(&forwarded[..]).unwrap();
+        assert_eq!(r.len(), 1);
+    }
+
+    #[test]
+    fn test_receive_and_forward_error() {
+        let buf = stream_data();
+        let mut sink = MockSink::default();
+        let mut forwarded = vec![];
+        assert!(receive_and_forward(&buf[..buf.len() - 1], &mut sink, &mut forwarded).is_err());
+        assert!(sink.offered.is_empty());
+        // Only the complete item is forwarded
+        let r = receive_data_bulk(&forwarded[..]).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+    }
+}
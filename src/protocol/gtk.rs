@@ -0,0 +1,40 @@
+use super::source_data::SourceDataItem;
+use gtk::glib;
+
+impl SourceDataItem {
+    /// The content as `glib::Bytes` to pass to `gtk::SelectionData::set` for the target `mime`,
+    /// `None` if the item doesn't offer the mime-type. The content is copied, since `glib::Bytes`
+    /// can be shared across threads and the item's content can't.
+    pub fn to_gtk_selection_data(&self, mime: &str) -> Option<glib::Bytes> {
+        if !self.mime_type.iter().any(|m| m == mime) {
+            return None;
+        }
+        Some(glib::Bytes::from(self.content.as_slice()))
+    }
+}
+
+/// The item received as the `gtk::SelectionData`, with its data type as the only mime-type.
+/// `None` if the selection request failed, which GTK reports with a negative length.
+pub fn from_gtk_selection_data(data: &gtk::SelectionData) -> Option<SourceDataItem> {
+    if data.length() < 0 {
+        return None;
+    }
+    let mime_type = data.data_type().name().to_string();
+    Some(SourceDataItem::new(vec![mime_type], data.data()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_gtk_selection_data() {
+        let item = SourceDataItem::new(
+            vec!["text/plain".to_string(), "UTF8_STRING".to_string()],
+            b"GOOD".to_vec(),
+        );
+        let bytes = item.to_gtk_selection_data("UTF8_STRING").unwrap();
+        assert_eq!(&*bytes, b"GOOD");
+        assert!(item.to_gtk_selection_data("text/html").is_none());
+    }
+}
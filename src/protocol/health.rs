@@ -0,0 +1,120 @@
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// The health of a stream being received, see [`HealthMonitoredReader::health_check`].
+#[derive(Debug, PartialEq)]
+pub enum ReaderHealth {
+    /// Bytes have been received recently.
+    Healthy,
+    /// Nothing has been received for a while, but not long enough to give up.
+    Idle { since: Duration },
+    /// Nothing has been received for longer than the stall threshold. The sender most likely
+    /// stopped in the middle of the stream and the connection should be closed.
+    Stalled { since: Duration },
+}
+
+/// A reader wrapper which records the time of the last successful read, so a long-running
+/// receiver can detect the other side stopped sending in the middle of a stream.
+pub struct HealthMonitoredReader<R: Read> {
+    inner: R,
+    last_read: Instant,
+    idle_after: Duration,
+    stalled_after: Duration,
+    clock: Box<dyn Fn() -> Instant>,
+}
+
+impl<R: Read> HealthMonitoredReader<R> {
+    /// `idle_after` and `stalled_after` are measured from the last read which returned any
+    /// bytes, or from the creation of the reader if nothing has been read yet.
+    pub fn new(inner: R, idle_after: Duration, stalled_after: Duration) -> Self {
+        Self::with_clock(inner, idle_after, stalled_after, Instant::now)
+    }
+
+    /// Same as `new`, but the time is told by `clock` instead of `Instant::now`.
+    pub fn with_clock(
+        inner: R,
+        idle_after: Duration,
+        stalled_after: Duration,
+        clock: impl Fn() -> Instant + 'static,
+    ) -> Self {
+        HealthMonitoredReader {
+            inner,
+            last_read: clock(),
+            idle_after,
+            stalled_after,
+            clock: Box::new(clock),
+        }
+    }
+
+    pub fn health_check(&self) -> ReaderHealth {
+        let since = (self.clock)().saturating_duration_since(self.last_read);
+        if since >= self.stalled_after {
+            ReaderHealth::Stalled { since }
+        } else if since >= self.idle_after {
+            ReaderHealth::Idle { since }
+        } else {
+            ReaderHealth::Healthy
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for HealthMonitoredReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.last_read = (self.clock)();
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PROTOCAL_VER, receive_data_bulk};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_health_check() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        let now = Rc::new(Cell::new(Instant::now()));
+        let clock = now.clone();
+        let mut reader = HealthMonitoredReader::with_clock(
+            &buf[..],
+            Duration::from_millis(50),
+            Duration::from_millis(500),
+            move || clock.get(),
+        );
+        assert_eq!(reader.health_check(), ReaderHealth::Healthy);
+
+        now.set(now.get() + Duration::from_millis(60));
+        assert_eq!(
+            reader.health_check(),
+            ReaderHealth::Idle {
+                since: Duration::from_millis(60)
+            }
+        );
+        now.set(now.get() + Duration::from_millis(500));
+        assert_eq!(
+            reader.health_check(),
+            ReaderHealth::Stalled {
+                since: Duration::from_millis(560)
+            }
+        );
+
+        // Reading resets the timer
+        let r = receive_data_bulk(&mut reader).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(reader.health_check(), ReaderHealth::Healthy);
+    }
+}
@@ -0,0 +1,83 @@
+use super::source_data::SourceDataItem;
+
+impl SourceDataItem {
+    /// Render the item as a Mermaid flowchart: a node for every mime-type, all pointing to the
+    /// content node labeled with the size.
+    pub fn to_mermaid_diagram(&self) -> String {
+        let mut out = String::from("graph LR\n");
+        self.write_mermaid_nodes(&mut out, "", "    ");
+        out
+    }
+
+    fn write_mermaid_nodes(&self, out: &mut String, id_prefix: &str, indent: &str) {
+        let content_id = format!("{id_prefix}content");
+        out.push_str(&format!(
+            "{indent}{content_id}[\"{} bytes\"]\n",
+            self.content.len()
+        ));
+        for (i, mime_type) in self.mime_type.iter().enumerate() {
+            out.push_str(&format!(
+                "{indent}{id_prefix}mime{i}[\"{}\"] --> {content_id}\n",
+                escape_label(mime_type)
+            ));
+        }
+    }
+}
+
+/// Render the items as a Mermaid flowchart with a subgraph for every item, see
+/// `SourceDataItem::to_mermaid_diagram`.
+pub fn items_to_mermaid(items: &[SourceDataItem]) -> String {
+    let mut out = String::from("graph LR\n");
+    for (i, item) in items.iter().enumerate() {
+        out.push_str(&format!("    subgraph item{i}[\"Item {i}\"]\n"));
+        item.write_mermaid_nodes(&mut out, &format!("item{i}_"), "        ");
+        out.push_str("    end\n");
+    }
+    out
+}
+
+// Quotes would end the label, Mermaid takes the HTML entity code instead
+fn escape_label(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_mermaid_diagram() {
+        let item = SourceDataItem::new(
+            vec!["text/plain".to_string(), "TEXT".to_string()],
+            b"GOOD".to_vec(),
+        );
+        assert_eq!(
+            item.to_mermaid_diagram(),
+            "graph LR\n\
+             \x20   content[\"4 bytes\"]\n\
+             \x20   mime0[\"text/plain\"] --> content\n\
+             \x20   mime1[\"TEXT\"] --> content\n"
+        );
+    }
+
+    #[test]
+    fn test_items_to_mermaid() {
+        let items = [
+            SourceDataItem::new(vec!["TEXT".to_string()], b"GOOD".to_vec()),
+            SourceDataItem::new(vec!["text/x-\"quoted\"".to_string()], vec![]),
+        ];
+        assert_eq!(
+            items_to_mermaid(&items),
+            "graph LR\n\
+             \x20   subgraph item0[\"Item 0\"]\n\
+             \x20       item0_content[\"4 bytes\"]\n\
+             \x20       item0_mime0[\"TEXT\"] --> item0_content\n\
+             \x20   end\n\
+             \x20   subgraph item1[\"Item 1\"]\n\
+             \x20       item1_content[\"0 bytes\"]\n\
+             \x20       item1_mime0[\"text/x-#quot;quoted#quot;\"] --> item1_content\n\
+             \x20   end\n"
+        );
+        assert_eq!(items_to_mermaid(&[]), "graph LR\n");
+    }
+}
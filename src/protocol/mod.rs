@@ -1,8 +1,126 @@
+#[cfg(feature = "bytes")]
+mod bytes;
+mod capabilities;
+mod channel;
+mod codec;
+#[cfg(feature = "compression")]
+mod compress;
+mod deadline;
+#[cfg(feature = "terminal-ui")]
+mod display;
+mod forward;
+#[cfg(feature = "gtk")]
+mod gtk;
+mod health;
+#[cfg(feature = "debug-viz")]
+mod mermaid;
+mod oom;
+mod pool;
+#[cfg(feature = "portal")]
+mod portal;
+mod position;
+mod profile;
+mod push;
+#[cfg(feature = "qt")]
+mod qt;
+mod recovery;
 mod recv;
+mod request;
+#[cfg(feature = "rest-api")]
+mod rest;
+mod resume;
+mod send;
 mod source_data;
+mod stream;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(feature = "windows")]
+mod windows;
 
+#[cfg(feature = "bytes")]
+pub use bytes::{
+    receive_data_bulk_from_bytes_mut_stream, receive_data_bulk_from_bytes_mut_stream_with_config,
+};
+pub use capabilities::{Capabilities, read_capabilities, receive_data_bulk_negotiated};
+pub use channel::{ChannelReader, receive_data_bulk_from_channel};
+pub use codec::{CodecRegistry, ContentCodec, receive_data_bulk_with_codecs};
+pub use deadline::DeadlineExceeded;
+pub use deadline::receive_data_bulk_with_deadline;
+pub use forward::receive_and_forward;
+#[cfg(feature = "gtk")]
+pub use gtk::from_gtk_selection_data;
+pub use health::HealthMonitoredReader;
+pub use health::ReaderHealth;
+#[cfg(feature = "debug-viz")]
+pub use mermaid::items_to_mermaid;
+pub use oom::OomSafeResult;
+pub use oom::receive_data_bulk_oom_safe;
+pub use pool::{
+    ItemPool, PooledItem, receive_data_bulk_into_pool, receive_data_bulk_into_pool_with_config,
+};
+#[cfg(feature = "portal")]
+pub use portal::{item_from_portal_variant, item_to_portal_variant};
+pub use position::FailedAtByte;
+pub use position::receive_data_bulk_with_position;
+pub use profile::{
+    ItemProfile, ParseProfile, receive_data_bulk_profiled, receive_data_bulk_profiled_with_config,
+};
+pub use push::{Parsed, PushParser};
+#[cfg(feature = "qt")]
+pub use qt::{mime_type_from_qt_format, qt_decode, qt_encode, qt_format_name};
+pub use recovery::{
+    ErrorAction, receive_data_bulk_with_error_handler,
+    receive_data_bulk_with_error_handler_with_config,
+};
+pub use recv::ByteOrder;
+pub use recv::DeniedByte;
+pub use recv::MAX_MIME_TYPE_LEN;
+pub use recv::MimeTypeTooLong;
+pub use recv::NamedSections;
+pub use recv::OutOfMemory;
 #[allow(unused_imports)]
 pub use recv::PROTOCAL_VER;
+pub use recv::PROTOCAL_VER_COUNTED;
+pub use recv::RecvConfig;
+pub use recv::SectionTooLarge;
+pub use recv::TrailingData;
 pub use recv::receive_data_bulk;
+pub use recv::receive_data_bulk_extended;
+#[cfg(unix)]
+pub use recv::receive_data_bulk_from_socket_fd;
+pub use recv::receive_data_bulk_le;
+pub use recv::receive_data_bulk_lenient;
+pub use recv::receive_data_bulk_raw;
+pub use recv::receive_data_bulk_strict;
+pub use recv::receive_data_bulk_with_alloc;
+pub use recv::receive_data_bulk_with_config;
+pub use recv::receive_data_bulk_with_magic;
+pub use recv::receive_data_bulk_without_header;
 pub use recv::receive_data_oneshot;
+pub use recv::receive_data_oneshot_detect_mime;
+pub use request::{COMMAND_KEY, Request, receive_request};
+#[cfg(feature = "rest-api")]
+pub use rest::find_by_richclip_url;
+pub use resume::{
+    ResumeState, receive_data_bulk_resumable, receive_data_bulk_resumable_with_config,
+};
+pub use send::ProtocolWriter;
+pub use send::encode_to_writer_without_header;
+pub use source_data::GlobPattern;
+pub use source_data::NullByteInMimeType;
 pub use source_data::SourceData;
+pub use source_data::SourceDataItem;
+pub use source_data::check_utf8_text;
+pub use source_data::common_types;
+pub use source_data::drop_oversized;
+pub use source_data::fingerprint_content;
+pub use source_data::fingerprint_items;
+pub use source_data::missing_types;
+pub use source_data::non_utf8_text_types;
+pub use source_data::parse_fingerprint;
+pub use source_data::text_edit_distance;
+pub use stream::{BoundedItemStream, ItemStillHeld, ItemStream, receive_data_stream};
+#[cfg(feature = "tracing")]
+pub use trace::receive_data_bulk_in_span;
+#[cfg(feature = "windows")]
+pub use windows::{CF_HDROP, CF_HTML, CF_TEXT, CF_UNICODETEXT};
@@ -0,0 +1,108 @@
+use super::recv::{
+    ContentAlloc, OutOfMemory, RecvConfig, check_item_count, default_alloc, read_header_with_count,
+    read_sections,
+};
+use super::source_data::SourceDataItem;
+use anyhow::Result;
+use std::io::Read;
+
+/// See `receive_data_bulk_oom_safe`.
+pub struct OomSafeResult {
+    pub items: Vec<SourceDataItem>,
+    /// The index of the item whose content failed to be allocated, `None` if all the items are
+    /// received.
+    pub oom_at_item: Option<usize>,
+}
+
+/// Same as `receive_data_bulk`, but stops parsing when the buffer of a content cannot be
+/// allocated, and returns the items received before it instead of an error.
+/// Other errors are still returned as is.
+pub fn receive_data_bulk_oom_safe(reader: impl Read) -> Result<OomSafeResult> {
+    receive_oom_safe(reader, &mut default_alloc)
+}
+
+fn receive_oom_safe(mut reader: impl Read, alloc: &mut ContentAlloc) -> Result<OomSafeResult> {
+    let count = read_header_with_count(&mut reader)?;
+    let mut items = Vec::new();
+    match read_sections(&mut reader, &RecvConfig::default(), alloc, &mut items) {
+        Ok(()) => {
+            check_item_count(count, items.len())?;
+            Ok(OomSafeResult {
+                items,
+                oom_at_item: None,
+            })
+        }
+        Err(e) if e.downcast_ref::<OutOfMemory>().is_some() => {
+            log::warn!("{e}, returning {} items received", items.len());
+            let oom_at_item = Some(items.len());
+            Ok(OomSafeResult { items, oom_at_item })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PROTOCAL_VER, ProtocolWriter};
+
+    #[test]
+    fn test_receive_data_bulk_oom_safe() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L',
+            b'C', 0, 0, 0, 5, b'L', b'A', b'R', b'G', b'E',
+            ];
+        let r = receive_data_bulk_oom_safe(&buf[..]).unwrap();
+        assert_eq!(r.items.len(), 2);
+        assert!(r.oom_at_item.is_none());
+
+        // Pretend there is no memory for more than 4 bytes
+        let mut alloc = |size| {
+            if size > 4 {
+                Err(OutOfMemory { size }.into())
+            } else {
+                default_alloc(size)
+            }
+        };
+        let r = receive_oom_safe(&buf[..], &mut alloc).unwrap();
+        assert_eq!(r.items.len(), 1);
+        assert_eq!(r.items[0].content.as_slice(), b"GOOD");
+        assert_eq!(r.oom_at_item, Some(1));
+
+        // Other errors are still errors
+        let r = receive_data_bulk_oom_safe(&buf[..buf.len() - 1]);
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_receive_data_bulk_oom_safe_counted() {
+        let mut writer = ProtocolWriter::with_item_count(Vec::new(), 2).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_mime("HTML").unwrap();
+        writer.write_content(b"LARGE").unwrap();
+        let buf = writer.into_inner();
+        let r = receive_data_bulk_oom_safe(&buf[..]).unwrap();
+        assert_eq!(r.items.len(), 2);
+
+        // The items before the failed allocation are returned without checking the count
+        let mut alloc = |size| {
+            if size > 4 {
+                Err(OutOfMemory { size }.into())
+            } else {
+                default_alloc(size)
+            }
+        };
+        let r = receive_oom_safe(&buf[..], &mut alloc).unwrap();
+        assert_eq!(r.oom_at_item, Some(1));
+
+        let Err(err) = receive_data_bulk_oom_safe(&buf[..buf.len() - 19]) else {
+            panic!("The count mismatch is not returned");
+        };
+        assert_eq!(err.to_string(), "Expected 2 items, but received 1");
+    }
+}
@@ -0,0 +1,323 @@
+use super::recv::{
+    NamedSections, OutOfMemory, RecvConfig, check_denied_bytes, check_item_count, decompress,
+    default_alloc, parse_named_section, read_header_with_count, read_section_data,
+    read_section_size, skip_section, trailing_data_error,
+};
+use super::source_data::{SourceDataItem, has_text_mime_type};
+use anyhow::{Context, Result, bail};
+use std::io::Read;
+use std::ops::Range;
+
+/// A buffer which stores the mime-types and the contents of the received items, so receiving
+/// the same kind of data again and again only allocates when the buffer needs to grow.
+/// Call `clear` before reusing it, the capacity is kept.
+#[derive(Default)]
+pub struct ItemPool {
+    bytes: Vec<u8>,
+    mime_types: Vec<Range<usize>>,
+}
+
+/// A handle of an item stored in an `ItemPool`. It is only valid for the pool it comes from, and
+/// until the pool is cleared.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PooledItem {
+    mime_types: Range<usize>,
+    content: Range<usize>,
+}
+
+impl ItemPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserve `bytes` bytes for the mime-types and the contents in advance.
+    pub fn with_capacity(bytes: usize) -> Self {
+        ItemPool {
+            bytes: Vec::with_capacity(bytes),
+            mime_types: Vec::new(),
+        }
+    }
+
+    /// Remove all the items. All the `PooledItem`s from this pool become invalid.
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.mime_types.clear();
+    }
+
+    /// The total size of the mime-types and the contents stored.
+    pub fn len_bytes(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Append `size` bytes read from the reader, and return where they are stored.
+    fn read_bytes(
+        &mut self,
+        reader: &mut impl Read,
+        size: usize,
+        config: &RecvConfig,
+    ) -> Result<Range<usize>> {
+        let start = self.bytes.len();
+        self.bytes
+            .try_reserve(size)
+            .map_err(|_| OutOfMemory { size })?;
+        self.bytes.resize(start + size, 0);
+        if let Err(e) = read_section_data(reader, &mut self.bytes[start..], config) {
+            self.bytes.truncate(start);
+            return Err(e);
+        }
+        Ok(start..start + size)
+    }
+}
+
+impl PooledItem {
+    pub fn content<'a>(&self, pool: &'a ItemPool) -> &'a [u8] {
+        &pool.bytes[self.content.clone()]
+    }
+
+    pub fn mime_types<'a>(&self, pool: &'a ItemPool) -> impl Iterator<Item = &'a str> + 'a {
+        pool.mime_types[self.mime_types.clone()]
+            .iter()
+            // The mime-types have been validated as UTF-8 when they were received
+            .map(|r| std::str::from_utf8(&pool.bytes[r.clone()]).unwrap())
+    }
+
+    /// Copy the item out of the pool.
+    pub fn to_source_data_item(&self, pool: &ItemPool) -> SourceDataItem {
+        SourceDataItem::new(
+            self.mime_types(pool).map(str::to_string).collect(),
+            self.content(pool).to_vec(),
+        )
+    }
+}
+
+/// Same as `receive_data_bulk`, but the mime-types and the contents are stored in the `pool`
+/// instead of being allocated for every item.
+/// Handles of the received items are returned, use them with the same `pool` to access the data.
+pub fn receive_data_bulk_into_pool(
+    reader: impl Read,
+    pool: &mut ItemPool,
+) -> Result<Vec<PooledItem>> {
+    receive_data_bulk_into_pool_with_config(reader, pool, &RecvConfig::default())
+}
+
+/// Same as `receive_data_bulk_into_pool`, with the given config. The pool doesn't store the `K`
+/// (named) sections, they are skipped with `NamedSections::Collect` as well.
+pub fn receive_data_bulk_into_pool_with_config(
+    mut reader: impl Read,
+    pool: &mut ItemPool,
+    config: &RecvConfig,
+) -> Result<Vec<PooledItem>> {
+    let count = read_header_with_count(&mut reader)?;
+    let mut ret = Vec::new();
+    let mut flag = [0u8; 1];
+    let mut mime_types_start = pool.mime_types.len();
+    // If no section of the item is read yet
+    let mut first = true;
+    loop {
+        let r = reader.read(&mut flag).context("Failed to read flag")?;
+        // EOF
+        if r == 0 {
+            break;
+        }
+        match read_section(&mut reader, flag[0], config, pool, mime_types_start) {
+            Ok(Some(item)) => {
+                ret.push(item);
+                mime_types_start = pool.mime_types.len();
+                first = true;
+            }
+            Ok(None) => first = false,
+            Err(e) => {
+                let item_before = first && !ret.is_empty();
+                return Err(trailing_data_error(e, flag[0], item_before, config));
+            }
+        }
+    }
+    check_item_count(count, ret.len())?;
+    Ok(ret)
+}
+
+// Read the section of the flag into the pool, the item is returned if it is complete.
+fn read_section(
+    reader: &mut impl Read,
+    flag: u8,
+    config: &RecvConfig,
+    pool: &mut ItemPool,
+    mime_types_start: usize,
+) -> Result<Option<PooledItem>> {
+    match flag {
+        b'M' => {
+            let size = read_section_size(reader, flag, config)?;
+            let range = pool
+                .read_bytes(reader, size, config)
+                .context("Failed to read mime type")?;
+            if let Err(e) = std::str::from_utf8(&pool.bytes[range.clone()]) {
+                pool.bytes.truncate(range.start);
+                return Err(e).context("Failed to parse mime type string");
+            }
+            pool.mime_types.push(range);
+        }
+        b'H' | b'N' => {
+            let size = read_section_size(reader, flag, config)?;
+            skip_section(reader, size)?;
+            log::debug!("Skipped section '{}' of {} bytes", flag as char, size);
+        }
+        b'C' | b'Z' => {
+            if pool.mime_types.len() == mime_types_start {
+                bail!("Failed to read content with empty mime type");
+            }
+            if flag == b'Z'
+                && let Some(agreed) = &config.capabilities
+                && !agreed.compression
+            {
+                bail!("Failed to read compressed content, compression is not negotiated");
+            }
+            let size = read_section_size(reader, flag, config)?;
+            let mut content = pool.read_bytes(reader, size, config)?;
+            if flag == b'Z' {
+                let decompressed =
+                    decompress(&pool.bytes[content.clone()], config.max_section_bytes);
+                pool.bytes.truncate(content.start);
+                pool.bytes.extend_from_slice(&decompressed?);
+                content = content.start..pool.bytes.len();
+            }
+            let item = PooledItem {
+                mime_types: mime_types_start..pool.mime_types.len(),
+                content,
+            };
+            let is_text = has_text_mime_type(item.mime_types(pool));
+            if let Err(e) =
+                check_denied_bytes(is_text, item.content(pool), &config.denied_text_bytes)
+            {
+                pool.bytes.truncate(item.content.start);
+                return Err(e);
+            }
+            return Ok(Some(item));
+        }
+        b'K' if config.named_sections != NamedSections::Reject => {
+            let size = read_section_size(reader, flag, config)?;
+            let mut data = default_alloc(size)?;
+            read_section_data(reader, &mut data, config)?;
+            let (key, _) = parse_named_section(&data, config.byte_order)?;
+            log::debug!("Skipped named section '{key}'");
+        }
+        _ => {
+            bail!("Failed to parse flag {}", flag);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PROTOCAL_VER, PROTOCAL_VER_COUNTED};
+
+    #[test]
+    fn test_receive_data_bulk_into_pool() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'M', 0, 0, 0, 6, b'S', b'T', b'R', b'I', b'N', b'G',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'H', 0, 0, 0, 0,
+            b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L',
+            b'C', 0, 0, 0, 0,
+            ];
+        let mut pool = ItemPool::with_capacity(64);
+        let items = receive_data_bulk_into_pool(&buf[..], &mut pool).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0].mime_types(&pool).collect::<Vec<_>>(),
+            ["TEXT", "STRING"]
+        );
+        assert_eq!(items[0].content(&pool), b"GOOD");
+        assert_eq!(items[1].mime_types(&pool).collect::<Vec<_>>(), ["HTML"]);
+        assert_eq!(items[1].content(&pool), b"");
+        assert_eq!(pool.len_bytes(), 18);
+
+        let item = items[0].to_source_data_item(&pool);
+        assert_eq!(item.mime_type, vec!["TEXT", "STRING"]);
+        assert_eq!(item.content.as_slice(), b"GOOD");
+
+        // Receiving again appends to the pool, the earlier handles are still valid
+        let more = receive_data_bulk_into_pool(&buf[..], &mut pool).unwrap();
+        assert_ne!(items[0], more[0]);
+        assert_eq!(items[0].content(&pool), b"GOOD");
+        assert_eq!(more[0].content(&pool), b"GOOD");
+
+        // The capacity is kept after clearing
+        let capacity = pool.bytes.capacity();
+        pool.clear();
+        assert_eq!(pool.len_bytes(), 0);
+        receive_data_bulk_into_pool(&buf[..], &mut pool).unwrap();
+        assert_eq!(pool.bytes.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_receive_data_bulk_into_pool_errors() {
+        let mut pool = ItemPool::new();
+        // Content without mime-type
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        assert!(receive_data_bulk_into_pool(&buf[..], &mut pool).is_err());
+
+        // Truncated content doesn't leave garbage in the pool
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 8, b'G', b'O', b'O', b'D',
+            ];
+        pool.clear();
+        assert!(receive_data_bulk_into_pool(&buf[..], &mut pool).is_err());
+        assert_eq!(pool.len_bytes(), 4);
+    }
+
+    #[test]
+    fn test_receive_data_bulk_into_pool_with_config() {
+        let mut pool = ItemPool::new();
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER_COUNTED, 0, 0, 0, 1,
+            b'K', 0, 0, 0, 9, 0, 0, 0, 1, b'k', b'v', b'a', b'l', b'u',
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        // Named sections are rejected by default
+        assert!(receive_data_bulk_into_pool(&buf[..], &mut pool).is_err());
+        let config = RecvConfig {
+            named_sections: NamedSections::Skip,
+            ..Default::default()
+        };
+        pool.clear();
+        let items = receive_data_bulk_into_pool_with_config(&buf[..], &mut pool, &config).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].content(&pool), b"GOOD");
+
+        // The item count of the header is checked
+        let mut more = buf.to_vec();
+        more.extend_from_slice(&buf[9..]);
+        assert!(receive_data_bulk_into_pool_with_config(&more[..], &mut pool, &config).is_err());
+
+        // The limits are checked before anything is stored
+        let config = RecvConfig {
+            named_sections: NamedSections::Skip,
+            max_section_bytes: 3,
+            ..Default::default()
+        };
+        pool.clear();
+        let e = receive_data_bulk_into_pool_with_config(&buf[..], &mut pool, &config).unwrap_err();
+        assert_eq!(e.to_string(), "Content size 9 exceeds the limit 3");
+        assert_eq!(pool.len_bytes(), 0);
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'H', 0xff, 0xff, 0xff, 0xff,
+            ];
+        assert!(receive_data_bulk_into_pool_with_config(&buf[..], &mut pool, &config).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use super::source_data::SourceDataItem;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use zvariant::{OwnedValue, Value};
+
+const MIME_TYPES_KEY: &str = "mime_types";
+const CONTENT_KEY: &str = "content";
+
+/// Convert the item to the `a{sv}` dictionary used by `org.freedesktop.portal.Clipboard`.
+/// The mime-types are stored as `as` under `mime_types`, the same key used by the portal's
+/// `SetSelection` options. The content is stored as `ay` under `content`, the portal itself
+/// transfers the content through a file descriptor, see `receive_data_bulk_from_socket_fd`.
+pub fn item_to_portal_variant(item: &SourceDataItem) -> Value<'static> {
+    let mut dict = HashMap::<&str, Value>::new();
+    dict.insert(MIME_TYPES_KEY, Value::from(item.mime_type.clone()));
+    dict.insert(CONTENT_KEY, Value::from(item.content.to_vec()));
+    Value::from(dict)
+}
+
+/// The inverse of `item_to_portal_variant`. A missing `content` is regarded as empty content.
+pub fn item_from_portal_variant(val: Value) -> Result<SourceDataItem> {
+    let mut dict = HashMap::<String, OwnedValue>::try_from(val)
+        .context("Failed to parse the portal variant as a{sv}")?;
+    let mime_type = dict
+        .remove(MIME_TYPES_KEY)
+        .context("No mime_types in the portal variant")?;
+    let mime_type =
+        Vec::<String>::try_from(mime_type).context("Failed to parse mime_types as 'as'")?;
+    let content = match dict.remove(CONTENT_KEY) {
+        Some(content) => Vec::<u8>::try_from(content).context("Failed to parse content as 'ay'")?,
+        None => vec![],
+    };
+    Ok(SourceDataItem::new(mime_type, content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_portal_variant() {
+        let item = SourceDataItem::new(
+            vec!["text/plain".to_string(), "TEXT".to_string()],
+            b"GOOD".to_vec(),
+        );
+        let val = item_to_portal_variant(&item);
+        assert_eq!(val.value_signature().to_string(), "a{sv}");
+
+        let r = item_from_portal_variant(val).unwrap();
+        assert_eq!(r.mime_type, item.mime_type);
+        assert_eq!(r.content, item.content);
+
+        // Not a dictionary
+        assert!(item_from_portal_variant(Value::from("text/plain")).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use super::recv::receive_data_bulk;
+use super::source_data::SourceDataItem;
+use anyhow::Result;
+use std::io::Read;
+use std::string::FromUtf8Error;
+
+/// Attached as the context of the error returned by `receive_data_bulk_with_position`.
+#[derive(Debug)]
+pub struct FailedAtByte {
+    /// Offset in the stream of the corrupt byte, e.g. the first byte of an invalid UTF-8
+    /// sequence. If the parsing doesn't tell which byte it is, e.g. for an unknown flag or the
+    /// end of a truncated stream, the offset of the last read, where the data which failed to be
+    /// parsed starts.
+    pub failed_at_byte: u64,
+}
+
+impl std::fmt::Display for FailedAtByte {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to parse the data at byte {}",
+            self.failed_at_byte
+        )
+    }
+}
+
+struct CountingReader<R: Read> {
+    inner: R,
+    offset: u64,
+    // Offset before the last read, which is where the data failed to be parsed starts.
+    last_read_at: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.last_read_at = self.offset;
+        let n = self.inner.read(buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// Same as `receive_data_bulk`, but the error has a `FailedAtByte` context which tells where the
+/// stream is corrupted or truncated. Use `anyhow::Error::downcast_ref` to retrieve it.
+pub fn receive_data_bulk_with_position(reader: impl Read) -> Result<Vec<SourceDataItem>> {
+    let mut reader = CountingReader {
+        inner: reader,
+        offset: 0,
+        last_read_at: 0,
+    };
+    receive_data_bulk(&mut reader).map_err(|e| {
+        let failed_at_byte = match e.chain().find_map(|c| c.downcast_ref::<FromUtf8Error>()) {
+            // The invalid data is the last one read
+            Some(utf8) => {
+                reader.offset - utf8.as_bytes().len() as u64
+                    + utf8.utf8_error().valid_up_to() as u64
+            }
+            None => reader.last_read_at,
+        };
+        e.context(FailedAtByte { failed_at_byte })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PROTOCAL_VER;
+
+    // Returns a byte at a time
+    struct SlowReader<'a>(&'a [u8]);
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(1);
+            self.0.read(&mut buf[..n])
+        }
+    }
+
+    fn failed_at(buf: &[u8]) -> u64 {
+        let err = receive_data_bulk_with_position(buf).unwrap_err();
+        err.downcast_ref::<FailedAtByte>().unwrap().failed_at_byte
+    }
+
+    #[test]
+    fn test_receive_data_bulk_with_position() {
+        // Wrong flag
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'X', 0, 0, 0, 4, b'B', b'A', b'D', b'!',
+            ];
+        assert_eq!(failed_at(&buf), 23);
+
+        // Invalid UTF-8 in mime-type
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', 0xff, b'X', b'T',
+            ];
+        assert_eq!(failed_at(&buf), 11);
+        // Read in pieces
+        let mut slow = SlowReader(&buf);
+        let err = receive_data_bulk_with_position(&mut slow).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FailedAtByte>().unwrap().failed_at_byte,
+            11
+        );
+
+        // Truncated, the data runs out at the end of the stream
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O',
+            ];
+        assert_eq!(failed_at(&buf), 21);
+
+        // Correct data
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        assert_eq!(receive_data_bulk_with_position(&buf[..]).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,173 @@
+use super::recv::{
+    RecvConfig, check_item_count, default_alloc, read_header_with_count, read_section,
+    trailing_data_error,
+};
+use super::source_data::SourceDataItem;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// Time spent by `receive_data_bulk_profiled`. Waiting for the reader is counted as well, so a
+/// slow sender shows up as slow reading.
+#[derive(Debug, Default)]
+pub struct ParseProfile {
+    pub time_reading_header: Duration,
+    pub time_reading_mime_types: Duration,
+    /// Including the decompression of compressed contents.
+    pub time_reading_content: Duration,
+    pub items: Vec<ItemProfile>,
+}
+
+#[derive(Debug)]
+pub struct ItemProfile {
+    pub mime_type_count: usize,
+    pub content_size: usize,
+    /// From the start of the first mime-type section to the end of the content section.
+    pub parse_duration: Duration,
+}
+
+/// Same as `receive_data_bulk`, and measure the time spent in each phase of the parsing.
+pub fn receive_data_bulk_profiled(
+    reader: impl Read,
+) -> Result<(Vec<SourceDataItem>, ParseProfile)> {
+    receive_data_bulk_profiled_with_config(reader, &RecvConfig::default())
+}
+
+/// Same as `receive_data_bulk_profiled`, with the given config.
+pub fn receive_data_bulk_profiled_with_config(
+    mut reader: impl Read,
+    config: &RecvConfig,
+) -> Result<(Vec<SourceDataItem>, ParseProfile)> {
+    let mut profile = ParseProfile::default();
+    let start = Instant::now();
+    let count = read_header_with_count(&mut reader)?;
+    profile.time_reading_header = start.elapsed();
+
+    let mut ret = Vec::new();
+    let mut flag = [0u8; 1];
+    let mut type_list = Vec::new();
+    let mut extensions = HashMap::new();
+    let mut item_start = None;
+    // If no section of the item is read yet
+    let mut first = true;
+    loop {
+        let r = reader.read(&mut flag).context("Failed to read flag")?;
+        // EOF
+        if r == 0 {
+            break;
+        }
+        let section_start = Instant::now();
+        let item = read_section(
+            &mut reader,
+            flag[0],
+            config,
+            &mut default_alloc,
+            &mut type_list,
+            &mut extensions,
+        )
+        .map_err(|e| trailing_data_error(e, flag[0], first && !ret.is_empty(), config))?;
+        first = false;
+        match item {
+            Some(item) => {
+                profile.time_reading_content += section_start.elapsed();
+                profile.items.push(ItemProfile {
+                    mime_type_count: item.mime_type.len(),
+                    content_size: item.content.len(),
+                    parse_duration: item_start.take().unwrap_or(section_start).elapsed(),
+                });
+                ret.push(item);
+                first = true;
+            }
+            None if flag[0] == b'M' => {
+                item_start.get_or_insert(section_start);
+                profile.time_reading_mime_types += section_start.elapsed();
+            }
+            None => {}
+        }
+    }
+    check_item_count(count, ret.len())?;
+    Ok((ret, profile))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{NamedSections, PROTOCAL_VER, PROTOCAL_VER_COUNTED};
+
+    // Delays every read, so the measured durations are not zero
+    struct SlowReader<'a>(&'a [u8]);
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(1));
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_receive_data_bulk_profiled() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'M', 0, 0, 0, 6, b'S', b'T', b'R', b'I', b'N', b'G',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'H', 0, 0, 0, 0,
+            b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L',
+            b'C', 0, 0, 0, 0,
+            ];
+        let (items, profile) = receive_data_bulk_profiled(SlowReader(&buf)).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(profile.items.len(), 2);
+        assert_eq!(profile.items[0].mime_type_count, 2);
+        assert_eq!(profile.items[0].content_size, 4);
+        assert_eq!(profile.items[1].mime_type_count, 1);
+        assert_eq!(profile.items[1].content_size, 0);
+
+        assert!(profile.time_reading_header > Duration::ZERO);
+        assert!(profile.time_reading_mime_types > Duration::ZERO);
+        assert!(profile.time_reading_content > Duration::ZERO);
+        for item in &profile.items {
+            assert!(item.parse_duration > Duration::ZERO);
+        }
+        // Item durations include reading the mime-types
+        let total: Duration = profile.items.iter().map(|i| i.parse_duration).sum();
+        assert!(total >= profile.time_reading_content);
+    }
+
+    #[test]
+    fn test_receive_data_bulk_profiled_with_config() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER_COUNTED, 0, 0, 0, 1,
+            b'K', 0, 0, 0, 9, 0, 0, 0, 1, b'k', b'v', b'a', b'l', b'u',
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        assert!(receive_data_bulk_profiled(&buf[..]).is_err());
+        let config = RecvConfig {
+            named_sections: NamedSections::Collect,
+            ..Default::default()
+        };
+        let (items, profile) = receive_data_bulk_profiled_with_config(&buf[..], &config).unwrap();
+        assert_eq!(items[0].extensions["k"], b"valu");
+        assert_eq!(profile.items.len(), 1);
+
+        // The skipped sections are limited too
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'H', 0xff, 0xff, 0xff, 0xff,
+            ];
+        let config = RecvConfig {
+            max_section_bytes: 1024,
+            ..Default::default()
+        };
+        let e = receive_data_bulk_profiled_with_config(&buf[..], &config).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Content size 4294967295 exceeds the limit 1024"
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use super::recv::{
+    MAGIC, NamedSections, PROTOCAL_VER, PROTOCAL_VER_COUNTED, RecvConfig, check_denied_bytes,
+    check_item_count, check_section_size, decode_u32, decompress, parse_named_section,
+};
+use super::source_data::SourceDataItem;
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+
+// Flag and length
+const SECTION_HEADER_LEN: usize = 5;
+
+/// A fixed-capacity byte queue, the bytes wrap around at the end of the array.
+struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    fn new() -> Self {
+        RingBuffer {
+            buf: [0; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Append as many bytes as there is room for, returns the number of the bytes appended.
+    fn push(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(N - self.len);
+        for (i, b) in data[..n].iter().enumerate() {
+            self.buf[(self.start + self.len + i) % N] = *b;
+        }
+        self.len += n;
+        n
+    }
+
+    /// Copy the first `out.len()` bytes out without consuming them, `false` if there are fewer.
+    fn peek(&self, out: &mut [u8]) -> bool {
+        if out.len() > self.len {
+            return false;
+        }
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = self.buf[(self.start + i) % N];
+        }
+        true
+    }
+
+    fn consume(&mut self, n: usize) {
+        let n = n.min(self.len);
+        self.start = (self.start + n) % N;
+        self.len -= n;
+    }
+
+    /// Move up to `max` bytes to the end of `out`, returns the number of the bytes moved.
+    fn drain_into(&mut self, out: &mut Vec<u8>, max: usize) -> usize {
+        let n = max.min(self.len);
+        // At most two contiguous parts
+        let first = n.min(N - self.start);
+        out.extend_from_slice(&self.buf[self.start..self.start + first]);
+        out.extend_from_slice(&self.buf[..n - first]);
+        self.consume(n);
+        n
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Header,
+    SectionHeader,
+    Mime(usize),
+    Skip(usize),
+    Content { flag: u8, remaining: usize },
+}
+
+/// What `PushParser::poll` parsed from the bytes pushed so far.
+#[derive(Debug)]
+pub enum Parsed {
+    /// Push more bytes to go on.
+    NeedMoreData,
+    Item(SourceDataItem),
+}
+
+/// A sans-io parser of the bulk protocol, for integrations which don't read from a `Read`. The
+/// caller pushes the bytes into a ring buffer of `N` bytes as they arrive, and polls the parser
+/// for the items. Only the content of the current item is kept outside the ring, so a content
+/// can be larger than `N`, but every section header and mime-type section has to fit in it.
+pub struct PushParser<const N: usize> {
+    ring: RingBuffer<N>,
+    state: State,
+    config: RecvConfig,
+    mime_types: Vec<String>,
+    extensions: HashMap<String, Vec<u8>>,
+    content: Vec<u8>,
+    // The item count of the header, and the number of the items parsed
+    count: Option<u32>,
+    items: usize,
+}
+
+impl<const N: usize> Default for PushParser<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> PushParser<N> {
+    pub fn new() -> Self {
+        Self::with_config(RecvConfig::default())
+    }
+
+    /// Create the parser with the limits and the section handling of the config. The timing
+    /// limits and `reject_trailing_data` don't apply, since the caller does the reading.
+    pub fn with_config(config: RecvConfig) -> Self {
+        PushParser {
+            ring: RingBuffer::new(),
+            state: State::Header,
+            config,
+            mime_types: vec![],
+            extensions: HashMap::new(),
+            content: vec![],
+            count: None,
+            items: 0,
+        }
+    }
+
+    /// Push the bytes into the ring buffer, returns the number of the bytes taken. It is fewer
+    /// than `data.len()` when the ring is full, push the rest after polling.
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        self.ring.push(data)
+    }
+
+    /// Parse the bytes pushed so far until an item is complete, or more bytes are needed.
+    pub fn poll(&mut self) -> Result<Parsed> {
+        loop {
+            match self.state {
+                State::Header => {
+                    // With the item count of `PROTOCAL_VER_COUNTED`
+                    let mut header = [0u8; 9];
+                    if !self.ring.peek(&mut header[..5]) {
+                        return self.need_bytes(5);
+                    }
+                    if header[..MAGIC.len()] != MAGIC {
+                        bail!(
+                            "Failed to match magic header: {:x?}",
+                            &header[..MAGIC.len()]
+                        );
+                    }
+                    let len = match header[MAGIC.len()] {
+                        ver if ver == PROTOCAL_VER => 5,
+                        ver if ver == PROTOCAL_VER_COUNTED => {
+                            if !self.ring.peek(&mut header) {
+                                return self.need_bytes(header.len());
+                            }
+                            self.count = Some(u32::from_be_bytes(header[5..].try_into().unwrap()));
+                            header.len()
+                        }
+                        ver => bail!("Failed to match protoal version: {}", ver),
+                    };
+                    self.ring.consume(len);
+                    self.state = State::SectionHeader;
+                }
+                State::SectionHeader => {
+                    let mut header = [0u8; SECTION_HEADER_LEN];
+                    if !self.ring.peek(&mut header) {
+                        return self.need_bytes(header.len());
+                    }
+                    self.ring.consume(header.len());
+                    let flag = header[0];
+                    let len = decode_u32(header[1..].try_into().unwrap(), self.config.byte_order)
+                        as usize;
+                    log::debug!("Read block flag '{flag}' of {len} bytes");
+                    self.state = match flag {
+                        b'M' => State::Mime(len),
+                        b'H' | b'N' => State::Skip(len),
+                        b'C' | b'Z' => {
+                            if self.mime_types.is_empty() {
+                                bail!("Failed to read content with empty mime type");
+                            }
+                            if flag == b'Z'
+                                && let Some(agreed) = &self.config.capabilities
+                                && !agreed.compression
+                            {
+                                bail!(
+                                    "Failed to read compressed content, compression is not negotiated"
+                                );
+                            }
+                            State::Content {
+                                flag,
+                                remaining: len,
+                            }
+                        }
+                        // Collected like a content, it is parsed when complete
+                        b'K' if self.config.named_sections != NamedSections::Reject => {
+                            State::Content {
+                                flag,
+                                remaining: len,
+                            }
+                        }
+                        _ => bail!("Failed to parse flag {flag}"),
+                    };
+                    check_section_size(flag, len, &self.config)?;
+                    if matches!(self.state, State::Content { .. }) {
+                        self.content = Vec::with_capacity(len.min(N));
+                    }
+                }
+                State::Mime(len) => {
+                    if self.ring.len < len {
+                        return self.need_bytes(len);
+                    }
+                    let mut buf = vec![0u8; len];
+                    self.ring.peek(&mut buf);
+                    self.ring.consume(len);
+                    let mime_type =
+                        String::from_utf8(buf).context("Failed to parse mime type string")?;
+                    self.mime_types.push(mime_type);
+                    self.state = State::SectionHeader;
+                }
+                State::Skip(remaining) => {
+                    let n = remaining.min(self.ring.len);
+                    self.ring.consume(n);
+                    if n < remaining {
+                        self.state = State::Skip(remaining - n);
+                        return Ok(Parsed::NeedMoreData);
+                    }
+                    self.state = State::SectionHeader;
+                }
+                State::Content { flag, remaining } => {
+                    let n = self.ring.drain_into(&mut self.content, remaining);
+                    if n < remaining {
+                        self.state = State::Content {
+                            flag,
+                            remaining: remaining - n,
+                        };
+                        return Ok(Parsed::NeedMoreData);
+                    }
+                    self.state = State::SectionHeader;
+                    let content = std::mem::take(&mut self.content);
+                    let content = match flag {
+                        b'K' => {
+                            let (key, value) =
+                                parse_named_section(&content, self.config.byte_order)?;
+                            if self.config.named_sections == NamedSections::Collect {
+                                self.extensions.insert(key, value);
+                            }
+                            continue;
+                        }
+                        b'Z' => decompress(&content, self.config.max_section_bytes)?,
+                        _ => content,
+                    };
+                    let mime_types = std::mem::take(&mut self.mime_types);
+                    let mut item = SourceDataItem::new(mime_types, content);
+                    item.extensions = std::mem::take(&mut self.extensions);
+                    check_denied_bytes(
+                        item.is_text(),
+                        &item.content,
+                        &self.config.denied_text_bytes,
+                    )?;
+                    self.items += 1;
+                    return Ok(Parsed::Item(item));
+                }
+            }
+        }
+    }
+
+    /// Check the stream ended at an item boundary, after the last item has been polled.
+    pub fn finish(self) -> Result<()> {
+        if self.state == State::Header {
+            bail!("Failed to read magic header");
+        }
+        if self.state != State::SectionHeader || self.ring.len != 0 {
+            bail!("The stream ended in the middle of a section");
+        }
+        if !self.mime_types.is_empty() {
+            bail!("Stream ended before the content of the mime-types");
+        }
+        check_item_count(self.count, self.items)
+    }
+
+    // A section which can never fit would need more data forever.
+    fn need_bytes(&self, len: usize) -> Result<Parsed> {
+        if len > N {
+            bail!("Section of {len} bytes doesn't fit the {N} bytes ring buffer");
+        }
+        Ok(Parsed::NeedMoreData)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolWriter;
+
+    fn stream_data() -> Vec<u8> {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_mime("text/plain").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_heartbeat().unwrap();
+        writer.write_mime("text/html").unwrap();
+        writer
+            .write_content(b"<b>A content longer than the ring</b>")
+            .unwrap();
+        writer.into_inner()
+    }
+
+    // Push in the given sizes, polling whenever the ring is full or the piece is taken.
+    fn parse<const N: usize>(buf: &[u8], sizes: &[usize]) -> Result<Vec<SourceDataItem>> {
+        let mut parser = PushParser::<N>::new();
+        let mut items = vec![];
+        let mut rest = buf;
+        for size in sizes.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let mut piece = &rest[..(*size).min(rest.len())];
+            rest = &rest[piece.len()..];
+            while !piece.is_empty() {
+                let n = parser.push(piece);
+                piece = &piece[n..];
+                while let Parsed::Item(item) = parser.poll()? {
+                    items.push(item);
+                }
+            }
+        }
+        parser.finish()?;
+        Ok(items)
+    }
+
+    #[test]
+    fn test_push_parser() {
+        let buf = stream_data();
+        for sizes in [&[1][..], &[3, 1, 7], &[16], &[5, 11, 2], &[buf.len()]] {
+            let r = parse::<16>(&buf, sizes).unwrap();
+            assert_eq!(r.len(), 2, "push sizes {sizes:?}");
+            assert_eq!(r[0].mime_type, vec!["TEXT", "text/plain"]);
+            assert_eq!(r[0].content.as_slice(), b"GOOD");
+            assert_eq!(r[1].mime_type, vec!["text/html"]);
+            assert_eq!(
+                r[1].content.as_slice(),
+                b"<b>A content longer than the ring</b>"
+            );
+        }
+        // The ring wraps around at every position
+        for n in 1..buf.len() {
+            assert_eq!(parse::<13>(&buf, &[n]).unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_push_parser_need_more_data() {
+        let buf = stream_data();
+        let mut parser = PushParser::<64>::new();
+        assert!(matches!(parser.poll().unwrap(), Parsed::NeedMoreData));
+        assert_eq!(parser.push(&buf[..20]), 20);
+        assert!(matches!(parser.poll().unwrap(), Parsed::NeedMoreData));
+        // Only the room left is taken
+        let n = parser.push(&buf[20..]);
+        assert!(n < buf.len() - 20);
+        assert!(matches!(parser.poll().unwrap(), Parsed::Item(_)));
+        assert!(matches!(parser.poll().unwrap(), Parsed::NeedMoreData));
+        assert_eq!(parser.push(&buf[20 + n..]), buf.len() - 20 - n);
+        assert!(matches!(parser.poll().unwrap(), Parsed::Item(_)));
+        assert!(matches!(parser.poll().unwrap(), Parsed::NeedMoreData));
+        parser.finish().unwrap();
+    }
+
+    #[test]
+    fn test_push_parser_errors() {
+        let buf = stream_data();
+        // Truncated
+        assert!(parse::<16>(&buf[..buf.len() - 1], &[4]).is_err());
+        // Bad magic
+        assert!(parse::<16>(&buf[1..], &[4]).is_err());
+        // Header only, no items
+        assert!(parse::<16>(&buf[..5], &[4]).unwrap().is_empty());
+        assert!(parse::<16>(&buf[..3], &[4]).is_err());
+        // Mime-type section larger than the ring
+        assert!(parse::<8>(&buf, &[4]).is_err());
+        // Content without mime-type
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_content(b"BAD").unwrap();
+        assert!(parse::<16>(&writer.into_inner(), &[4]).is_err());
+    }
+
+    #[test]
+    fn test_push_parser_with_config() {
+        let mut writer = ProtocolWriter::with_item_count(Vec::new(), 1).unwrap();
+        writer.write_named("source", b"terminal").unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+        let parse_with = |config: RecvConfig, buf: &[u8]| {
+            let mut parser = PushParser::<64>::with_config(config);
+            assert_eq!(parser.push(buf), buf.len());
+            let mut items = vec![];
+            while let Parsed::Item(item) = parser.poll()? {
+                items.push(item);
+            }
+            parser.finish()?;
+            anyhow::Ok(items)
+        };
+        // Named sections are rejected by default
+        assert!(parse_with(RecvConfig::default(), &buf).is_err());
+        let config = RecvConfig {
+            named_sections: NamedSections::Collect,
+            ..Default::default()
+        };
+        let r = parse_with(config.clone(), &buf).unwrap();
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert_eq!(r[0].extensions["source"], b"terminal");
+
+        // The item count of the header is checked
+        let e = parse_with(config.clone(), &buf[..9]).unwrap_err();
+        assert_eq!(e.to_string(), "Expected 1 items, but received 0");
+
+        // The limits are checked with the section header
+        let config = RecvConfig {
+            max_section_bytes: 3,
+            max_mime_type_len: 3,
+            ..Default::default()
+        };
+        let e = parse_with(config.clone(), &stream_data()[..10]).unwrap_err();
+        assert!(
+            e.downcast_ref::<crate::protocol::MimeTypeTooLong>()
+                .is_some()
+        );
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_heartbeat().unwrap();
+        writer.write_mime("TXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let e = parse_with(config, &writer.into_inner()).unwrap_err();
+        assert_eq!(e.to_string(), "Content size 4 exceeds the limit 3");
+    }
+}
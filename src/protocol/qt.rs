@@ -0,0 +1,129 @@
+use super::source_data::SourceDataItem;
+
+const QT_WINDOWS_MIME_PREFIX: &str = "application/x-qt-windows-mime;value=\"";
+
+// Qt's names of the native Windows formats, and the mime-types they stand for.
+const WINDOWS_FORMAT_MAP: &[(&str, &str)] = &[
+    ("HTML Format", "text/html"),
+    ("Rich Text Format", "text/rtf"),
+    ("UniformResourceLocatorW", "text/uri-list"),
+    ("PNG", "image/png"),
+];
+
+// The X11 text targets don't exist in Qt, they are all `text/plain` there.
+const X_TEXT_TARGETS: &[&str] = &["TEXT", "STRING", "UTF8_STRING"];
+
+/// The Qt format name of the mime-type. Qt uses mime-types as the format names, except for the
+/// X11 text targets, which are `text/plain` in Qt.
+pub fn qt_format_name(mime_type: &str) -> &str {
+    if X_TEXT_TARGETS.contains(&mime_type) {
+        "text/plain"
+    } else {
+        mime_type
+    }
+}
+
+/// The mime-type of the Qt format name. The native Windows formats Qt exposes as
+/// `application/x-qt-windows-mime;value="..."` are mapped to their mime-types when known, and
+/// `application/x-qt-image` to `image/png`, which is what Qt encodes images as.
+pub fn mime_type_from_qt_format(format: &str) -> &str {
+    if let Some(name) = format
+        .strip_prefix(QT_WINDOWS_MIME_PREFIX)
+        .and_then(|s| s.strip_suffix('"'))
+    {
+        return WINDOWS_FORMAT_MAP
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map_or(format, |(_, mime_type)| mime_type);
+    }
+    if format == "application/x-qt-image" {
+        return "image/png";
+    }
+    format
+}
+
+/// Encode the item as `QMimeData` formats, one `(format, data)` pair for each distinct format.
+pub fn qt_encode(item: &SourceDataItem) -> Vec<(String, Vec<u8>)> {
+    let mut ret: Vec<(String, Vec<u8>)> = vec![];
+    for mime_type in &item.mime_type {
+        let format = qt_format_name(mime_type);
+        if !ret.iter().any(|(f, _)| f == format) {
+            ret.push((format.to_string(), item.content.to_vec()));
+        }
+    }
+    ret
+}
+
+/// Decode the `QMimeData` formats as an item. An item has one content for all its mime-types,
+/// so the content of the first pair is used, and only the formats with the same data are
+/// included. Use it once for every distinct data to get them all.
+pub fn qt_decode(pairs: &[(String, Vec<u8>)]) -> SourceDataItem {
+    let Some((_, content)) = pairs.first() else {
+        return SourceDataItem::new(vec![], Vec::new());
+    };
+    let mut mime_type: Vec<String> = vec![];
+    for (format, data) in pairs {
+        let mapped = mime_type_from_qt_format(format);
+        if data == content && !mime_type.iter().any(|t| t == mapped) {
+            mime_type.push(mapped.to_string());
+        }
+    }
+    SourceDataItem::new(mime_type, content.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qt_encode() {
+        let item = SourceDataItem::new(
+            ["text/plain", "UTF8_STRING", "TEXT", "text/html"]
+                .map(String::from)
+                .to_vec(),
+            b"GOOD".to_vec(),
+        );
+        let pairs = qt_encode(&item);
+        assert_eq!(
+            pairs,
+            [
+                ("text/plain".to_string(), b"GOOD".to_vec()),
+                ("text/html".to_string(), b"GOOD".to_vec()),
+            ]
+        );
+        let r = qt_decode(&pairs);
+        assert_eq!(r.mime_type, ["text/plain", "text/html"]);
+        assert_eq!(r.content.as_slice(), b"GOOD");
+    }
+
+    #[test]
+    fn test_qt_decode() {
+        let pairs = [
+            (
+                "application/x-qt-windows-mime;value=\"HTML Format\"".to_string(),
+                b"<b>GOOD</b>".to_vec(),
+            ),
+            ("text/plain".to_string(), b"GOOD".to_vec()),
+            (
+                "application/x-qt-windows-mime;value=\"Unknown\"".to_string(),
+                b"<b>GOOD</b>".to_vec(),
+            ),
+            ("text/html".to_string(), b"<b>GOOD</b>".to_vec()),
+        ];
+        let r = qt_decode(&pairs);
+        assert_eq!(
+            r.mime_type,
+            [
+                "text/html",
+                "application/x-qt-windows-mime;value=\"Unknown\"",
+            ]
+        );
+        assert_eq!(r.content.as_slice(), b"<b>GOOD</b>");
+
+        assert_eq!(
+            mime_type_from_qt_format("application/x-qt-image"),
+            "image/png"
+        );
+        assert!(qt_decode(&[]).mime_type.is_empty());
+    }
+}
@@ -0,0 +1,403 @@
+use super::recv::{
+    MimeTypeTooLong, NamedSections, RecvConfig, SectionTooLarge, check_denied_bytes,
+    check_item_count, decompress, parse_named_section, read_header_with_count, read_section_size,
+    skip_section,
+};
+use super::source_data::SourceDataItem;
+use anyhow::{Context, Error, anyhow};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+/// What `receive_data_bulk_with_error_handler` does after an error.
+#[derive(Debug)]
+pub enum ErrorAction {
+    /// Discard the item being received and go on with the next section.
+    Skip,
+    /// Stop and return the items received so far.
+    Abort,
+    /// Parse the bytes in place of the failed header or section, then go on with the rest of the
+    /// stream.
+    Retry(Vec<u8>),
+}
+
+// The error of a section which was read entirely, so the next section can still be found.
+enum Failure {
+    Section(Error),
+    Stream(Error),
+}
+
+enum Step {
+    Continue,
+    Item(SourceDataItem),
+    Eof,
+}
+
+// Reads the injected bytes first.
+struct InjectReader<R: Read> {
+    injected: Cursor<Vec<u8>>,
+    inner: R,
+}
+
+impl<R: Read> InjectReader<R> {
+    fn inject(&mut self, mut bytes: Vec<u8>) {
+        let pos = self.injected.position() as usize;
+        bytes.extend_from_slice(&self.injected.get_ref()[pos..]);
+        self.injected = Cursor::new(bytes);
+    }
+}
+
+impl<R: Read> Read for InjectReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.injected.read(buf)?;
+        if n > 0 || buf.is_empty() {
+            return Ok(n);
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Same as `receive_data_bulk`, but the errors are passed to `on_error` instead of failing the
+/// whole stream, so the items already received are never lost. It decides with an
+/// `ErrorAction` how to go on, e.g. a relay can drop the broken items and pass the others on.
+/// The errors are the same as the ones of `receive_data_bulk`, which can be downcast to e.g.
+/// `MimeTypeTooLong`.
+/// `Skip` only goes on if the failed section was read entirely, e.g. an invalid mime-type or an
+/// unknown flag. If the header is wrong or the stream ends in the middle of a section, there is
+/// no next section to go on with, and it stops like `Abort`. `Retry` works in both cases, but
+/// the handler is called again if the replacement bytes fail too.
+pub fn receive_data_bulk_with_error_handler<R, F>(reader: R, on_error: F) -> Vec<SourceDataItem>
+where
+    R: Read,
+    F: FnMut(Error) -> ErrorAction,
+{
+    receive_data_bulk_with_error_handler_with_config(reader, &RecvConfig::default(), on_error)
+}
+
+/// Same as `receive_data_bulk_with_error_handler`, with the given config. If the header has an
+/// item count which doesn't match at the end of the stream, the error is passed to `on_error`
+/// too, and the items are returned unless it is retried. `RecvConfig::reject_trailing_data` is
+/// not applied, the handler decides what to do with any broken section.
+pub fn receive_data_bulk_with_error_handler_with_config<R, F>(
+    reader: R,
+    config: &RecvConfig,
+    mut on_error: F,
+) -> Vec<SourceDataItem>
+where
+    R: Read,
+    F: FnMut(Error) -> ErrorAction,
+{
+    let mut reader = InjectReader {
+        injected: Cursor::new(vec![]),
+        inner: reader,
+    };
+    let mut items = vec![];
+    let mut pending = Pending::default();
+    let mut header_read = false;
+    let mut count = None;
+    loop {
+        let step = if header_read {
+            read_section(&mut reader, config, &mut pending)
+        } else {
+            read_header_with_count(&mut reader)
+                .map(|c| {
+                    count = c;
+                    Step::Continue
+                })
+                .map_err(Failure::Stream)
+        };
+        let (error, delimited) = match step {
+            Ok(Step::Continue) => {
+                header_read = true;
+                continue;
+            }
+            Ok(Step::Item(item)) => {
+                items.push(item);
+                continue;
+            }
+            Ok(Step::Eof) => match check_item_count(count, items.len()) {
+                Ok(()) => return items,
+                Err(e) => (e, false),
+            },
+            Err(Failure::Section(e)) => (e, true),
+            Err(Failure::Stream(e)) => (e, false),
+        };
+        log::debug!("Failed to receive the section: {error:#}");
+        match on_error(error) {
+            ErrorAction::Skip if delimited => pending = Pending::default(),
+            ErrorAction::Skip | ErrorAction::Abort => return items,
+            ErrorAction::Retry(bytes) => reader.inject(bytes),
+        }
+    }
+}
+
+// The sections received for the next item.
+#[derive(Default)]
+struct Pending {
+    type_list: Vec<String>,
+    extensions: HashMap<String, Vec<u8>>,
+}
+
+fn read_section(
+    reader: &mut impl Read,
+    config: &RecvConfig,
+    pending: &mut Pending,
+) -> Result<Step, Failure> {
+    let mut flag = [0u8; 1];
+    let r = reader
+        .read(&mut flag)
+        .context("Failed to read flag")
+        .map_err(Failure::Stream)?;
+    // EOF
+    if r == 0 {
+        return Ok(Step::Eof);
+    }
+    let flag = flag[0];
+    let size = match read_section_size(reader, flag, config) {
+        Ok(size) => size,
+        Err(e) => {
+            // Skip the data without reading it to memory, then the next section can be read
+            let size = match (
+                e.downcast_ref::<MimeTypeTooLong>(),
+                e.downcast_ref::<SectionTooLarge>(),
+            ) {
+                (Some(too_long), _) => too_long.length,
+                (_, Some(too_large)) => too_large.size,
+                _ => return Err(Failure::Stream(e)),
+            };
+            skip_section(reader, size).map_err(Failure::Stream)?;
+            return Err(Failure::Section(e));
+        }
+    };
+    log::debug!("Read block flag '{flag}' of {size} bytes");
+    if matches!(flag, b'H' | b'N') {
+        skip_section(reader, size).map_err(Failure::Stream)?;
+        return Ok(Step::Continue);
+    }
+
+    // Read it in steps instead of allocating the declared size up front
+    let mut data = vec![];
+    reader
+        .take(size as u64)
+        .read_to_end(&mut data)
+        .context("Failed to read section")
+        .map_err(Failure::Stream)?;
+    if data.len() != size {
+        return Err(Failure::Stream(anyhow!(
+            "Section ended after {} of {size} bytes",
+            data.len()
+        )));
+    }
+
+    match flag {
+        b'M' => {
+            let mime_type = String::from_utf8(data)
+                .context("Failed to parse mime type string")
+                .map_err(Failure::Section)?;
+            pending.type_list.push(mime_type);
+            Ok(Step::Continue)
+        }
+        b'C' | b'Z' => {
+            if pending.type_list.is_empty() {
+                return Err(Failure::Section(anyhow!(
+                    "Failed to read content with empty mime type"
+                )));
+            }
+            if flag == b'Z'
+                && let Some(agreed) = &config.capabilities
+                && !agreed.compression
+            {
+                return Err(Failure::Section(anyhow!(
+                    "Failed to read compressed content, compression is not negotiated"
+                )));
+            }
+            let content = if flag == b'Z' {
+                decompress(&data, config.max_section_bytes).map_err(Failure::Section)?
+            } else {
+                data
+            };
+            let pending = std::mem::take(pending);
+            let mut item = SourceDataItem::new(pending.type_list, content);
+            item.extensions = pending.extensions;
+            check_denied_bytes(item.is_text(), &item.content, &config.denied_text_bytes)
+                .map_err(Failure::Section)?;
+            Ok(Step::Item(item))
+        }
+        b'K' if config.named_sections != NamedSections::Reject => {
+            let (key, value) =
+                parse_named_section(&data, config.byte_order).map_err(Failure::Section)?;
+            if config.named_sections == NamedSections::Collect {
+                pending.extensions.insert(key, value);
+            } else {
+                log::debug!("Skipped named section '{key}'");
+            }
+            Ok(Step::Continue)
+        }
+        _ => Err(Failure::Section(anyhow!("Failed to parse flag {}", flag))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{MimeTypeTooLong, ProtocolWriter};
+
+    // Two good items around an item with an unknown section
+    fn stream_data() -> Vec<u8> {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_mime("text/html").unwrap();
+        let mut buf = writer.into_inner();
+        buf.extend_from_slice(&[b'X', 0, 0, 0, 3, 1, 2, 3]);
+        let mut writer = ProtocolWriter::without_header(buf);
+        writer.write_content(b"<b>BAD</b>").unwrap();
+        writer.write_mime("image/png").unwrap();
+        writer.write_content(b"PNG").unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn test_skip_and_abort() {
+        let buf = stream_data();
+        let mut errors = vec![];
+        let r = receive_data_bulk_with_error_handler(&buf[..], |e| {
+            errors.push(e.to_string());
+            ErrorAction::Skip
+        });
+        // The item of the unknown section is discarded, so its content has no mime-type
+        assert_eq!(
+            errors,
+            [
+                "Failed to parse flag 88",
+                "Failed to read content with empty mime type"
+            ]
+        );
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert_eq!(r[1].mime_type, ["image/png"]);
+
+        let r = receive_data_bulk_with_error_handler(&buf[..], |_| ErrorAction::Abort);
+        assert_eq!(r.len(), 1);
+
+        // Can't go on in the middle of a section
+        let mut calls = 0;
+        let r = receive_data_bulk_with_error_handler(&buf[..buf.len() - 1], |_| {
+            calls += 1;
+            ErrorAction::Skip
+        });
+        assert_eq!(r.len(), 1);
+        assert_eq!(calls, 3);
+
+        // The structured errors can be downcast
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime(&"x".repeat(300)).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let r = receive_data_bulk_with_error_handler(&writer.into_inner()[..], |e| {
+            assert!(e.downcast_ref::<MimeTypeTooLong>().is_some());
+            ErrorAction::Skip
+        });
+        assert_eq!(r[0].mime_type, ["TEXT"]);
+    }
+
+    #[test]
+    fn test_retry() {
+        let buf = stream_data();
+        // Replace the unknown section with a mime-type
+        let r = receive_data_bulk_with_error_handler(&buf[..], |_| {
+            ErrorAction::Retry(vec![b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L'])
+        });
+        assert_eq!(r.len(), 3);
+        assert_eq!(r[1].mime_type, ["text/html", "HTML"]);
+        assert_eq!(r[1].content.as_slice(), b"<b>BAD</b>");
+
+        // Replace the wrong header
+        let mut calls = 0;
+        let r = receive_data_bulk_with_error_handler(&buf[2..], |e| {
+            calls += 1;
+            if calls == 1 {
+                assert!(e.to_string().contains("magic header"));
+                // The magic took the first 4 bytes, up to the first flag
+                ErrorAction::Retry(buf[..6].to_vec())
+            } else {
+                ErrorAction::Abort
+            }
+        });
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].mime_type, ["TEXT"]);
+    }
+
+    #[test]
+    fn test_counted_stream() {
+        let mut writer = ProtocolWriter::with_item_count(Vec::new(), 2).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_mime("image/png").unwrap();
+        writer.write_content(b"PNG").unwrap();
+        let buf = writer.into_inner();
+        let r = receive_data_bulk_with_error_handler(&buf[..], |e| panic!("{e:#}"));
+        assert_eq!(r.len(), 2);
+
+        // The count mismatch is passed to the handler, the items are still returned
+        let cut = buf.len() - 3 - 5 - 9 - 5;
+        let mut errors = vec![];
+        let r = receive_data_bulk_with_error_handler(&buf[..cut], |e| {
+            errors.push(e.to_string());
+            ErrorAction::Skip
+        });
+        assert_eq!(r.len(), 1);
+        assert_eq!(errors, ["Expected 2 items, but received 1"]);
+    }
+
+    #[test]
+    fn test_named_sections() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_named("source", b"terminal").unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+        for (named_sections, extensions) in [(NamedSections::Skip, 0), (NamedSections::Collect, 1)]
+        {
+            let config = RecvConfig {
+                named_sections,
+                ..Default::default()
+            };
+            let r = receive_data_bulk_with_error_handler_with_config(&buf[..], &config, |e| {
+                panic!("{e:#}")
+            });
+            assert_eq!(r.len(), 1);
+            assert_eq!(r[0].content.as_slice(), b"GOOD");
+            assert_eq!(r[0].extensions.len(), extensions);
+        }
+        let config = RecvConfig {
+            named_sections: NamedSections::Collect,
+            ..Default::default()
+        };
+        let r = receive_data_bulk_with_error_handler_with_config(&buf[..], &config, |_| {
+            ErrorAction::Abort
+        });
+        assert_eq!(r[0].extensions["source"], b"terminal");
+
+        // Rejected by default, the item is dropped with its extension
+        let mut errors = vec![];
+        let r = receive_data_bulk_with_error_handler(&buf[..], |e| {
+            errors.push(e.to_string());
+            ErrorAction::Skip
+        });
+        assert_eq!(r.len(), 1);
+        assert_eq!(errors, ["Failed to parse flag 75"]);
+
+        // The limits are checked with the shared helpers, the section is skipped
+        let config = RecvConfig {
+            named_sections: NamedSections::Collect,
+            max_section_bytes: 8,
+            ..Default::default()
+        };
+        let r = receive_data_bulk_with_error_handler_with_config(&buf[..], &config, |e| {
+            assert!(e.downcast_ref::<SectionTooLarge>().is_some());
+            ErrorAction::Skip
+        });
+        assert_eq!(r.len(), 1);
+        assert!(r[0].extensions.is_empty());
+    }
+}
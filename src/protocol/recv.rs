@@ -1,10 +1,22 @@
 use anyhow::{Context, Result, bail};
-use std::io::Read;
+use std::collections::HashMap;
+use std::io::{BufRead, Error, ErrorKind, Read};
+#[cfg(unix)]
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
 
 pub static PROTOCAL_VER: u8 = 0;
-static MAGIC: [u8; 4] = [0x20, 0x09, 0x02, 0x14];
+/// The protocol version whose header is followed by the number of the items, see
+/// `receive_data_bulk`.
+pub static PROTOCAL_VER_COUNTED: u8 = 1;
+pub(super) static MAGIC: [u8; 4] = [0x20, 0x09, 0x02, 0x14];
+/// The default limit of the mime-type length, see `RecvConfig::max_mime_type_len`. Registered
+/// mime-types are far shorter.
+pub const MAX_MIME_TYPE_LEN: usize = 256;
 
-use super::source_data::SourceDataItem;
+use super::capabilities::Capabilities;
+use super::source_data::{SourceDataItem, validate_mime_type};
+use crate::clipboard::sniff_mime_type;
 
 /// Receive the mime-types and the content for the clipboard.
 /// It uses a simple protocol which defines as below:
@@ -32,13 +44,389 @@ use super::source_data::SourceDataItem;
 /// - Every section starts with the section type, `M` (mime-type) or `C` (content).
 /// - Before `C` section, there must be one or more `M` section to indicate the data type.
 /// - Section length will be parsed as big-endian uint32 number.
-pub fn receive_data_bulk(mut reader: impl Read) -> Result<Vec<SourceDataItem>> {
+/// - `H` (heartbeat) sections are ignored, they only tell the sender is still alive.
+/// - `N` (negotiation) sections are ignored too, see `Capabilities` for the handshake.
+/// - `Z` sections are the same as `C` sections, but the content is compressed with zlib. They are
+///   only supported with the `compression` feature.
+/// - `K` (named) sections are key-value extensions of the item they come before. The data is
+///   the key length as big-endian uint32, the UTF-8 key, and the value in the rest. They fail
+///   here, see `receive_data_bulk_lenient` and `receive_data_bulk_extended`.
+/// - With the protocol version `PROTOCAL_VER_COUNTED`, the version is followed by the number of
+///   the items as big-endian uint32, and it fails if the stream has a different number of items.
+pub fn receive_data_bulk(reader: impl Read) -> Result<Vec<SourceDataItem>> {
+    receive_data_bulk_with_config(reader, &RecvConfig::default())
+}
+
+/// Options for `receive_data_bulk_with_config`.
+#[derive(Clone)]
+pub struct RecvConfig {
+    /// The maximum size of a content section. For compressed sections, it limits both the size
+    /// before and after the decompression, so a small section cannot expand into an unbounded
+    /// allocation.
+    pub max_section_bytes: usize,
+    /// The maximum length of a mime-type section, it fails with `MimeTypeTooLong` if exceeded.
+    pub max_mime_type_len: usize,
+    /// The byte order of the section lengths, see `receive_data_bulk_le`.
+    pub byte_order: ByteOrder,
+    /// The number of bytes a section may be waited for per second. If set, the content of a
+    /// section must arrive within `min_wait` plus one second for every `max_wait_bytes` of its
+    /// size, otherwise it fails. A large size claimed by a sender which never sends the content
+    /// is given up on, instead of waiting for it forever.
+    /// The window is checked before every read, so it cannot interrupt a single blocking read.
+    /// Set a read timeout on the underlying reader too, e.g. `UnixStream::set_read_timeout`, the
+    /// reads timing out are retried until the window is over.
+    pub max_wait_bytes: Option<usize>,
+    /// See `max_wait_bytes`.
+    pub min_wait: Duration,
+    /// The bytes the content of a text item must not contain, e.g. `vec![0]` for a NUL-free
+    /// channel. It fails with `DeniedByte` if they are found. Other items are not checked.
+    pub denied_text_bytes: Vec<u8>,
+    /// What to do with the `K` (named) sections.
+    pub named_sections: NamedSections,
+    /// Fail with `TrailingData` if the bytes after a complete item have an unknown flag or are cut
+    /// short by EOF, instead of the error of that section, see `receive_data_bulk_strict`.
+    pub reject_trailing_data: bool,
+    /// The capabilities agreed in the handshake, the sections needing the others fail, e.g. `Z`
+    /// without `compression`. `None` accepts all of them, for the streams without a handshake.
+    /// See `receive_data_bulk_negotiated`.
+    pub capabilities: Option<Capabilities>,
+}
+
+/// See `RecvConfig::named_sections`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NamedSections {
+    /// Fail like for any other unknown section.
+    #[default]
+    Reject,
+    /// Ignore them, see `receive_data_bulk_lenient`.
+    Skip,
+    /// Collect them into `SourceDataItem::extensions`, see `receive_data_bulk_extended`.
+    Collect,
+}
+
+impl Default for RecvConfig {
+    fn default() -> Self {
+        RecvConfig {
+            max_section_bytes: u32::MAX as usize,
+            max_mime_type_len: MAX_MIME_TYPE_LEN,
+            byte_order: ByteOrder::BigEndian,
+            max_wait_bytes: None,
+            min_wait: Duration::from_secs(1),
+            denied_text_bytes: Vec::new(),
+            named_sections: NamedSections::Reject,
+            reject_trailing_data: false,
+            capabilities: None,
+        }
+    }
+}
+
+/// The byte order of the section lengths. The protocol uses big-endian, little-endian is only
+/// for the experimental `receive_data_bulk_le`.
+/// As the companion of `PROTOCAL_VER`, the value is also the `PROTOCAL_BYTE_ORDER` byte which
+/// follows the version in little-endian streams.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ByteOrder {
+    BigEndian = b'B',
+    LittleEndian = b'L',
+}
+
+pub(super) fn decode_u32(buf: [u8; 4], order: ByteOrder) -> u32 {
+    match order {
+        ByteOrder::BigEndian => u32::from_be_bytes(buf),
+        ByteOrder::LittleEndian => u32::from_le_bytes(buf),
+    }
+}
+
+pub(super) fn encode_u32(value: u32, order: ByteOrder) -> [u8; 4] {
+    match order {
+        ByteOrder::BigEndian => value.to_be_bytes(),
+        ByteOrder::LittleEndian => value.to_le_bytes(),
+    }
+}
+
+/// Same as `receive_data_bulk`, with the given limits.
+pub fn receive_data_bulk_with_config(
+    mut reader: impl Read,
+    config: &RecvConfig,
+) -> Result<Vec<SourceDataItem>> {
+    let count = read_header_with_count(&mut reader)?;
+    read_counted_sections(&mut reader, count, config)
+}
+
+/// Read the sections until EOF, and check there are `count` items if the header has the count.
+pub(super) fn read_counted_sections(
+    reader: &mut impl Read,
+    count: Option<u32>,
+    config: &RecvConfig,
+) -> Result<Vec<SourceDataItem>> {
+    // The count is not trusted for the allocation until the items arrive
+    let mut ret = Vec::<SourceDataItem>::with_capacity(count.unwrap_or(0).min(1024) as usize);
+    read_sections(reader, config, &mut default_alloc, &mut ret)?;
+    check_item_count(count, ret.len())?;
+    Ok(ret)
+}
+
+/// Check the number of the received items matches the count of the header, if it has one.
+pub(super) fn check_item_count(count: Option<u32>, received: usize) -> Result<()> {
+    if let Some(count) = count
+        && count as usize != received
+    {
+        bail!("Expected {count} items, but received {received}");
+    }
+    Ok(())
+}
+
+/// Same as `receive_data_bulk`, but the buffer of every content section is created by `alloc`
+/// with the size of the section, e.g. to `mlock` the buffers holding secrets. The returned buffer
+/// must have a capacity of at least the size, e.g. `Vec::with_capacity(size)`, it is cleared and
+/// filled without reallocating, otherwise the parsing fails. Compressed sections are
+/// decompressed into a normally allocated buffer.
+pub fn receive_data_bulk_with_alloc(
+    mut reader: impl Read,
+    alloc: impl Fn(usize) -> Vec<u8>,
+) -> Result<Vec<SourceDataItem>> {
+    let count = read_header_with_count(&mut reader)?;
+    let mut ret = Vec::<SourceDataItem>::new();
+    let mut alloc = |size| {
+        let mut buf = alloc(size);
+        if buf.capacity() < size {
+            // Growing it would move the content out of the buffer the caller prepared
+            bail!(
+                "The allocated buffer has a capacity of {} bytes, {size} bytes are required",
+                buf.capacity()
+            );
+        }
+        buf.clear();
+        buf.resize(size, 0);
+        Ok(buf)
+    };
+    read_sections(&mut reader, &RecvConfig::default(), &mut alloc, &mut ret)?;
+    check_item_count(count, ret.len())?;
+    Ok(ret)
+}
+
+/// Same as `receive_data_bulk`, but the stream has no magic header and protocol version, it
+/// starts with the first section directly. This is meant for transports that already frame and
+/// authenticate the data.
+/// Without the header, there is nothing to tell the stream was produced by richclip, or with a
+/// compatible protocol version. Random or mismatched input will fail or, worse, be parsed into
+/// unexpected items, so only use it with a trusted producer.
+pub fn receive_data_bulk_raw(mut reader: impl Read) -> Result<Vec<SourceDataItem>> {
+    let mut ret = Vec::<SourceDataItem>::new();
+    read_sections(
+        &mut reader,
+        &RecvConfig::default(),
+        &mut default_alloc,
+        &mut ret,
+    )?;
+    Ok(ret)
+}
+
+/// Parse the stream written by `encode_to_writer_without_header`, the same as
+/// `receive_data_bulk_raw`.
+pub fn receive_data_bulk_without_header(reader: impl Read) -> Result<Vec<SourceDataItem>> {
+    receive_data_bulk_raw(reader)
+}
+
+/// Same as `receive_data_bulk`, but the stream starts with the given magic instead of the
+/// standard `MAGIC`, for embedding the protocol in other ones. The protocol version is still
+/// checked after the magic.
+pub fn receive_data_bulk_with_magic(
+    mut reader: impl Read,
+    magic: &[u8; 4],
+) -> Result<Vec<SourceDataItem>> {
+    let count = read_header_with_magic(&mut reader, magic)?;
+    read_counted_sections(&mut reader, count, &RecvConfig::default())
+}
+
+/// **Experimental**: the little-endian variant of `receive_data_bulk`, which may change or be
+/// removed. The version byte is followed by the `PROTOCAL_BYTE_ORDER` byte, which must be
+/// `ByteOrder::LittleEndian`, and all the section lengths are little-endian uint32 numbers.
+/// The stream is not compatible with `receive_data_bulk`, nor anything else in richclip yet.
+pub fn receive_data_bulk_le(mut reader: impl Read) -> Result<Vec<SourceDataItem>> {
+    read_header(&mut reader)?;
+    let mut order = [0u8; 1];
+    reader
+        .read_exact(&mut order)
+        .context("Failed to read protocal byte order")?;
+    if order[0] != ByteOrder::LittleEndian as u8 {
+        bail!("Failed to match protocal byte order: {}", order[0]);
+    }
+    let config = RecvConfig {
+        byte_order: ByteOrder::LittleEndian,
+        ..Default::default()
+    };
+    let mut ret = Vec::<SourceDataItem>::new();
+    read_sections(&mut reader, &config, &mut default_alloc, &mut ret)?;
+    Ok(ret)
+}
+
+/// Returned when the buffer for a content section cannot be allocated.
+#[derive(Debug)]
+pub struct OutOfMemory {
+    pub size: usize,
+}
+
+impl std::fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to allocate {} bytes for content", self.size)
+    }
+}
+
+impl std::error::Error for OutOfMemory {}
+
+/// Returned when a mime-type section is longer than `RecvConfig::max_mime_type_len`.
+#[derive(Debug)]
+pub struct MimeTypeTooLong {
+    pub length: usize,
+    pub max: usize,
+}
+
+impl std::fmt::Display for MimeTypeTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Mime type of {} bytes exceeds the limit {}",
+            self.length, self.max
+        )
+    }
+}
+
+impl std::error::Error for MimeTypeTooLong {}
+
+/// Returned when a section other than a mime-type is longer than `RecvConfig::max_section_bytes`.
+#[derive(Debug)]
+pub struct SectionTooLarge {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl std::fmt::Display for SectionTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Content size {} exceeds the limit {}",
+            self.size, self.max
+        )
+    }
+}
+
+impl std::error::Error for SectionTooLarge {}
+
+/// Check the length of a mime-type section before it is read.
+pub(super) fn check_mime_type_len(length: usize, max: usize) -> Result<()> {
+    if length > max {
+        return Err(MimeTypeTooLong { length, max }.into());
+    }
+    Ok(())
+}
+
+/// Returned when the content of a text item contains one of `RecvConfig::denied_text_bytes`.
+#[derive(Debug)]
+pub struct DeniedByte {
+    pub byte: u8,
+    /// The offset in the content.
+    pub offset: usize,
+}
+
+impl std::fmt::Display for DeniedByte {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Denied byte 0x{:02x} in the text content at offset {}",
+            self.byte, self.offset
+        )
+    }
+}
+
+impl std::error::Error for DeniedByte {}
+
+/// Returned with `RecvConfig::reject_trailing_data` if the bytes after a complete item have an
+/// unknown flag, or a known one whose section is cut short by EOF. The error of parsing them is
+/// its cause.
+#[derive(Debug)]
+pub struct TrailingData {
+    /// The first byte of the trailing data.
+    pub byte: u8,
+}
+
+impl std::fmt::Display for TrailingData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unexpected trailing data starting with 0x{:02x}",
+            self.byte
+        )
+    }
+}
+
+impl std::error::Error for TrailingData {}
+
+/// Check the content of a text item has none of the `RecvConfig::denied_text_bytes`.
+pub(super) fn check_denied_bytes(is_text: bool, content: &[u8], denied: &[u8]) -> Result<()> {
+    if denied.is_empty() || !is_text {
+        return Ok(());
+    }
+    if let Some(offset) = content.iter().position(|b| denied.contains(b)) {
+        return Err(DeniedByte {
+            byte: content[offset],
+            offset,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Allocates a zeroed buffer of the given size for a content section.
+pub(super) type ContentAlloc<'a> = dyn FnMut(usize) -> Result<Vec<u8>> + 'a;
+
+pub(super) fn default_alloc(size: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    buf.try_reserve_exact(size)
+        .map_err(|_| OutOfMemory { size })?;
+    buf.resize(size, 0);
+    Ok(buf)
+}
+
+/// Check the magic header and the protocol version, which is either `PROTOCAL_VER` or
+/// `PROTOCAL_VER_COUNTED`. The item count is returned for the latter.
+pub(super) fn read_header_with_count(reader: &mut impl Read) -> Result<Option<u32>> {
+    read_header_with_magic(reader, &MAGIC)
+}
+
+/// Same as `read_header_with_count`, with the given magic instead of `MAGIC`.
+fn read_header_with_magic(reader: &mut impl Read, expected_magic: &[u8; 4]) -> Result<Option<u32>> {
+    let ver = read_magic_and_version(reader, expected_magic)?;
+    if ver == PROTOCAL_VER {
+        return Ok(None);
+    }
+    if ver != PROTOCAL_VER_COUNTED {
+        bail!("Failed to match protoal version: {}", ver);
+    }
+    let mut count = [0u8; 4];
+    reader
+        .read_exact(&mut count)
+        .context("Failed to read item count")?;
+    Ok(Some(u32::from_be_bytes(count)))
+}
+
+/// Check the magic header and the protocol version, which must be `PROTOCAL_VER`.
+pub(super) fn read_header(reader: &mut impl Read) -> Result<()> {
+    let ver = read_magic_and_version(reader, &MAGIC)?;
+    if ver != PROTOCAL_VER {
+        bail!("Failed to match protoal version: {}", ver);
+    }
+    Ok(())
+}
+
+fn read_magic_and_version(reader: &mut impl Read, expected_magic: &[u8; 4]) -> Result<u8> {
     // Check magic header
     let mut magic = [0u8; 4];
     reader
         .read_exact(&mut magic)
         .context("Failed to read magic header")?;
-    if magic != MAGIC {
+    if magic != *expected_magic {
         bail!("Failed to match magic header: {:x?}", magic);
     }
 
@@ -47,43 +435,223 @@ pub fn receive_data_bulk(mut reader: impl Read) -> Result<Vec<SourceDataItem>> {
     reader
         .read_exact(&mut ver)
         .context("Failed to read protocal version")?;
-    if ver[0] != PROTOCAL_VER {
-        bail!("Failed to match protoal version: {}", ver[0]);
+    Ok(ver[0])
+}
+
+/// Read the sections until EOF. The parsed items are pushed to `ret` as soon as they are
+/// complete, so they are still available if an error is returned later.
+pub(super) fn read_sections(
+    reader: &mut impl Read,
+    config: &RecvConfig,
+    alloc: &mut ContentAlloc,
+    ret: &mut Vec<SourceDataItem>,
+) -> Result<()> {
+    let mut after_item = false;
+    while let Some(item) = read_next_item(reader, config, alloc, after_item)? {
+        after_item = true;
+        ret.push(item);
     }
+    Ok(())
+}
 
+/// Read the sections until the next item is complete. `None` is returned at EOF.
+/// `after_item` tells if a complete item is before it, only then the data which doesn't start a
+/// section is taken as trailing data with `RecvConfig::reject_trailing_data`.
+pub(super) fn read_next_item(
+    reader: &mut impl Read,
+    config: &RecvConfig,
+    alloc: &mut ContentAlloc,
+    after_item: bool,
+) -> Result<Option<SourceDataItem>> {
     let mut flag = [0u8; 1];
     let mut type_list = Vec::new();
-    let mut ret = Vec::<SourceDataItem>::new();
+    let mut extensions = HashMap::new();
+    // If no section of the item is read yet
+    let mut first = true;
     loop {
         let r = reader.read(&mut flag).context("Failed to read flag")?;
         // EOF
         if r == 0 {
-            break;
+            return Ok(None);
         }
         log::debug!("Read block flag '{}'", flag[0]);
-        match flag[0] {
-            b'M' => {
-                let mime_type = read_mime_types(&mut reader)?;
-                type_list.push(mime_type);
+        let item = match read_section(
+            reader,
+            flag[0],
+            config,
+            alloc,
+            &mut type_list,
+            &mut extensions,
+        ) {
+            Err(e) => return Err(trailing_data_error(e, flag[0], first && after_item, config)),
+            Ok(item) => item,
+        };
+        if item.is_some() {
+            return Ok(item);
+        }
+        first = false;
+    }
+}
+
+/// Wrap the error of the section of the flag with `TrailingData` if it is trailing data, see
+/// `RecvConfig::reject_trailing_data`. `item_before` tells if the section is the first one after a
+/// complete item.
+pub(super) fn trailing_data_error(
+    e: anyhow::Error,
+    flag: u8,
+    item_before: bool,
+    config: &RecvConfig,
+) -> anyhow::Error {
+    if item_before && config.reject_trailing_data && is_trailing(flag, &e, config) {
+        return e.context(TrailingData { byte: flag });
+    }
+    e
+}
+
+// The unknown flags and the sections cut short by EOF are trailing data, the other errors, e.g.
+// a too long mime-type or running out of memory, are the errors of a valid section.
+fn is_trailing(flag: u8, e: &anyhow::Error, config: &RecvConfig) -> bool {
+    let known = match flag {
+        b'M' | b'H' | b'N' | b'C' | b'Z' => true,
+        b'K' => config.named_sections != NamedSections::Reject,
+        _ => false,
+    };
+    !known
+        || e.chain().any(|cause| {
+            cause
+                .downcast_ref::<Error>()
+                .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
+        })
+}
+
+// Read the section of the flag, the item is returned if it is complete.
+pub(super) fn read_section(
+    reader: &mut impl Read,
+    flag: u8,
+    config: &RecvConfig,
+    alloc: &mut ContentAlloc,
+    type_list: &mut Vec<String>,
+    extensions: &mut HashMap<String, Vec<u8>>,
+) -> Result<Option<SourceDataItem>> {
+    match flag {
+        b'M' => {
+            let mime_type =
+                read_mime_types_ordered(reader, config.byte_order, config.max_mime_type_len)?;
+            type_list.push(mime_type);
+        }
+        b'H' | b'N' => {
+            let size = read_section_size(reader, flag, config)?;
+            skip_section(reader, size)?;
+            log::debug!("Skipped section '{}' of {} bytes", flag as char, size);
+        }
+        b'C' => {
+            if type_list.is_empty() {
+                bail!("Failed to read content with empty mime type");
             }
-            b'C' => {
-                if type_list.is_empty() {
-                    bail!("Failed to read content with empty mime type");
-                }
-                let content = read_content(&mut reader)?;
-                ret.push(SourceDataItem {
-                    mime_type: type_list,
-                    content: content.into(),
-                });
-                type_list = Vec::new();
+            let content = read_content_ordered(reader, config.max_section_bytes, config, alloc)?;
+            let mut item = SourceDataItem::new(std::mem::take(type_list), content);
+            item.extensions = std::mem::take(extensions);
+            check_denied_bytes(item.is_text(), &item.content, &config.denied_text_bytes)?;
+            return Ok(Some(item));
+        }
+        b'Z' => {
+            if type_list.is_empty() {
+                bail!("Failed to read content with empty mime type");
             }
-            _ => {
-                bail!("Failed to parse flag {}", flag[0]);
+            if let Some(agreed) = &config.capabilities
+                && !agreed.compression
+            {
+                bail!("Failed to read compressed content, compression is not negotiated");
             }
+            let compressed = read_content_ordered(reader, config.max_section_bytes, config, alloc)?;
+            let content = decompress(&compressed, config.max_section_bytes)?;
+            let mut item = SourceDataItem::new(std::mem::take(type_list), content);
+            item.extensions = std::mem::take(extensions);
+            check_denied_bytes(item.is_text(), &item.content, &config.denied_text_bytes)?;
+            return Ok(Some(item));
+        }
+        b'K' if config.named_sections != NamedSections::Reject => {
+            let data =
+                read_content_ordered(reader, config.max_section_bytes, config, &mut default_alloc)?;
+            let (key, value) = parse_named_section(&data, config.byte_order)?;
+            if config.named_sections == NamedSections::Collect {
+                extensions.insert(key, value);
+            } else {
+                log::debug!("Skipped named section '{key}'");
+            }
+        }
+        _ => {
+            bail!("Failed to parse flag {}", flag);
         }
     }
+    Ok(None)
+}
 
-    Ok(ret)
+pub(super) fn parse_named_section(data: &[u8], order: ByteOrder) -> Result<(String, Vec<u8>)> {
+    let Some((len, rest)) = data.split_first_chunk::<4>() else {
+        bail!("Named section of {} bytes is too short", data.len());
+    };
+    let len = decode_u32(*len, order) as usize;
+    if len > rest.len() {
+        bail!("Key length {len} exceeds the named section");
+    }
+    let key = String::from_utf8(rest[..len].to_vec()).context("Failed to parse section key")?;
+    Ok((key, rest[len..].to_vec()))
+}
+
+/// Same as `receive_data_bulk`, but the `K` (named) sections are skipped, so the streams with
+/// extensions this version doesn't know are still received.
+pub fn receive_data_bulk_lenient(reader: impl Read) -> Result<Vec<SourceDataItem>> {
+    let config = RecvConfig {
+        named_sections: NamedSections::Skip,
+        ..Default::default()
+    };
+    receive_data_bulk_with_config(reader, &config)
+}
+
+/// Same as `receive_data_bulk`, but the `K` (named) sections are collected into the
+/// `SourceDataItem::extensions` of the item they come before. The value of a repeated key
+/// replaces the earlier one, and the named sections after the last content are dropped.
+pub fn receive_data_bulk_extended(reader: impl Read) -> Result<Vec<SourceDataItem>> {
+    let config = RecvConfig {
+        named_sections: NamedSections::Collect,
+        ..Default::default()
+    };
+    receive_data_bulk_with_config(reader, &config)
+}
+
+/// Same as `receive_data_bulk`, for the streams which are expected to be self-contained. The
+/// bytes after a complete item with an unknown flag, or cut short by EOF, are taken as
+/// corruption, and it fails with `TrailingData` instead of the error of parsing them as a section.
+pub fn receive_data_bulk_strict(reader: impl Read) -> Result<Vec<SourceDataItem>> {
+    let config = RecvConfig {
+        reject_trailing_data: true,
+        ..Default::default()
+    };
+    receive_data_bulk_with_config(reader, &config)
+}
+
+/// Same as `receive_data_bulk`, but reads from the given file descriptor, e.g. the read end of a
+/// pipe or a socket received from another process.
+/// The `fd` is closed after parsing if `close_on_done` is true, otherwise it is left open and the
+/// caller keeps the ownership.
+#[cfg(unix)]
+pub fn receive_data_bulk_from_socket_fd(
+    fd: RawFd,
+    close_on_done: bool,
+) -> Result<Vec<SourceDataItem>> {
+    use std::fs::File;
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: The caller guarantees the fd is valid. It won't be closed by dropping the `File`
+    // unless `close_on_done` is set.
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    let ret = receive_data_bulk(&mut *file);
+    if close_on_done {
+        unsafe { ManuallyDrop::drop(&mut file) };
+    }
+    ret
 }
 
 pub fn receive_data_oneshot(
@@ -104,23 +672,51 @@ pub fn receive_data_oneshot(
     if filtered.is_empty() {
         bail!("All given mime_types are empty");
     }
+    for mime_type in &filtered {
+        validate_mime_type(mime_type)?;
+    }
 
-    let ret = vec![SourceDataItem {
-        mime_type: filtered,
-        content: content.into(),
-    }];
+    let ret = vec![SourceDataItem::new(filtered, content)];
     Ok(ret)
 }
 
+/// Same as `receive_data_oneshot`, but the mime-type is detected from the first 512 bytes with
+/// `sniff_mime_type`, or `application/octet-stream` if nothing matches. Only the bytes returned
+/// by the first `fill_buf` are looked at, which may be fewer than 512.
+pub fn receive_data_oneshot_detect_mime(mut reader: impl BufRead) -> Result<Vec<SourceDataItem>> {
+    let head = reader.fill_buf().context("Failed to read content")?;
+    let mime_type =
+        sniff_mime_type(&head[..head.len().min(512)]).unwrap_or("application/octet-stream");
+    log::debug!("Detected mime-type {mime_type} for oneshot mode");
+    receive_data_oneshot(reader, &[mime_type.to_string()])
+}
+
+#[cfg(feature = "compression")]
+pub(super) fn decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    super::compress::decompress(data, max_size)
+}
+
+#[cfg(not(feature = "compression"))]
+pub(super) fn decompress(_data: &[u8], _max_size: usize) -> Result<Vec<u8>> {
+    bail!("Compressed sections are not supported without the 'compression' feature");
+}
+
+#[cfg(test)]
 fn read_mime_types(reader: &mut impl Read) -> Result<String> {
+    read_mime_types_ordered(reader, ByteOrder::BigEndian, MAX_MIME_TYPE_LEN)
+}
+
+pub(super) fn read_mime_types_ordered(
+    reader: &mut impl Read,
+    order: ByteOrder,
+    max_len: usize,
+) -> Result<String> {
     let mut size_buf = [0u8; 4];
     reader
         .read_exact(&mut size_buf)
         .context("Failed to read mime type size")?;
-    let size: u32 = ((size_buf[0] as u32) << 24)
-        + ((size_buf[1] as u32) << 16)
-        + ((size_buf[2] as u32) << 8)
-        + size_buf[3] as u32;
+    let size = decode_u32(size_buf, order);
+    check_mime_type_len(size as usize, max_len)?;
 
     log::debug!("Expected mime-type size: {}", size);
     let mut buf = vec![0u8; size as usize];
@@ -134,25 +730,133 @@ fn read_mime_types(reader: &mut impl Read) -> Result<String> {
     Ok(mime_type)
 }
 
+/// Read the length of the section of the flag, and check it with `check_section_size` before
+/// anything is allocated for the section.
+pub(super) fn read_section_size(
+    reader: &mut impl Read,
+    flag: u8,
+    config: &RecvConfig,
+) -> Result<usize> {
+    let mut size_buf = [0u8; 4];
+    reader
+        .read_exact(&mut size_buf)
+        .context("Failed to read section size")?;
+    let size = decode_u32(size_buf, config.byte_order) as usize;
+    check_section_size(flag, size, config)?;
+    Ok(size)
+}
+
+/// Check the length of the section of the flag against the limits of the config. Mime-types are
+/// limited by `max_mime_type_len`, all the other sections by `max_section_bytes`.
+pub(super) fn check_section_size(flag: u8, size: usize, config: &RecvConfig) -> Result<()> {
+    if flag == b'M' {
+        return check_mime_type_len(size, config.max_mime_type_len);
+    }
+    if size > config.max_section_bytes {
+        return Err(SectionTooLarge {
+            size,
+            max: config.max_section_bytes,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Discard the data of a section, without allocating a buffer of its size.
+pub(super) fn skip_section(reader: &mut impl Read, size: usize) -> Result<()> {
+    let skipped = std::io::copy(&mut reader.by_ref().take(size as u64), &mut std::io::sink())
+        .context("Failed to skip section")?;
+    if skipped != size as u64 {
+        return Err(Error::from(ErrorKind::UnexpectedEof)).context("Failed to skip section");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
 fn read_content(reader: &mut impl Read) -> Result<Vec<u8>> {
+    read_content_with(reader, u32::MAX as usize, &mut default_alloc)
+}
+
+pub(super) fn read_content_with(
+    reader: &mut impl Read,
+    max_size: usize,
+    alloc: &mut ContentAlloc,
+) -> Result<Vec<u8>> {
+    read_content_ordered(reader, max_size, &RecvConfig::default(), alloc)
+}
+
+// Read the content with the byte order and the wait window of the config.
+fn read_content_ordered(
+    reader: &mut impl Read,
+    max_size: usize,
+    config: &RecvConfig,
+    alloc: &mut ContentAlloc,
+) -> Result<Vec<u8>> {
     let mut size_buf = [0u8; 4];
     reader
         .read_exact(&mut size_buf)
         .context("Failed to read content size")?;
-    let size: u32 = ((size_buf[0] as u32) << 24)
-        + ((size_buf[1] as u32) << 16)
-        + ((size_buf[2] as u32) << 8)
-        + size_buf[3] as u32;
+    let size = decode_u32(size_buf, config.byte_order);
 
     log::debug!("Expected content size: {}", size);
-    let mut buf = vec![0u8; size as usize];
-    reader
-        .read_exact(&mut buf)
-        .context("Failed to read content")?;
-
+    if size as usize > max_size {
+        return Err(SectionTooLarge {
+            size: size as usize,
+            max: max_size,
+        }
+        .into());
+    }
+    let mut buf = alloc(size as usize)?;
+    read_section_data(reader, &mut buf, config)?;
     Ok(buf)
 }
 
+/// Fill the buffer with the data of a section, within the time `RecvConfig::max_wait_bytes`
+/// allows for its size if set.
+pub(super) fn read_section_data(
+    reader: &mut impl Read,
+    buf: &mut [u8],
+    config: &RecvConfig,
+) -> Result<()> {
+    match config.max_wait_bytes {
+        Some(rate) => {
+            let window =
+                config.min_wait + Duration::from_secs_f64(buf.len() as f64 / rate.max(1) as f64);
+            read_exact_within(reader, buf, window)
+        }
+        None => reader.read_exact(buf).context("Failed to read content"),
+    }
+}
+
+fn read_exact_within(reader: &mut impl Read, buf: &mut [u8], window: Duration) -> Result<()> {
+    let deadline = Instant::now() + window;
+    let mut pos = 0;
+    while pos < buf.len() {
+        if Instant::now() >= deadline {
+            bail!(
+                "Content of {} bytes did not arrive within {:?}, {} bytes received",
+                buf.len(),
+                window,
+                pos
+            );
+        }
+        match reader.read(&mut buf[pos..]) {
+            Ok(0) => {
+                return Err(Error::from(ErrorKind::UnexpectedEof))
+                    .context("Failed to read content");
+            }
+            Ok(n) => pos += n,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(e).context("Failed to read content"),
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
@@ -232,6 +936,197 @@ mod tests {
         assert_eq!(data2.mime_type.len(), 1);
         assert_eq!(data2.mime_type[0], "text/html");
         assert_eq!(data2.content.as_slice(), b"BAD");
+
+        // Heartbeats are ignored
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'H', 0, 0, 0, 0,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'H', 0, 0, 0, 1, 0,
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'H', 0, 0, 0, 0,
+            ];
+        let r = receive_data_bulk(&mut &buf[..]).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].mime_type, ["TEXT"]);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+
+        // The length is limited like the content, and checked before the data arrives
+        let config = RecvConfig {
+            max_section_bytes: 0,
+            ..Default::default()
+        };
+        let e = receive_data_bulk_with_config(&buf[..], &config).unwrap_err();
+        assert_eq!(e.to_string(), "Content size 1 exceeds the limit 0");
+        #[rustfmt::skip]
+        let huge = [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER, b'N', 0xff, 0xff, 0xff, 0xff, 0];
+        let e = receive_data_bulk(&huge[..]).unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<Error>().unwrap().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_receive_data_bulk_raw() {
+        #[rustfmt::skip]
+        let buf = [
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+        ];
+        let r = receive_data_bulk_raw(&buf[..]).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].mime_type, vec!["TEXT"]);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+
+        // The header is not expected
+        let mut with_header = vec![0x20, 0x09, 0x02, 0x14, PROTOCAL_VER];
+        with_header.extend(buf);
+        assert!(receive_data_bulk_raw(&with_header[..]).is_err());
+    }
+
+    #[test]
+    fn test_receive_data_bulk_with_magic() {
+        let magic = *b"MYPR";
+        #[rustfmt::skip]
+        let buf = [b'M', b'Y', b'P', b'R', PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+        ];
+        let r = receive_data_bulk_with_magic(&buf[..], &magic).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert!(receive_data_bulk(&buf[..]).is_err());
+
+        // The standard magic is rejected
+        let mut standard = buf;
+        standard[..4].copy_from_slice(&MAGIC);
+        assert!(receive_data_bulk_with_magic(&standard[..], &magic).is_err());
+        assert!(receive_data_bulk_with_magic(&standard[..], &MAGIC).is_ok());
+
+        // With the item count
+        #[rustfmt::skip]
+        let counted = [b'M', b'Y', b'P', b'R', PROTOCAL_VER_COUNTED, 0, 0, 0, 1,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+        ];
+        let r = receive_data_bulk_with_magic(&counted[..], &magic).unwrap();
+        assert_eq!(r.len(), 1);
+        let err = receive_data_bulk_with_magic(&counted[..9], &magic).unwrap_err();
+        assert_eq!(err.to_string(), "Expected 1 items, but received 0");
+    }
+
+    #[test]
+    fn test_receive_data_bulk_max_wait_bytes() {
+        // Like a socket with a read timeout, whose sender stops sending
+        struct StalledReader<'a>(&'a [u8]);
+
+        impl Read for StalledReader<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.0.is_empty() {
+                    std::thread::sleep(Duration::from_millis(1));
+                    return Err(ErrorKind::WouldBlock.into());
+                }
+                let n = self.0.len().min(buf.len());
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        let config = RecvConfig {
+            max_wait_bytes: Some(1024),
+            min_wait: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let r = receive_data_bulk_with_config(&buf[..], &config).unwrap();
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+
+        // Only 2 bytes of the content arrive
+        let start = Instant::now();
+        let r = receive_data_bulk_with_config(StalledReader(&buf[..21]), &config);
+        let e = r.unwrap_err().to_string();
+        assert!(
+            e.starts_with("Content of 4 bytes did not arrive within 53."),
+            "{e}"
+        );
+        assert!(e.ends_with(", 2 bytes received"), "{e}");
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // EOF is still an error
+        let r = receive_data_bulk_with_config(&buf[..21], &config);
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_receive_data_bulk_le() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER, ByteOrder::LittleEndian as u8,
+            b'M', 4, 0, 0, 0, b'T', b'E', b'X', b'T',
+            b'H', 1, 0, 0, 0, 0,
+            b'C', 4, 0, 0, 0, b'G', b'O', b'O', b'D',
+            ];
+        let r = receive_data_bulk_le(&buf[..]).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].mime_type, vec!["TEXT"]);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+
+        // Missing or wrong byte order
+        let r = receive_data_bulk_le(&buf[..5]);
+        assert!(r.is_err());
+        let mut be = buf;
+        be[5] = ByteOrder::BigEndian as u8;
+        let r = receive_data_bulk_le(&be[..]);
+        assert!(r.is_err());
+        // Not a big-endian stream
+        let r = receive_data_bulk(&buf[..]);
+        assert!(r.is_err());
+
+        assert_eq!(decode_u32([0, 0, 1, 2], ByteOrder::BigEndian), 0x0102);
+        assert_eq!(decode_u32([2, 1, 0, 0], ByteOrder::LittleEndian), 0x0102);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_receive_data_bulk_from_socket_fd() {
+        use std::io::Write;
+        use std::os::fd::IntoRawFd;
+        use std::os::unix::net::UnixStream;
+
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        let is_open = |fd| unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 };
+
+        // Keep the fd open
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        tx.write_all(&buf).unwrap();
+        drop(tx);
+        let fd = rx.into_raw_fd();
+        let r = receive_data_bulk_from_socket_fd(fd, false).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert!(is_open(fd));
+        unsafe { libc::close(fd) };
+
+        // Close the fd when done
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        tx.write_all(&buf).unwrap();
+        drop(tx);
+        let fd = rx.into_raw_fd();
+        let r = receive_data_bulk_from_socket_fd(fd, true).unwrap();
+        assert_eq!(r.len(), 1);
     }
 
     #[test]
@@ -257,6 +1152,224 @@ mod tests {
 
         // With zero mime-type
         let r = receive_data_oneshot(&mut &buf[..], &["".to_string()]);
+        assert!(r.is_err());
+
+        // With null byte in mime-type
+        let r = receive_data_oneshot(&mut &buf[..], &["text\0".to_string()]);
         assert!(r.is_err())
     }
+
+    #[test]
+    fn test_max_mime_type_len() {
+        let mime_type = "x".repeat(MAX_MIME_TYPE_LEN + 1);
+        let mut writer = crate::protocol::ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime(&mime_type).unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+
+        let err = receive_data_bulk(&buf[..]).unwrap_err();
+        let err = err.downcast_ref::<MimeTypeTooLong>().unwrap();
+        assert_eq!((err.length, err.max), (257, 256));
+        assert!(receive_data_stream_first(&buf).is_err());
+
+        let config = RecvConfig {
+            max_mime_type_len: 1024,
+            ..Default::default()
+        };
+        let r = receive_data_bulk_with_config(&buf[..], &config).unwrap();
+        assert_eq!(r[0].mime_type, [mime_type]);
+
+        // Fails before reading the mime-type
+        #[rustfmt::skip]
+        let huge =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0xff, 0xff, 0xff, 0xff,
+            ];
+        let err = receive_data_bulk(&huge[..]).unwrap_err();
+        assert!(err.downcast_ref::<MimeTypeTooLong>().is_some());
+    }
+
+    fn receive_data_stream_first(buf: &[u8]) -> Result<SourceDataItem> {
+        crate::protocol::receive_data_stream(buf)?.next().unwrap()
+    }
+
+    #[test]
+    fn test_named_sections() {
+        let mut writer = crate::protocol::ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_named("source", b"terminal").unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_named("", b"").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"BAD").unwrap();
+        writer.write_named("trailing", b"x").unwrap();
+        let buf = writer.into_inner();
+
+        assert!(receive_data_bulk(&buf[..]).is_err());
+
+        let r = receive_data_bulk_lenient(&buf[..]).unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert!(r[0].extensions.is_empty());
+
+        let r = receive_data_bulk_extended(&buf[..]).unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].extensions.len(), 2);
+        assert_eq!(r[0].extensions["source"], b"terminal");
+        assert_eq!(r[0].extensions[""], b"");
+        assert!(r[1].extensions.is_empty());
+
+        // The key length exceeds the section
+        #[rustfmt::skip]
+        let buf = [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'K', 0, 0, 0, 5, 0, 0, 0, 2, b'k'];
+        assert!(receive_data_bulk_extended(&buf[..]).is_err());
+        assert!(receive_data_bulk_lenient(&buf[..4 + 1 + 5 + 3]).is_err());
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut writer = crate::protocol::ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_mime("image/png").unwrap();
+        writer.write_content(b"PNG").unwrap();
+        let buf = writer.into_inner();
+        assert_eq!(receive_data_bulk_strict(&buf[..]).unwrap().len(), 2);
+
+        let mut garbage = buf.clone();
+        garbage.extend_from_slice(b"\xde\xad\xbe\xef");
+        let e = receive_data_bulk_strict(&garbage[..]).unwrap_err();
+        assert_eq!(e.downcast_ref::<TrailingData>().unwrap().byte, 0xde);
+        assert_eq!(e.to_string(), "Unexpected trailing data starting with 0xde");
+        // Not strict
+        let e = receive_data_bulk(&garbage[..]).unwrap_err();
+        assert!(e.downcast_ref::<TrailingData>().is_none());
+        assert_eq!(e.to_string(), "Failed to parse flag 222");
+
+        // A valid flag without a complete section is trailing data too
+        let mut garbage = buf.clone();
+        garbage.extend_from_slice(b"M\0\0");
+        let e = receive_data_bulk_strict(&garbage[..]).unwrap_err();
+        assert_eq!(e.downcast_ref::<TrailingData>().unwrap().byte, b'M');
+
+        // The broken sections of an item are not
+        let mut garbage = buf.clone();
+        garbage.extend_from_slice(&[b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T', 0xde]);
+        let e = receive_data_bulk_strict(&garbage[..]).unwrap_err();
+        assert!(e.downcast_ref::<TrailingData>().is_none());
+
+        // Nor the invalid sections
+        let mut garbage = buf.clone();
+        garbage.extend_from_slice(&[b'M', 0xff, 0xff, 0xff, 0xff]);
+        let e = receive_data_bulk_strict(&garbage[..]).unwrap_err();
+        assert!(e.downcast_ref::<TrailingData>().is_none());
+        assert!(e.downcast_ref::<MimeTypeTooLong>().is_some());
+
+        // Nor the first section after the header
+        let garbage = [&buf[..5], b"\xde\xad"].concat();
+        let e = receive_data_bulk_strict(&garbage[..]).unwrap_err();
+        assert!(e.downcast_ref::<TrailingData>().is_none());
+    }
+
+    #[test]
+    fn test_denied_text_bytes() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L',
+            b'C', 0, 0, 0, 2, 0, 0,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', 0, b'D',
+            ];
+        let config = RecvConfig {
+            denied_text_bytes: vec![0, 0x1b],
+            ..Default::default()
+        };
+        let err = receive_data_bulk_with_config(&buf[..], &config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Denied byte 0x00 in the text content at offset 2"
+        );
+        let err = err.downcast_ref::<DeniedByte>().unwrap();
+        assert_eq!((err.byte, err.offset), (0, 2));
+
+        // The binary item passes, and nothing is denied by default
+        let config = RecvConfig {
+            denied_text_bytes: vec![0x1b],
+            ..Default::default()
+        };
+        assert_eq!(
+            receive_data_bulk_with_config(&buf[..], &config)
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(receive_data_bulk(&buf[..]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_receive_data_bulk_with_alloc() {
+        #[rustfmt::skip]
+        let buf =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L',
+            b'C', 0, 0, 0, 0,
+            b'M', 0, 0, 0, 4, b'J', b'S', b'O', b'N',
+            b'C', 0, 0, 0, 2, b'{', b'}',
+            ];
+        let sizes = std::cell::RefCell::new(Vec::new());
+        let r = receive_data_bulk_with_alloc(&buf[..], |size| {
+            sizes.borrow_mut().push(size);
+            let mut buf = Vec::with_capacity(size);
+            // Cleared before it is filled
+            buf.extend(std::iter::repeat_n(0xff, size));
+            buf
+        })
+        .unwrap();
+        assert_eq!(*sizes.borrow(), [4, 0, 2]);
+        assert_eq!(r.len(), 3);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert_eq!(r[1].content.as_slice(), b"");
+        assert_eq!(r[2].content.as_slice(), b"{}");
+
+        // Too small to hold the content without reallocating
+        let err = receive_data_bulk_with_alloc(&buf[..], |_| vec![0xff; 1]).unwrap_err();
+        assert!(format!("{err:#}").contains("capacity of 1 bytes, 4 bytes are required"));
+
+        // With the item count
+        let mut writer = crate::protocol::ProtocolWriter::with_item_count(Vec::new(), 2).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let counted = writer.into_inner();
+        let err = receive_data_bulk_with_alloc(&counted[..], Vec::with_capacity).unwrap_err();
+        assert_eq!(err.to_string(), "Expected 2 items, but received 1");
+        let mut counted = counted;
+        counted[8] = 1;
+        let r = receive_data_bulk_with_alloc(&counted[..], Vec::with_capacity).unwrap();
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+    }
+
+    #[test]
+    fn test_receive_data_oneshot_detect_mime() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let r = receive_data_oneshot_detect_mime(&png[..]).unwrap();
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].mime_type, ["image/png"]);
+        assert_eq!(r[0].content.as_slice(), png);
+
+        // The content after the peeked bytes is read as well
+        let mut text = vec![b'A'; 1000];
+        text.extend_from_slice(b"\x00");
+        let r = receive_data_oneshot_detect_mime(std::io::BufReader::new(&text[..])).unwrap();
+        assert_eq!(r[0].mime_type, ["text/plain;charset=utf-8"]);
+        assert_eq!(r[0].content.as_slice(), text);
+
+        let r = receive_data_oneshot_detect_mime(&b"\x00\x01"[..]).unwrap();
+        assert_eq!(r[0].mime_type, ["application/octet-stream"]);
+        let r = receive_data_oneshot_detect_mime(&b""[..]).unwrap();
+        assert_eq!(r[0].mime_type, ["text/plain;charset=utf-8"]);
+    }
 }
@@ -0,0 +1,122 @@
+use super::capabilities::{Capabilities, read_handshake};
+use super::recv::{
+    RecvConfig, default_alloc, encode_u32, parse_named_section, read_counted_sections,
+    read_section_data, read_section_size,
+};
+use super::source_data::SourceDataItem;
+use anyhow::{Context, Result, bail};
+use std::io::Read;
+
+/// The key of the `K` section which makes a stream a command, see `Request::Command`.
+pub const COMMAND_KEY: &str = "command";
+
+/// What a client sends to a server, see `receive_request`.
+#[derive(Debug)]
+pub enum Request {
+    Items(Vec<SourceDataItem>),
+    /// The name of a command. The stream is a single `K` section whose key is `COMMAND_KEY` and
+    /// whose value is the name, after the handshake if there is one. See
+    /// `ProtocolWriter::write_command`.
+    Command(String),
+}
+
+/// Same as `receive_data_bulk_negotiated`, but the stream may be a command instead of items.
+pub fn receive_request(
+    mut reader: impl Read,
+    config: &RecvConfig,
+    negotiate: impl FnOnce(Capabilities) -> Result<Capabilities>,
+) -> Result<Request> {
+    let (count, config, flag) = read_handshake(&mut reader, config, negotiate)?;
+    if flag != Some(b'K') {
+        let flag = flag.as_slice();
+        let items = read_counted_sections(&mut flag.chain(reader), count, &config)?;
+        return Ok(Request::Items(items));
+    }
+
+    let size = read_section_size(&mut reader, b'K', &config)?;
+    let mut data = default_alloc(size)?;
+    read_section_data(&mut reader, &mut data, &config)?;
+    let (key, value) = parse_named_section(&data, config.byte_order)?;
+    if key != COMMAND_KEY {
+        // An extension of the first item, parsed again with the rest
+        let size_buf = encode_u32(size as u32, config.byte_order);
+        let section = [&[b'K'][..], &size_buf, &data].concat();
+        let items = read_counted_sections(&mut section.chain(reader), count, &config)?;
+        return Ok(Request::Items(items));
+    }
+
+    let name = String::from_utf8(value).context("Failed to parse command name")?;
+    let mut rest = [0u8; 1];
+    if reader.read(&mut rest).context("Failed to read flag")? != 0 {
+        bail!("Unexpected data after the command '{name}'");
+    }
+    Ok(Request::Command(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{NamedSections, ProtocolWriter};
+
+    fn no_handshake(_: Capabilities) -> Result<Capabilities> {
+        unreachable!()
+    }
+
+    #[test]
+    fn test_receive_request_command() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_command("touch").unwrap();
+        let buf = writer.into_inner();
+        let r = receive_request(&buf[..], &RecvConfig::default(), no_handshake).unwrap();
+        assert!(matches!(r, Request::Command(name) if name == "touch"));
+
+        // After the handshake
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_capabilities(&Capabilities::default()).unwrap();
+        writer.write_command("touch").unwrap();
+        let r = receive_request(&writer.into_inner()[..], &RecvConfig::default(), Ok).unwrap();
+        assert!(matches!(r, Request::Command(name) if name == "touch"));
+
+        // Nothing may follow
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_command("touch").unwrap();
+        writer.write_heartbeat().unwrap();
+        assert!(
+            receive_request(
+                &writer.into_inner()[..],
+                &RecvConfig::default(),
+                no_handshake
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_receive_request_items() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_named("source", b"test").unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+
+        let config = RecvConfig {
+            named_sections: NamedSections::Collect,
+            ..Default::default()
+        };
+        let Request::Items(items) = receive_request(&buf[..], &config, no_handshake).unwrap()
+        else {
+            panic!("Not items");
+        };
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].content.as_slice(), b"GOOD");
+        assert_eq!(items[0].extensions["source"], b"test");
+        // Rejected like any other named section by default
+        assert!(receive_request(&buf[..], &RecvConfig::default(), no_handshake).is_err());
+
+        let Request::Items(items) = receive_request(&buf[..5], &config, no_handshake).unwrap()
+        else {
+            panic!("Not items");
+        };
+        assert!(items.is_empty());
+    }
+}
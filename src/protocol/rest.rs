@@ -0,0 +1,94 @@
+use super::source_data::SourceDataItem;
+use url::Url;
+
+const PATH_PREFIX: &str = "clipboard";
+
+impl SourceDataItem {
+    /// The URL of the item, `{base_url}/clipboard/{content_hash}/0`, for serving it with
+    /// `GET /clipboard/{hash}/{index}`. The index selects one of the mime-types of the item,
+    /// use `to_richclip_url_with_index` for the others. The hash is `fingerprint` in hex.
+    /// The base URL has to be a hierarchical one like `http://host/api`, others like `data:`
+    /// have no path to append to and are returned as they are.
+    pub fn to_richclip_url(&self, base_url: &Url) -> Url {
+        self.to_richclip_url_with_index(base_url, 0)
+    }
+
+    pub fn to_richclip_url_with_index(&self, base_url: &Url, mime_type_index: usize) -> Url {
+        let mut url = base_url.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments
+                .pop_if_empty()
+                .push(PATH_PREFIX)
+                .push(&format!("{:016x}", self.fingerprint()))
+                .push(&mime_type_index.to_string());
+        }
+        url
+    }
+}
+
+/// Find the item addressed by the URL made by `SourceDataItem::to_richclip_url`. The item and
+/// the mime-type to be used as `Content-Type` are returned.
+pub fn find_by_richclip_url<'a>(
+    items: &'a [SourceDataItem],
+    base_url: &Url,
+    url: &Url,
+) -> Option<(&'a SourceDataItem, &'a str)> {
+    let base: Vec<&str> = base_url
+        .path_segments()?
+        .filter(|s| !s.is_empty())
+        .collect();
+    let path: Vec<&str> = url.path_segments()?.collect();
+    let [prefix, hash, index] = path.strip_prefix(base.as_slice())? else {
+        return None;
+    };
+    if *prefix != PATH_PREFIX {
+        return None;
+    }
+    let hash = u64::from_str_radix(hash, 16).ok()?;
+    let index: usize = index.parse().ok()?;
+    let item = items.iter().find(|item| item.fingerprint() == hash)?;
+    Some((item, item.mime_type.get(index)?.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_richclip_url() {
+        let items = [
+            SourceDataItem::new(
+                vec!["text/plain".to_string(), "TEXT".to_string()],
+                b"GOOD".to_vec(),
+            ),
+            SourceDataItem::new(vec!["text/html".to_string()], b"<b>GOOD</b>".to_vec()),
+        ];
+        let base = Url::parse("http://localhost:8080/api/").unwrap();
+        let url = items[0].to_richclip_url(&base);
+        let hash = format!("{:016x}", items[0].fingerprint());
+        assert_eq!(
+            url.as_str(),
+            format!("http://localhost:8080/api/clipboard/{hash}/0")
+        );
+        // Without the trailing slash
+        let base = Url::parse("http://localhost:8080/api").unwrap();
+        assert_eq!(items[0].to_richclip_url(&base), url);
+        assert_ne!(items[1].to_richclip_url(&base), url);
+
+        let url = items[0].to_richclip_url_with_index(&base, 1);
+        let (item, mime_type) = find_by_richclip_url(&items, &base, &url).unwrap();
+        assert_eq!(item.content.as_slice(), b"GOOD");
+        assert_eq!(mime_type, "TEXT");
+
+        let url = items[1].to_richclip_url(&base);
+        let (_, mime_type) = find_by_richclip_url(&items, &base, &url).unwrap();
+        assert_eq!(mime_type, "text/html");
+
+        // Out of range index, or another base
+        let url = items[1].to_richclip_url_with_index(&base, 1);
+        assert!(find_by_richclip_url(&items, &base, &url).is_none());
+        let other = Url::parse("http://localhost:8080/other").unwrap();
+        let url = items[0].to_richclip_url(&other);
+        assert!(find_by_richclip_url(&items, &base, &url).is_none());
+    }
+}
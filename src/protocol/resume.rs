@@ -0,0 +1,351 @@
+use super::recv::{
+    NamedSections, OutOfMemory, RecvConfig, check_denied_bytes, check_item_count, decompress,
+    default_alloc, parse_named_section, read_header_with_count, read_mime_types_ordered,
+    read_section_data, read_section_size, skip_section,
+};
+use super::source_data::SourceDataItem;
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::io::Read;
+
+/// The progress of `receive_data_bulk_resumable`, kept when the transfer is interrupted so it
+/// can be resumed from where it stopped.
+#[derive(Debug, Default)]
+pub struct ResumeState {
+    items: Vec<SourceDataItem>,
+    // The mime-types and the named sections of the item being received
+    mime_types: Vec<String>,
+    extensions: HashMap<String, Vec<u8>>,
+    // The item count of the header of the first stream
+    count: Option<u32>,
+    // The flag, the size and the received bytes of the interrupted content section
+    content: Option<(u8, usize, Vec<u8>)>,
+    started: bool,
+}
+
+impl ResumeState {
+    /// The number of the complete items received.
+    pub fn items_received(&self) -> usize {
+        self.items.len()
+    }
+
+    /// The number of the mime-types received for the next item.
+    pub fn mime_types_received(&self) -> usize {
+        self.mime_types.len()
+    }
+
+    /// The number of the bytes received of the interrupted content section, which is the offset
+    /// the resumed stream has to start at.
+    pub fn content_offset(&self) -> u64 {
+        self.content.as_ref().map_or(0, |(_, _, c)| c.len() as u64)
+    }
+}
+
+/// Same as `receive_data_bulk`, but the progress is saved to `state` after every section, so an
+/// interrupted transfer can be resumed by calling it again with the same state and a new reader.
+/// The items are returned and the state is reset when the stream ends at a section boundary
+/// without pending mime-types. Since nothing marks the end of the stream, a transfer interrupted
+/// right after an item's content still looks complete.
+///
+/// The resumed stream starts with the magic header and the protocol version, followed by an `R`
+/// section whose data is the `ResumeState::content_offset` as a big-endian uint64. Then it goes
+/// on right where the interrupted one stopped: with a `C` or `Z` section of the remaining bytes
+/// if it stopped in a content section, or with the section after the last complete one. The
+/// sender learns the position from the `ResumeState` accessors out of band, and writes the `R`
+/// section with `ProtocolWriter::write_resume`.
+pub fn receive_data_bulk_resumable(
+    reader: impl Read,
+    state: &mut ResumeState,
+) -> Result<Vec<SourceDataItem>> {
+    receive_data_bulk_resumable_with_config(reader, state, &RecvConfig::default())
+}
+
+/// Same as `receive_data_bulk_resumable`, with the given config. Pass the same config when
+/// resuming. `RecvConfig::reject_trailing_data` is not applied, the broken sections are taken as
+/// an interruption. The item count is taken from the header of the first stream, and checked
+/// when the last one ends.
+pub fn receive_data_bulk_resumable_with_config(
+    mut reader: impl Read,
+    state: &mut ResumeState,
+    config: &RecvConfig,
+) -> Result<Vec<SourceDataItem>> {
+    let count = read_header_with_count(&mut reader)?;
+    if state.started {
+        read_resume(&mut reader, state, config)?;
+    } else {
+        state.count = count;
+    }
+    state.started = true;
+
+    loop {
+        if let Some((flag, size, _)) = &state.content {
+            let (flag, size) = (*flag, *size);
+            read_remaining_content(&mut reader, state, size)?;
+            let (_, _, content) = state.content.take().unwrap();
+            let content = if flag == b'Z' {
+                decompress(&content, config.max_section_bytes)?
+            } else {
+                content
+            };
+            let mime_types = std::mem::take(&mut state.mime_types);
+            let mut item = SourceDataItem::new(mime_types, content);
+            item.extensions = std::mem::take(&mut state.extensions);
+            check_denied_bytes(item.is_text(), &item.content, &config.denied_text_bytes)?;
+            state.items.push(item);
+        }
+
+        let mut flag = [0u8; 1];
+        let r = reader.read(&mut flag).context("Failed to read flag")?;
+        // EOF
+        if r == 0 {
+            if !state.mime_types.is_empty() {
+                bail!("Stream ended before the content of the mime-types");
+            }
+            let state = std::mem::take(state);
+            check_item_count(state.count, state.items.len())?;
+            return Ok(state.items);
+        }
+        match flag[0] {
+            b'M' => {
+                let mime_type = read_mime_types_ordered(
+                    &mut reader,
+                    config.byte_order,
+                    config.max_mime_type_len,
+                )?;
+                state.mime_types.push(mime_type);
+            }
+            b'H' | b'N' => {
+                let size = read_section_size(&mut reader, flag[0], config)?;
+                skip_section(&mut reader, size)?;
+            }
+            b'C' | b'Z' => {
+                if state.mime_types.is_empty() {
+                    bail!("Failed to read content with empty mime type");
+                }
+                if flag[0] == b'Z'
+                    && let Some(agreed) = &config.capabilities
+                    && !agreed.compression
+                {
+                    bail!("Failed to read compressed content, compression is not negotiated");
+                }
+                let size = read_section_size(&mut reader, flag[0], config)?;
+                let mut content = Vec::new();
+                content
+                    .try_reserve_exact(size)
+                    .map_err(|_| OutOfMemory { size })?;
+                state.content = Some((flag[0], size, content));
+            }
+            b'K' if config.named_sections != NamedSections::Reject => {
+                let size = read_section_size(&mut reader, flag[0], config)?;
+                let mut data = default_alloc(size)?;
+                read_section_data(&mut reader, &mut data, config)?;
+                let (key, value) = parse_named_section(&data, config.byte_order)?;
+                if config.named_sections == NamedSections::Collect {
+                    state.extensions.insert(key, value);
+                }
+            }
+            b'R' => bail!("Resume section in the middle of the stream"),
+            _ => bail!("Failed to parse flag {}", flag[0]),
+        }
+    }
+}
+
+// Read the `R` section and the header of the content section continuing the interrupted one.
+fn read_resume(reader: &mut impl Read, state: &mut ResumeState, config: &RecvConfig) -> Result<()> {
+    let mut flag = [0u8; 1];
+    reader
+        .read_exact(&mut flag)
+        .context("Failed to read resume section")?;
+    if flag[0] != b'R' {
+        bail!("Expected a resume section, got flag {}", flag[0]);
+    }
+    if read_section_size(reader, flag[0], config)? != 8 {
+        bail!("Resume section must be 8 bytes");
+    }
+    let mut offset = [0u8; 8];
+    reader
+        .read_exact(&mut offset)
+        .context("Failed to read resume offset")?;
+    let offset = u64::from_be_bytes(offset);
+    if offset != state.content_offset() {
+        bail!(
+            "Resume offset {offset} doesn't match the {} bytes received",
+            state.content_offset()
+        );
+    }
+
+    if let Some((flag, size, content)) = &state.content {
+        let mut next = [0u8; 1];
+        reader
+            .read_exact(&mut next)
+            .context("Failed to read flag")?;
+        if next[0] != *flag {
+            bail!(
+                "Expected flag {} to resume the content, got {}",
+                flag,
+                next[0]
+            );
+        }
+        let remaining = read_section_size(reader, *flag, config)?;
+        if content.len() + remaining != *size {
+            bail!("Resumed content of {remaining} bytes doesn't complete the {size} bytes content");
+        }
+    }
+    Ok(())
+}
+
+fn read_remaining_content(
+    reader: &mut impl Read,
+    state: &mut ResumeState,
+    size: usize,
+) -> Result<()> {
+    let (_, _, content) = state.content.as_mut().unwrap();
+    let mut buf = [0u8; 64 * 1024];
+    while content.len() < size {
+        let len = buf.len().min(size - content.len());
+        let n = match reader.read(&mut buf[..len]) {
+            Ok(0) => bail!("Content ended after {} of {} bytes", content.len(), size),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context("Failed to read content"),
+        };
+        content.extend_from_slice(&buf[..n]);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolWriter;
+
+    #[test]
+    fn test_receive_data_bulk_resumable() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_mime("text/html").unwrap();
+        writer.write_content(b"<b>VERY GOOD</b>").unwrap();
+        let buf = writer.into_inner();
+
+        // Interrupted in the second content
+        let cut = buf.len() - 6;
+        let mut state = ResumeState::default();
+        assert!(receive_data_bulk_resumable(&buf[..cut], &mut state).is_err());
+        assert_eq!(state.items_received(), 1);
+        assert_eq!(state.mime_types_received(), 1);
+        assert_eq!(state.content_offset(), 10);
+
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_resume(state.content_offset()).unwrap();
+        writer.write_content(&b"<b>VERY GOOD</b>"[10..]).unwrap();
+        let resumed = writer.into_inner();
+        let r = receive_data_bulk_resumable(&resumed[..], &mut state).unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert_eq!(r[1].mime_type, ["text/html"]);
+        assert_eq!(r[1].content.as_slice(), b"<b>VERY GOOD</b>");
+        // Reset after the completion
+        assert_eq!(state.items_received(), 0);
+
+        // Uninterrupted
+        let r = receive_data_bulk_resumable(&buf[..], &mut state).unwrap();
+        assert_eq!(r.len(), 2);
+    }
+
+    #[test]
+    fn test_resume_between_sections() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        let buf = writer.into_inner();
+        let mut state = ResumeState::default();
+        // Pending mime-type without content
+        assert!(receive_data_bulk_resumable(&buf[..], &mut state).is_err());
+        assert_eq!(state.mime_types_received(), 1);
+        assert_eq!(state.content_offset(), 0);
+
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_resume(0).unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let r = receive_data_bulk_resumable(&writer.into_inner()[..], &mut state).unwrap();
+        assert_eq!(r[0].mime_type, ["TEXT"]);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+    }
+
+    #[test]
+    fn test_resume_errors() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+        let cut = buf.len() - 2;
+
+        let resume = |offset: u64, rest: &[u8]| {
+            let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+            writer.write_resume(offset).unwrap();
+            writer.write_content(rest).unwrap();
+            writer.into_inner()
+        };
+        let mut state = ResumeState::default();
+        receive_data_bulk_resumable(&buf[..cut], &mut state).unwrap_err();
+        // Wrong offset
+        assert!(receive_data_bulk_resumable(&resume(1, b"OD")[..], &mut state).is_err());
+        // Wrong remaining size
+        assert!(receive_data_bulk_resumable(&resume(2, b"ODD")[..], &mut state).is_err());
+        // Without the resume section
+        assert!(receive_data_bulk_resumable(&buf[..], &mut state).is_err());
+        // The state is still usable
+        let r = receive_data_bulk_resumable(&resume(2, b"OD")[..], &mut state).unwrap();
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+
+        // Not expected in a new or a normal stream
+        assert!(receive_data_bulk_resumable(&resume(0, b"GOOD")[..], &mut state).is_err());
+        assert!(crate::protocol::receive_data_bulk(&resume(0, b"GOOD")[..]).is_err());
+    }
+
+    #[test]
+    fn test_receive_data_bulk_resumable_with_config() {
+        let config = RecvConfig {
+            named_sections: NamedSections::Collect,
+            max_section_bytes: 32,
+            ..Default::default()
+        };
+        let mut writer = ProtocolWriter::with_item_count(Vec::new(), 1).unwrap();
+        writer.write_named("source", b"terminal").unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+        let mut state = ResumeState::default();
+        let cut = buf.len() - 2;
+        receive_data_bulk_resumable_with_config(&buf[..cut], &mut state, &config).unwrap_err();
+
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_resume(2).unwrap();
+        writer.write_content(b"OD").unwrap();
+        let resumed = writer.into_inner();
+        let r = receive_data_bulk_resumable_with_config(&resumed[..], &mut state, &config).unwrap();
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert_eq!(r[0].extensions["source"], b"terminal");
+
+        // The count of the first header is checked at the end
+        let mut twice = buf.clone();
+        twice.extend_from_slice(&buf[9..]);
+        let e = receive_data_bulk_resumable_with_config(
+            &twice[..],
+            &mut ResumeState::default(),
+            &config,
+        )
+        .unwrap_err();
+        assert_eq!(e.to_string(), "Expected 1 items, but received 2");
+
+        // The declared size is checked before reserving it
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(&[b'A'; 33]).unwrap();
+        let buf = writer.into_inner();
+        let e =
+            receive_data_bulk_resumable_with_config(&buf[..], &mut ResumeState::default(), &config)
+                .unwrap_err();
+        assert_eq!(e.to_string(), "Content size 33 exceeds the limit 32");
+    }
+}
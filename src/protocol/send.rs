@@ -0,0 +1,312 @@
+use super::capabilities::Capabilities;
+use super::recv::{MAGIC, PROTOCAL_VER, PROTOCAL_VER_COUNTED};
+use super::request::COMMAND_KEY;
+#[cfg(feature = "compression")]
+use super::source_data::entropy;
+use super::source_data::{SourceDataItem, validate_mime_type};
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Bits per byte, see `SourceDataItem::compute_entropy`.
+#[cfg(feature = "compression")]
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.5;
+
+/// Write the data in the format which `receive_data_bulk` parses.
+pub struct ProtocolWriter<W: Write> {
+    writer: W,
+    last_write: Instant,
+}
+
+impl<W: Write> ProtocolWriter<W> {
+    /// Create the writer and write the magic header and the protocol version.
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer
+            .write_all(&MAGIC)
+            .context("Failed to write magic header")?;
+        writer
+            .write_all(&[PROTOCAL_VER])
+            .context("Failed to write protocal version")?;
+        Ok(ProtocolWriter {
+            writer,
+            last_write: Instant::now(),
+        })
+    }
+
+    /// Create the writer with the `PROTOCAL_VER_COUNTED` header, announcing the number of the
+    /// items which will be written. The receiver fails if the number doesn't match.
+    pub fn with_item_count(mut writer: W, count: u32) -> Result<Self> {
+        writer
+            .write_all(&MAGIC)
+            .context("Failed to write magic header")?;
+        writer
+            .write_all(&[PROTOCAL_VER_COUNTED])
+            .context("Failed to write protocal version")?;
+        writer
+            .write_all(&count.to_be_bytes())
+            .context("Failed to write item count")?;
+        Ok(ProtocolWriter {
+            writer,
+            last_write: Instant::now(),
+        })
+    }
+
+    /// Create the writer without writing the magic header and the protocol version, for
+    /// `receive_data_bulk_without_header`.
+    pub fn without_header(writer: W) -> Self {
+        ProtocolWriter {
+            writer,
+            last_write: Instant::now(),
+        }
+    }
+
+    pub fn write_mime(&mut self, mime_type: &str) -> Result<()> {
+        validate_mime_type(mime_type)?;
+        self.write_section(b'M', mime_type.as_bytes())
+            .context("Failed to write mime type")
+    }
+
+    pub fn write_content(&mut self, content: &[u8]) -> Result<()> {
+        self.write_section(b'C', content)
+            .context("Failed to write content")
+    }
+
+    /// Write the content as a zlib compressed `Z` section, or as a normal `C` section if its
+    /// entropy is above `INCOMPRESSIBLE_ENTROPY`, since it is probably compressed already.
+    #[cfg(feature = "compression")]
+    pub fn write_compressed_content(&mut self, content: &[u8]) -> Result<()> {
+        if entropy(content) > INCOMPRESSIBLE_ENTROPY {
+            log::debug!("Content is not compressible, write it as it is");
+            return self.write_content(content);
+        }
+        let compressed = super::compress::compress(content)?;
+        self.write_section(b'Z', &compressed)
+            .context("Failed to write compressed content")
+    }
+
+    /// Write all the mime-types of the item followed by its content.
+    pub fn write_item(&mut self, item: &SourceDataItem) -> Result<()> {
+        for mime_type in &item.mime_type {
+            self.write_mime(mime_type)?;
+        }
+        self.write_content(&item.content)
+    }
+
+    /// Same as `write_item`, with the `SourceDataItem::extensions` of the item written as `K`
+    /// (named) sections before it, in the order of their keys.
+    pub fn write_item_extended(&mut self, item: &SourceDataItem) -> Result<()> {
+        let mut keys: Vec<_> = item.extensions.keys().collect();
+        keys.sort();
+        for key in keys {
+            self.write_named(key, &item.extensions[key])?;
+        }
+        self.write_item(item)
+    }
+
+    /// Write the `N` section announcing the capabilities, see `Capabilities`.
+    pub fn write_capabilities(&mut self, capabilities: &Capabilities) -> Result<()> {
+        self.write_section(b'N', &capabilities.encode())
+            .context("Failed to write capabilities")
+    }
+
+    /// Write a `K` (named) section, a key-value extension of the next item. See
+    /// `receive_data_bulk_extended`.
+    pub fn write_named(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        let key_len = u32::try_from(key.len())
+            .with_context(|| format!("Key length {} is too large", key.len()))?;
+        let mut data = Vec::with_capacity(4 + key.len() + value.len());
+        data.extend_from_slice(&key_len.to_be_bytes());
+        data.extend_from_slice(key.as_bytes());
+        data.extend_from_slice(value);
+        self.write_section(b'K', &data)
+            .context("Failed to write named section")
+    }
+
+    /// Write a command to the server instead of items, see `Request::Command`.
+    pub fn write_command(&mut self, name: &str) -> Result<()> {
+        self.write_named(COMMAND_KEY, name.as_bytes())
+    }
+
+    /// Write the `R` section which starts a resumed stream, see `receive_data_bulk_resumable`.
+    pub fn write_resume(&mut self, offset: u64) -> Result<()> {
+        self.write_section(b'R', &offset.to_be_bytes())
+            .context("Failed to write resume section")
+    }
+
+    /// Write a zero-length `H` section which the receiver ignores.
+    pub fn write_heartbeat(&mut self) -> Result<()> {
+        self.write_section(b'H', &[])?;
+        self.writer.flush().context("Failed to write heartbeat")
+    }
+
+    /// Write a heartbeat if nothing has been written for `idle`. Returns true if it is written.
+    /// Long-lived connections can call this periodically to tell the peer they are alive.
+    pub fn heartbeat_if_idle(&mut self, idle: Duration) -> Result<bool> {
+        if self.last_write.elapsed() < idle {
+            return Ok(false);
+        }
+        self.write_heartbeat()?;
+        Ok(true)
+    }
+
+    /// Flush the underlying writer, e.g. to pass an item on before the next one arrives.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Failed to flush")
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_section(&mut self, flag: u8, data: &[u8]) -> Result<()> {
+        let size = u32::try_from(data.len())
+            .with_context(|| format!("Section size {} is too large", data.len()))?;
+        self.writer.write_all(&[flag])?;
+        self.writer.write_all(&size.to_be_bytes())?;
+        self.writer.write_all(data)?;
+        self.last_write = Instant::now();
+        Ok(())
+    }
+}
+
+/// Write the `M` and `C` sections of the items only, without the magic header and the protocol
+/// version, for embedding in another protocol which does the framing.
+pub fn encode_to_writer_without_header(items: &[SourceDataItem], writer: impl Write) -> Result<()> {
+    let mut writer = ProtocolWriter::without_header(writer);
+    for item in items {
+        writer.write_item(item)?;
+    }
+    writer.writer.flush().context("Failed to flush the writer")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::NullByteInMimeType;
+    use crate::protocol::{receive_data_bulk, receive_data_bulk_without_header};
+
+    #[test]
+    fn test_protocol_writer() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("text/plain").unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer
+            .write_item(&SourceDataItem::new(
+                vec!["text/html".to_string()],
+                b"BAD".to_vec(),
+            ))
+            .unwrap();
+        let buf = writer.into_inner();
+
+        #[rustfmt::skip]
+        let expected =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 10, b't', b'e', b'x', b't', b'/', b'p', b'l', b'a', b'i', b'n',
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'M', 0, 0, 0, 9, b't', b'e', b'x', b't', b'/', b'h', b't', b'm', b'l',
+            b'C', 0, 0, 0, 3, b'B', b'A', b'D',
+            ];
+        assert_eq!(buf, expected);
+
+        let r = receive_data_bulk(&buf[..]).unwrap();
+        assert_eq!(r.len(), 2);
+    }
+
+    #[test]
+    fn test_with_item_count() {
+        let item = SourceDataItem::new(vec!["TEXT".to_string()], b"GOOD".to_vec());
+        let write = |count: u32, items: usize| {
+            let mut writer = ProtocolWriter::with_item_count(Vec::new(), count).unwrap();
+            for _ in 0..items {
+                writer.write_item(&item).unwrap();
+            }
+            writer.into_inner()
+        };
+
+        let buf = write(2, 2);
+        assert_eq!(buf[..9], [0x20, 0x09, 0x02, 0x14, 1, 0, 0, 0, 2]);
+        let r = receive_data_bulk(&buf[..]).unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[1].content.as_slice(), b"GOOD");
+        let r = receive_data_bulk(&write(0, 0)[..]).unwrap();
+        assert!(r.is_empty());
+
+        // The count disagrees with the items
+        let err = receive_data_bulk(&write(3, 2)[..]).unwrap_err();
+        assert_eq!(err.to_string(), "Expected 3 items, but received 2");
+        assert!(receive_data_bulk(&write(1, 2)[..]).is_err());
+
+        let items: Vec<_> = crate::protocol::receive_data_stream(&write(1, 1)[..])
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(items.len(), 1);
+
+        // Only the version 0 for the little-endian variant
+        assert!(crate::protocol::receive_data_bulk_le(&write(1, 1)[..]).is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_if_idle() {
+        let idle = Duration::from_millis(20);
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        assert!(!writer.heartbeat_if_idle(idle).unwrap());
+
+        std::thread::sleep(idle);
+        assert!(writer.heartbeat_if_idle(idle).unwrap());
+        // Heartbeat counts as a write
+        assert!(!writer.heartbeat_if_idle(idle).unwrap());
+        writer.write_content(b"GOOD").unwrap();
+
+        let buf = writer.into_inner();
+        #[rustfmt::skip]
+        let expected =
+            [0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'H', 0, 0, 0, 0,
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            ];
+        assert_eq!(buf, expected);
+        let r = receive_data_bulk(&buf[..]).unwrap();
+        assert_eq!(r.len(), 1);
+    }
+
+    #[test]
+    fn test_write_mime_null_byte() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        let r = writer.write_mime("text\0plain");
+        let err = r.unwrap_err();
+        let err = err.downcast_ref::<NullByteInMimeType>().unwrap();
+        assert_eq!(err.position, 4);
+        // Nothing has been written
+        assert_eq!(writer.into_inner().len(), 5);
+    }
+
+    #[test]
+    fn test_encode_to_writer_without_header() {
+        let items = [
+            SourceDataItem::new(vec!["TEXT".to_string()], b"GOOD".to_vec()),
+            SourceDataItem::new(vec!["HTML".to_string(), "html".to_string()], b"".to_vec()),
+        ];
+        let mut buf = Vec::new();
+        encode_to_writer_without_header(&items, &mut buf).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+            b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+            b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L',
+            b'M', 0, 0, 0, 4, b'h', b't', b'm', b'l',
+            b'C', 0, 0, 0, 0,
+        ];
+        assert_eq!(buf, expected);
+
+        let r = receive_data_bulk_without_header(&buf[..]).unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert_eq!(r[1].mime_type, vec!["HTML", "html"]);
+        assert!(receive_data_bulk(&buf[..]).is_err());
+    }
+}
@@ -1,8 +1,440 @@
+use crate::clipboard::is_text_mime_type;
+use anyhow::{Result, bail};
+use glob::{MatchOptions, Pattern};
+use std::collections::HashMap;
 use std::rc::Rc;
 
+/// The content of the clipboard offered as one or more mime-types.
+/// Construct it with `SourceDataItem::new`, more fields may be added later.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
 pub struct SourceDataItem {
     pub mime_type: Vec<String>,
     pub content: Rc<Vec<u8>>,
+    /// The named sections of the item by their keys, only collected by
+    /// `receive_data_bulk_extended`.
+    pub extensions: HashMap<String, Vec<u8>>,
+}
+
+/// Mime-types are not allowed to contain null bytes, see `validate_mime_type`.
+#[derive(Debug)]
+pub struct NullByteInMimeType {
+    pub mime_type: String,
+    /// Byte offset of the first null byte
+    pub position: usize,
+}
+
+impl std::fmt::Display for NullByteInMimeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Null byte at position {} in mime type {:?}",
+            self.position, self.mime_type
+        )
+    }
+}
+
+impl std::error::Error for NullByteInMimeType {}
+
+/// Reject mime-types with embedded null bytes, they would corrupt the parsers which treat them
+/// as null-terminated strings.
+pub(super) fn validate_mime_type(mime_type: &str) -> Result<()> {
+    if let Some(position) = mime_type.bytes().position(|b| b == 0) {
+        return Err(NullByteInMimeType {
+            mime_type: mime_type.to_string(),
+            position,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The mime-types kept first by `SourceDataItem::apply_mime_type_cap`, from the highest priority.
+const MIME_TYPE_PRIORITY: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "TEXT",
+    "STRING",
+    "text/html",
+    "image/png",
+];
+
+impl SourceDataItem {
+    pub fn new(mime_type: Vec<String>, content: impl Into<Rc<Vec<u8>>>) -> Self {
+        SourceDataItem {
+            mime_type,
+            content: content.into(),
+            extensions: HashMap::new(),
+        }
+    }
+
+    /// A fast hash of the content, using the same mixing as rustc's FxHash.
+    /// This is NOT a cryptographic hash and trivially collides for crafted input. Two items with
+    /// different fingerprints have different contents, but the same fingerprint only means the
+    /// contents are probably equal. Compare the contents when it matters.
+    pub fn fingerprint(&self) -> u64 {
+        fingerprint_content(&self.content)
+    }
+
+    /// The Shannon entropy of the content in bits per byte, from 0 to 8. Already compressed data,
+    /// like JPEG, is close to 8 and won't shrink by compressing it again.
+    pub fn compute_entropy(&self) -> f64 {
+        entropy(&self.content)
+    }
+
+    /// Rotate the mime-types so the first occurrence of `pivot` becomes the first one, keeping
+    /// the order of the others as in a ring. Returns false if `pivot` is not found.
+    pub fn rotate_mime_types(&mut self, pivot: &str) -> bool {
+        match self.mime_type.iter().position(|t| t == pivot) {
+            Some(pos) => {
+                self.mime_type.rotate_left(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether any of the mime-types is a text type.
+    pub fn is_text(&self) -> bool {
+        has_text_mime_type(self.mime_type.iter().map(String::as_str))
+    }
+
+    /// Strip the leading and trailing ASCII whitespace from the content of a text item. Non-text
+    /// items, and text items without such whitespace, are returned as they are, sharing the
+    /// content.
+    pub fn trim_content(&self) -> SourceDataItem {
+        let trimmed = self.content.trim_ascii();
+        if !self.is_text() || trimmed.len() == self.content.len() {
+            return self.clone();
+        }
+        SourceDataItem::new(self.mime_type.clone(), Rc::new(trimmed.to_vec()))
+    }
+
+    /// Truncate the content to at most `max` bytes, e.g. for previews, the rest is discarded.
+    /// The content of a text item is cut at the last UTF-8 character boundary at or before `max`,
+    /// so no character is split. The item is returned as it is if the content already fits.
+    pub fn clamp_content_size(&self, max: usize) -> SourceDataItem {
+        if self.content.len() <= max {
+            return self.clone();
+        }
+        let mut end = max;
+        if self.is_text() {
+            // At most 3 continuation bytes precede the boundary in valid UTF-8
+            while end > 0 && max - end < 3 && (self.content[end] & 0xC0) == 0x80 {
+                end -= 1;
+            }
+            if (self.content[end] & 0xC0) == 0x80 {
+                end = max;
+            }
+        }
+        SourceDataItem::new(self.mime_type.clone(), self.content[..end].to_vec())
+    }
+
+    /// Keep at most `max_mime_types` mime-types, for sinks which can only offer a few of them.
+    /// The ones in the default priority list are kept first, e.g. `text/plain` before `TEXT`,
+    /// then the others in their order. The kept ones stay in their original order.
+    pub fn apply_mime_type_cap(&self, max_mime_types: usize) -> SourceDataItem {
+        if self.mime_type.len() <= max_mime_types {
+            return self.clone();
+        }
+        let rank = |t: &str| {
+            MIME_TYPE_PRIORITY
+                .iter()
+                .position(|p| p.eq_ignore_ascii_case(t))
+                .unwrap_or(MIME_TYPE_PRIORITY.len())
+        };
+        let mut by_priority: Vec<usize> = (0..self.mime_type.len()).collect();
+        by_priority.sort_by_key(|i| rank(&self.mime_type[*i]));
+        let mut kept = by_priority[..max_mime_types].to_vec();
+        kept.sort();
+        SourceDataItem {
+            mime_type: kept.iter().map(|i| self.mime_type[*i].clone()).collect(),
+            content: self.content.clone(),
+            extensions: self.extensions.clone(),
+        }
+    }
+
+    /// The same content offered with the mime-types of both items, e.g. when two backends provide
+    /// the same content with different mime-types. The ones of `self` come first, then the ones
+    /// of `other` which are not among them, the duplicates are removed.
+    pub fn union_mime_types(&self, other: &SourceDataItem) -> SourceDataItem {
+        let mut mime_type: Vec<String> = vec![];
+        for t in self.mime_type.iter().chain(&other.mime_type) {
+            if !mime_type.contains(t) {
+                mime_type.push(t.clone());
+            }
+        }
+        SourceDataItem {
+            mime_type,
+            content: self.content.clone(),
+            extensions: self.extensions.clone(),
+        }
+    }
+
+    /// Extract the plain text from a `text/html` item: the tags and comments are removed and the
+    /// `&amp;`, `&lt;`, `&gt;` and `&quot;` entities are decoded, other entities are kept as
+    /// they are. This is a simple state machine rather than an HTML parser, e.g. the contents of
+    /// `<script>` are kept. Returns `None` for other items.
+    pub fn strip_html_tags(&self) -> Option<SourceDataItem> {
+        let is_html = self.mime_type.iter().any(|t| {
+            let t = t.to_ascii_lowercase();
+            t == "text/html" || t.starts_with("text/html;")
+        });
+        if !is_html {
+            return None;
+        }
+
+        enum State {
+            Text,
+            Tag(Option<u8>),
+            Comment,
+        }
+        const ENTITIES: [(&[u8], u8); 4] = [
+            (b"&amp;", b'&'),
+            (b"&lt;", b'<'),
+            (b"&gt;", b'>'),
+            (b"&quot;", b'"'),
+        ];
+        let html = self.content.as_slice();
+        let mut text = Vec::with_capacity(html.len());
+        let mut state = State::Text;
+        let mut i = 0;
+        while i < html.len() {
+            let b = html[i];
+            match state {
+                State::Text if html[i..].starts_with(b"<!--") => {
+                    state = State::Comment;
+                    i += 4;
+                    continue;
+                }
+                State::Text if b == b'<' => state = State::Tag(None),
+                State::Text if b == b'&' => {
+                    if let Some((entity, c)) =
+                        ENTITIES.iter().find(|(e, _)| html[i..].starts_with(e))
+                    {
+                        text.push(*c);
+                        i += entity.len();
+                        continue;
+                    }
+                    text.push(b);
+                }
+                State::Text => text.push(b),
+                // A `>` in a quoted attribute value doesn't end the tag
+                State::Tag(None) if b == b'"' || b == b'\'' => state = State::Tag(Some(b)),
+                State::Tag(None) if b == b'>' => state = State::Text,
+                State::Tag(Some(quote)) if b == quote => state = State::Tag(None),
+                State::Tag(_) => {}
+                State::Comment if html[i..].starts_with(b"-->") => {
+                    state = State::Text;
+                    i += 3;
+                    continue;
+                }
+                State::Comment => {}
+            }
+            i += 1;
+        }
+        Some(SourceDataItem::new(vec!["text/plain".to_string()], text))
+    }
+
+    /// The mime-types as glob patterns, so types like `image/*` match any image type. A
+    /// mime-type which is not a valid pattern only matches itself.
+    pub fn mime_types_as_glob_patterns(&self) -> Vec<GlobPattern> {
+        self.mime_type
+            .iter()
+            .map(|t| GlobPattern::new(t).unwrap_or_else(|_| GlobPattern::literal(t)))
+            .collect()
+    }
+
+    /// Whether `mime_type` matches any of the mime-types used as glob patterns, see
+    /// `mime_types_as_glob_patterns`.
+    pub fn matches_any(&self, mime_type: &str) -> bool {
+        self.mime_types_as_glob_patterns()
+            .iter()
+            .any(|p| p.matches(mime_type))
+    }
+
+    /// Take the content out of the item. If the item is the only owner of the content, it is
+    /// moved out without copying. Otherwise the content is still used by other items and has to
+    /// be copied, borrow `content` instead then.
+    pub fn into_content(self) -> Vec<u8> {
+        Rc::unwrap_or_clone(self.content)
+    }
+}
+
+/// A mime-type glob pattern like `image/*` or `text/plain;charset=*`, matched
+/// case-insensitively as mime-types are.
+#[derive(Clone, Debug)]
+pub struct GlobPattern(Pattern);
+
+impl GlobPattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        Ok(GlobPattern(Pattern::new(pattern)?))
+    }
+
+    /// A pattern matching the text itself only, even if it contains `*`, `?` or `[`.
+    pub fn literal(text: &str) -> Self {
+        GlobPattern(Pattern::new(&Pattern::escape(text)).unwrap())
+    }
+
+    pub fn matches(&self, mime_type: &str) -> bool {
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        self.0.matches_with(mime_type, options)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+/// See `SourceDataItem::compute_entropy`.
+pub(super) fn entropy(data: &[u8]) -> f64 {
+    let mut histogram = [0usize; 256];
+    for b in data {
+        histogram[*b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    histogram
+        .iter()
+        .filter(|n| **n > 0)
+        .map(|n| {
+            let p = *n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// `SourceDataItem::fingerprint` of the content without an item.
+pub fn fingerprint_content(content: &[u8]) -> u64 {
+    const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+    let add = |hash: u64, word: u64| (hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+
+    let mut chunks = content.chunks_exact(8);
+    let mut hash = chunks.by_ref().fold(0, |hash, chunk| {
+        add(hash, u64::from_le_bytes(chunk.try_into().unwrap()))
+    });
+    for b in chunks.remainder() {
+        hash = add(hash, *b as u64);
+    }
+    // Mix the length in so trailing zero bytes still count.
+    add(hash, content.len() as u64)
+}
+
+/// Parse the fingerprint in hex, as it is printed with `{:016x}`.
+pub fn parse_fingerprint(s: &str) -> Result<u64, String> {
+    u64::from_str_radix(s, 16).map_err(|_| format!("'{s}' is not a fingerprint in hex digits"))
+}
+
+/// Combine the fingerprints of all the items, see `SourceDataItem::fingerprint`.
+pub fn fingerprint_items(items: &[SourceDataItem]) -> u64 {
+    items.iter().fold(items.len() as u64, |hash, item| {
+        hash.rotate_left(7) ^ item.fingerprint()
+    })
+}
+
+/// The mime-types offered by every one of the items, in the order of the first item.
+pub fn common_types(items: &[SourceDataItem]) -> Vec<String> {
+    let Some((first, rest)) = items.split_first() else {
+        return vec![];
+    };
+    first
+        .mime_type
+        .iter()
+        .filter(|t| rest.iter().all(|item| item.mime_type.contains(t)))
+        .cloned()
+        .collect()
+}
+
+/// The required mime-types which none of the items offers, in the order they are required.
+pub fn missing_types(items: &[SourceDataItem], required: &[String]) -> Vec<String> {
+    required
+        .iter()
+        .filter(|t| !items.iter().any(|item| item.mime_type.contains(t)))
+        .cloned()
+        .collect()
+}
+
+/// Drop the items whose content is larger than `max_size` bytes.
+pub fn drop_oversized(items: Vec<SourceDataItem>, max_size: usize) -> Vec<SourceDataItem> {
+    items
+        .into_iter()
+        .filter(|item| {
+            let fits = item.content.len() <= max_size;
+            if !fits {
+                log::debug!(
+                    "Drop {:?} of {} bytes, larger than {max_size} bytes",
+                    item.mime_type,
+                    item.content.len()
+                );
+            }
+            fits
+        })
+        .collect()
+}
+
+/// The mime-types of the text items whose content is not valid UTF-8.
+pub fn non_utf8_text_types(items: &[SourceDataItem]) -> Vec<String> {
+    items
+        .iter()
+        .filter(|item| item.is_text() && std::str::from_utf8(&item.content).is_err())
+        .flat_map(|item| item.mime_type.iter().cloned())
+        .collect()
+}
+
+/// Warn if any text item is not valid UTF-8, listing the mime-types of them. With `strict`, it
+/// fails instead.
+pub fn check_utf8_text(items: &[SourceDataItem], strict: bool) -> Result<()> {
+    let types = non_utf8_text_types(items);
+    if types.is_empty() {
+        return Ok(());
+    }
+    let msg = format!("The text content is not valid UTF-8: {}", types.join(", "));
+    if strict {
+        bail!(msg);
+    }
+    log::warn!("{msg}");
+    Ok(())
+}
+
+// Above this the quadratic edit distance gets too slow to be used on every clipboard change.
+const MAX_EDIT_DISTANCE_BYTES: usize = 10 * 1024;
+
+/// The Levenshtein distance between the text contents of the two items, counted in characters.
+/// `None` is returned if either item is not text, not valid UTF-8, or larger than 10KB.
+pub fn text_edit_distance(a: &SourceDataItem, b: &SourceDataItem) -> Option<usize> {
+    let text_of = |item: &SourceDataItem| {
+        if item.content.len() > MAX_EDIT_DISTANCE_BYTES || !item.is_text() {
+            return None;
+        }
+        std::str::from_utf8(&item.content)
+            .ok()
+            .map(|s| s.chars().collect::<Vec<char>>())
+    };
+    let (a, b) = (text_of(a)?, text_of(b)?);
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+
+    // Only the previous row of the DP table is needed to compute the current one
+    let mut prev: Vec<usize> = (0..=short.len()).collect();
+    let mut cur = vec![0; short.len() + 1];
+    for (i, lc) in long.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, sc) in short.iter().enumerate() {
+            let substitution = prev[j] + usize::from(lc != sc);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    Some(prev[short.len()])
+}
+
+/// Whether any of the mime-types is a text type, see `SourceDataItem::is_text`.
+pub(super) fn has_text_mime_type<'a>(mime_types: impl IntoIterator<Item = &'a str>) -> bool {
+    mime_types
+        .into_iter()
+        .any(|t| !t.is_empty() && is_text_mime_type(t))
 }
 
 pub trait SourceData {
@@ -59,6 +491,338 @@ mod tests {
     use crate::protocol::PROTOCAL_VER;
     use crate::protocol::receive_data_bulk;
 
+    #[test]
+    fn test_rotate_mime_types() {
+        let mut item = SourceDataItem::new(
+            ["a", "b", "c", "d", "c"].map(String::from).to_vec(),
+            Rc::new(vec![]),
+        );
+        assert!(item.rotate_mime_types("c"));
+        assert_eq!(item.mime_type, ["c", "d", "c", "a", "b"]);
+        assert!(item.rotate_mime_types("c"));
+        assert_eq!(item.mime_type, ["c", "d", "c", "a", "b"]);
+        assert!(!item.rotate_mime_types("e"));
+        assert_eq!(item.mime_type, ["c", "d", "c", "a", "b"]);
+    }
+
+    #[test]
+    fn test_into_content() {
+        let item = SourceDataItem::new(vec!["text/plain".to_string()], Rc::new(b"GOOD".to_vec()));
+        let ptr = item.content.as_ptr();
+        let shared = item.clone();
+
+        // Copied while shared
+        let content = item.into_content();
+        assert_eq!(content, b"GOOD");
+        assert_ne!(content.as_ptr(), ptr);
+        assert_eq!(shared.content.as_ptr(), ptr);
+
+        // Moved out when it is the only owner
+        let content = shared.into_content();
+        assert_eq!(content, b"GOOD");
+        assert_eq!(content.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_trim_content() {
+        let item = SourceDataItem::new(
+            vec!["text/plain".to_string()],
+            Rc::new(b"\n\t  hello  \r\n".to_vec()),
+        );
+        let r = item.trim_content();
+        assert_eq!(r.mime_type, vec!["text/plain"]);
+        assert_eq!(r.content.as_slice(), b"hello");
+        // Nothing to trim
+        assert!(Rc::ptr_eq(&r.trim_content().content, &r.content));
+
+        // Interior whitespace is kept
+        let item = SourceDataItem::new(
+            vec!["TEXT".to_string()],
+            Rc::new(b" \thello \t\n world\r\n".to_vec()),
+        );
+        assert_eq!(item.trim_content().content.as_slice(), b"hello \t\n world");
+
+        let item = SourceDataItem::new(
+            vec!["image/png".to_string()],
+            Rc::new(b" \x89PNG\n".to_vec()),
+        );
+        assert_eq!(item.trim_content().content.as_slice(), b" \x89PNG\n");
+    }
+
+    #[test]
+    fn test_clamp_content_size() {
+        let text = SourceDataItem::new(vec!["text/plain".to_string()], "aé€😀".as_bytes().to_vec());
+        let clamped: Vec<usize> = (0..=11)
+            .map(|max| text.clamp_content_size(max).content.len())
+            .collect();
+        assert_eq!(clamped, [0, 1, 1, 3, 3, 3, 6, 6, 6, 6, 10, 10]);
+        assert_eq!(text.clamp_content_size(3).mime_type, vec!["text/plain"]);
+        assert!(Rc::ptr_eq(
+            &text.clamp_content_size(10).content,
+            &text.content
+        ));
+
+        // Binary is cut at the size
+        let binary = SourceDataItem::new(vec!["image/png".to_string()], "aé€".as_bytes().to_vec());
+        assert_eq!(binary.clamp_content_size(2).content.as_slice(), b"a\xc3");
+
+        // Not UTF-8 at all
+        let bad = SourceDataItem::new(vec!["text/plain".to_string()], vec![0x80; 8]);
+        assert_eq!(bad.clamp_content_size(5).content.len(), 5);
+    }
+
+    #[test]
+    fn test_apply_mime_type_cap() {
+        let item = SourceDataItem::new(
+            [
+                "TEXT",
+                "x-custom",
+                "STRING",
+                "text/plain",
+                "UTF8_STRING",
+                "other",
+            ]
+            .iter()
+            .map(|t| t.to_string())
+            .collect(),
+            b"GOOD".to_vec(),
+        );
+        assert_eq!(
+            item.apply_mime_type_cap(2).mime_type,
+            ["text/plain", "UTF8_STRING"]
+        );
+        assert_eq!(
+            item.apply_mime_type_cap(4).mime_type,
+            ["TEXT", "STRING", "text/plain", "UTF8_STRING"]
+        );
+        // The others keep their order
+        assert_eq!(
+            item.apply_mime_type_cap(5).mime_type,
+            ["TEXT", "x-custom", "STRING", "text/plain", "UTF8_STRING"]
+        );
+        assert_eq!(item.apply_mime_type_cap(6).mime_type, item.mime_type);
+        assert!(item.apply_mime_type_cap(0).mime_type.is_empty());
+        assert!(Rc::ptr_eq(
+            &item.apply_mime_type_cap(1).content,
+            &item.content
+        ));
+    }
+
+    #[test]
+    fn test_union_mime_types() {
+        let types = |t: &[&str]| t.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let a = SourceDataItem::new(
+            types(&["text/plain", "TEXT", "text/plain"]),
+            b"GOOD".to_vec(),
+        );
+        let b = SourceDataItem::new(types(&["UTF8_STRING", "TEXT", "STRING"]), b"BAD".to_vec());
+        let u = a.union_mime_types(&b);
+        assert_eq!(u.mime_type, ["text/plain", "TEXT", "UTF8_STRING", "STRING"]);
+        assert!(Rc::ptr_eq(&u.content, &a.content));
+        assert_eq!(
+            b.union_mime_types(&a).mime_type,
+            ["UTF8_STRING", "TEXT", "STRING", "text/plain"]
+        );
+    }
+
+    #[test]
+    fn test_strip_html_tags() {
+        let html = |content: &str| {
+            SourceDataItem::new(vec!["text/html".to_string()], content.as_bytes().to_vec())
+        };
+        let strip = |item: SourceDataItem| {
+            let stripped = item.strip_html_tags().unwrap();
+            assert_eq!(stripped.mime_type, vec!["text/plain"]);
+            String::from_utf8(stripped.content.to_vec()).unwrap()
+        };
+        assert_eq!(
+            strip(html("<p class=\"a\">Tom &amp; <b>Jerry</b></p>")),
+            "Tom & Jerry"
+        );
+        assert_eq!(
+            strip(html("&lt;b&gt; &quot;é&quot; &amp;lt; &nbsp;")),
+            "<b> \"é\" &lt; &nbsp;"
+        );
+        // Quoted `>` and comments
+        assert_eq!(
+            strip(html(
+                "<a title='1 > 0' href=\">\">GOOD</a><!-- <b>x</b> -->!"
+            )),
+            "GOOD!"
+        );
+        // Unclosed tag
+        assert_eq!(strip(html("GOOD<br")), "GOOD");
+        let charset = SourceDataItem::new(
+            vec!["TEXT".to_string(), "text/html;charset=utf-8".to_string()],
+            b"<i>GOOD</i>".to_vec(),
+        );
+        assert_eq!(strip(charset), "GOOD");
+
+        let text = SourceDataItem::new(vec!["text/plain".to_string()], b"<i>GOOD</i>".to_vec());
+        assert!(text.strip_html_tags().is_none());
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let item = SourceDataItem::new(
+            ["image/*", "text/plain;charset=*", "x-[odd", "TEXT"]
+                .map(String::from)
+                .to_vec(),
+            vec![],
+        );
+        let patterns = item.mime_types_as_glob_patterns();
+        assert_eq!(patterns.len(), 4);
+        assert!(patterns[0].matches("image/png"));
+        assert!(!patterns[0].matches("text/png"));
+        assert!(patterns[1].matches("text/plain;charset=utf-8"));
+        // Invalid pattern matches literally
+        assert!(patterns[2].matches("x-[odd"));
+        assert!(GlobPattern::new("x-[odd").is_err());
+
+        assert!(item.matches_any("IMAGE/JPEG"));
+        assert!(item.matches_any("text"));
+        assert!(!item.matches_any("text/html"));
+        assert_eq!(GlobPattern::literal("a*").as_str(), "a[*]");
+    }
+
+    #[test]
+    fn test_check_utf8_text() {
+        let items = vec![
+            SourceDataItem::new(
+                vec!["text/plain".to_string(), "TEXT".to_string()],
+                Rc::new("héllo".as_bytes().to_vec()),
+            ),
+            SourceDataItem::new(vec!["text/html".to_string()], Rc::new(b"h\xe9llo".to_vec())),
+            // Not text
+            SourceDataItem::new(vec!["image/png".to_string()], Rc::new(b"\x89PNG".to_vec())),
+        ];
+        assert_eq!(non_utf8_text_types(&items), ["text/html"]);
+        check_utf8_text(&items, false).unwrap();
+        let e = check_utf8_text(&items, true).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "The text content is not valid UTF-8: text/html"
+        );
+        check_utf8_text(&items[..1], true).unwrap();
+    }
+
+    #[test]
+    fn test_drop_oversized() {
+        let item = |mime_type: &str, size: usize| {
+            SourceDataItem::new(vec![mime_type.to_string()], Rc::new(vec![0; size]))
+        };
+        let items = vec![item("image/png", 1000), item("image/jpeg", 100)];
+        let r = drop_oversized(items.clone(), 100);
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].mime_type, vec!["image/jpeg"]);
+        assert_eq!(drop_oversized(items.clone(), 1000).len(), 2);
+        assert!(drop_oversized(items, 99).is_empty());
+    }
+
+    #[test]
+    fn test_common_types() {
+        let item = |mime_types: &[&str]| {
+            SourceDataItem::new(
+                mime_types.iter().map(|s| s.to_string()).collect(),
+                Rc::new(vec![]),
+            )
+        };
+        let items = [
+            item(&["text/plain", "TEXT", "text/html"]),
+            item(&["text/html", "text/plain"]),
+            item(&["text/plain", "text/html", "STRING"]),
+        ];
+        assert_eq!(common_types(&items), vec!["text/plain", "text/html"]);
+
+        let items = [item(&["text/plain"]), item(&["image/png"])];
+        assert!(common_types(&items).is_empty());
+        assert!(common_types(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_missing_types() {
+        let item = |mime_types: &[&str]| {
+            SourceDataItem::new(
+                mime_types.iter().map(|s| s.to_string()).collect(),
+                Rc::new(vec![]),
+            )
+        };
+        let required = |types: &[&str]| types.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let items = [item(&["text/plain", "TEXT"]), item(&["text/html"])];
+        // Across the items
+        assert!(missing_types(&items, &required(&["text/plain", "text/html"])).is_empty());
+        assert_eq!(
+            missing_types(&items, &required(&["image/png", "text/html", "STRING"])),
+            ["image/png", "STRING"]
+        );
+        assert_eq!(missing_types(&[], &required(&["TEXT"])), ["TEXT"]);
+    }
+
+    #[test]
+    fn test_text_edit_distance() {
+        let item = |mime_type: &str, content: &[u8]| {
+            SourceDataItem::new(vec![mime_type.to_string()], Rc::new(content.to_vec()))
+        };
+        let text = |content: &str| item("text/plain", content.as_bytes());
+        assert_eq!(text_edit_distance(&text("GOOD"), &text("GOOD")), Some(0));
+        assert_eq!(text_edit_distance(&text("GOOD"), &text("GOOD!")), Some(1));
+        assert_eq!(
+            text_edit_distance(&text("kitten"), &text("sitting")),
+            Some(3)
+        );
+        assert_eq!(text_edit_distance(&text(""), &text("abc")), Some(3));
+        // Counted in characters rather than bytes
+        assert_eq!(text_edit_distance(&text("café"), &text("cafe")), Some(1));
+
+        assert_eq!(
+            text_edit_distance(&text("GOOD"), &item("image/png", b"GOOD")),
+            None
+        );
+        assert_eq!(
+            text_edit_distance(&text("GOOD"), &item("text/plain", b"\xff")),
+            None
+        );
+        let large = "a".repeat(MAX_EDIT_DISTANCE_BYTES + 1);
+        assert_eq!(text_edit_distance(&text("a"), &text(&large)), None);
+    }
+
+    #[test]
+    fn test_compute_entropy() {
+        let item = |content: Vec<u8>| SourceDataItem::new(vec!["TEXT".to_string()], content);
+        assert_eq!(item(vec![]).compute_entropy(), 0.0);
+        assert_eq!(item(vec![b'A'; 100]).compute_entropy(), 0.0);
+        assert_eq!(item(b"ABAB".to_vec()).compute_entropy(), 1.0);
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(item(all_bytes.repeat(4)).compute_entropy(), 8.0);
+        let text = item(b"The quick brown fox jumps over the lazy dog".to_vec());
+        assert!((3.0..5.0).contains(&text.compute_entropy()));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let item = |content: &[u8]| {
+            SourceDataItem::new(vec!["text/plain".to_string()], Rc::new(content.to_vec()))
+        };
+        let a = item(b"Some content longer than 8 bytes");
+        assert_eq!(
+            a.fingerprint(),
+            item(b"Some content longer than 8 bytes").fingerprint()
+        );
+        assert_ne!(
+            a.fingerprint(),
+            item(b"Some content longer than 8 bytez").fingerprint()
+        );
+        assert_ne!(item(b"").fingerprint(), item(b"\0").fingerprint());
+        assert_ne!(item(b"ab").fingerprint(), item(b"ba").fingerprint());
+        assert_eq!(a.fingerprint(), fingerprint_content(&a.content));
+
+        let hex = format!("{:016x}", a.fingerprint());
+        assert_eq!(parse_fingerprint(&hex), Ok(a.fingerprint()));
+        assert_eq!(parse_fingerprint("00ff"), Ok(0xff));
+        assert!(parse_fingerprint("xyz").is_err());
+        assert!(parse_fingerprint("").is_err());
+    }
+
     #[test]
     fn test_content_by_mime_type() {
         #[rustfmt::skip]
@@ -0,0 +1,217 @@
+use super::recv::{
+    RecvConfig, check_item_count, default_alloc, read_header_with_count, read_next_item,
+};
+use super::source_data::SourceDataItem;
+use anyhow::Result;
+use std::io::Read;
+use std::rc::{Rc, Weak};
+
+/// Parses the items one by one, see `receive_data_stream`. It stops after the first error.
+pub struct ItemStream<R: Read> {
+    reader: R,
+    config: RecvConfig,
+    count: Option<u32>,
+    received: usize,
+    done: bool,
+}
+
+/// Same as `receive_data_bulk`, but the items are parsed lazily while iterating, so only the
+/// items which are still held are kept in memory. If the header has an item count, a mismatch
+/// is returned as the last item.
+pub fn receive_data_stream<R: Read>(mut reader: R) -> Result<ItemStream<R>> {
+    let count = read_header_with_count(&mut reader)?;
+    Ok(ItemStream {
+        reader,
+        config: RecvConfig::default(),
+        count,
+        received: 0,
+        done: false,
+    })
+}
+
+impl<R: Read> ItemStream<R> {
+    /// Turn it into a `BoundedItemStream`, which holds at most one item in memory.
+    pub fn bounded(self) -> BoundedItemStream<R> {
+        BoundedItemStream {
+            stream: self,
+            last_content: Weak::new(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for ItemStream<R> {
+    type Item = Result<SourceDataItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let ret = read_next_item(
+            &mut self.reader,
+            &self.config,
+            &mut default_alloc,
+            self.received > 0,
+        );
+        match ret {
+            Ok(Some(_)) => self.received += 1,
+            Ok(None) => {
+                self.done = true;
+                return check_item_count(self.count, self.received).err().map(Err);
+            }
+            Err(_) => self.done = true,
+        }
+        ret.transpose()
+    }
+}
+
+/// Returned by `BoundedItemStream` when the content of the previous item is still held.
+#[derive(Debug)]
+pub struct ItemStillHeld;
+
+impl std::fmt::Display for ItemStillHeld {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The previous item must be dropped before parsing the next one"
+        )
+    }
+}
+
+impl std::error::Error for ItemStillHeld {}
+
+/// Same as `ItemStream`, but it refuses to parse the next item with `ItemStillHeld` while the
+/// content of the previous one, or any clone of it, is still alive. So the items in memory,
+/// whether held by the consumer or not, never exceed one.
+/// Handle every item within the body of the loop, it is dropped before the next one is parsed:
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// for item in richclip::protocol::receive_data_stream(std::io::stdin())?.bounded() {
+///     let item = item?;
+///     println!("{:?}: {} bytes", item.mime_type, item.content.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// `ItemStillHeld` is returned once and ends the stream like any other error, so collecting the
+/// items, e.g. with `collect::<Vec<_>>()`, stops there instead of retrying forever.
+pub struct BoundedItemStream<R: Read> {
+    stream: ItemStream<R>,
+    last_content: Weak<Vec<u8>>,
+}
+
+impl<R: Read> Iterator for BoundedItemStream<R> {
+    type Item = Result<SourceDataItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.stream.done {
+            return None;
+        }
+        if self.last_content.strong_count() > 0 {
+            self.stream.done = true;
+            return Some(Err(ItemStillHeld.into()));
+        }
+        let ret = self.stream.next();
+        if let Some(Ok(item)) = &ret {
+            self.last_content = Rc::downgrade(&item.content);
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PROTOCAL_VER, ProtocolWriter};
+
+    #[rustfmt::skip]
+    fn stream_data() -> Vec<u8> {
+        vec![0x20, 0x09, 0x02, 0x14, PROTOCAL_VER,
+        b'M', 0, 0, 0, 4, b'T', b'E', b'X', b'T',
+        b'C', 0, 0, 0, 4, b'G', b'O', b'O', b'D',
+        b'H', 0, 0, 0, 0,
+        b'M', 0, 0, 0, 4, b'H', b'T', b'M', b'L',
+        b'C', 0, 0, 0, 4, b'h', b't', b'm', b'l',
+        ]
+    }
+
+    #[test]
+    fn test_receive_data_stream() {
+        let buf = stream_data();
+        let items: Vec<SourceDataItem> = receive_data_stream(&buf[..])
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].mime_type, vec!["TEXT"]);
+        assert_eq!(items[1].content.as_slice(), b"html");
+
+        // Stops after the error
+        let mut stream = receive_data_stream(&buf[..buf.len() - 1]).unwrap();
+        assert!(stream.next().unwrap().is_ok());
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+
+        assert!(receive_data_stream(&buf[1..]).is_err());
+    }
+
+    #[test]
+    fn test_receive_data_stream_counted() {
+        let mut writer = ProtocolWriter::with_item_count(Vec::new(), 2).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        writer.write_mime("HTML").unwrap();
+        writer.write_content(b"html").unwrap();
+        let buf = writer.into_inner();
+        let items: Vec<SourceDataItem> = receive_data_stream(&buf[..])
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(items.len(), 2);
+
+        // The mismatch ends the stream
+        let mut stream = receive_data_stream(&buf[..buf.len() - 18]).unwrap();
+        assert!(stream.next().unwrap().is_ok());
+        let err = stream.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Expected 2 items, but received 1");
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_bounded_item_stream() {
+        let buf = stream_data();
+        let mut stream = receive_data_stream(&buf[..]).unwrap().bounded();
+        let first = stream.next().unwrap().unwrap();
+        assert_eq!(first.content.as_slice(), b"GOOD");
+
+        // Held by a clone of the content
+        let content = first.content.clone();
+        drop(first);
+        let e = stream.next().unwrap().unwrap_err();
+        assert!(e.downcast_ref::<ItemStillHeld>().is_some());
+        // Ended, even after the content is dropped
+        assert!(stream.next().is_none());
+        drop(content);
+        assert!(stream.next().is_none());
+
+        // Collecting without dropping the items stops at the error
+        let r: Vec<_> = receive_data_stream(&buf[..]).unwrap().bounded().collect();
+        assert_eq!(r.len(), 2);
+        assert!(r[0].is_ok());
+        assert!(
+            r[1].as_ref()
+                .unwrap_err()
+                .downcast_ref::<ItemStillHeld>()
+                .is_some()
+        );
+
+        // Dropped within the loop
+        let mut count = 0;
+        for item in receive_data_stream(&buf[..]).unwrap().bounded() {
+            item.unwrap();
+            count += 1;
+        }
+        assert_eq!(count, 2);
+    }
+}
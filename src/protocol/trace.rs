@@ -0,0 +1,41 @@
+use super::recv::receive_data_bulk;
+use super::source_data::SourceDataItem;
+use anyhow::Result;
+use std::io::Read;
+
+/// Same as `receive_data_bulk`, but the parsing runs in `span`, e.g. the span of the request
+/// handler the stream comes from, instead of whatever span is current on the receiving thread.
+/// The span is entered for the whole stream, so every section is parsed in it. The internal
+/// events are `log` records, they become events of the span when they are forwarded to
+/// `tracing`, e.g. with `tracing_log::LogTracer`.
+pub fn receive_data_bulk_in_span(
+    reader: impl Read,
+    span: tracing::Span,
+) -> Result<Vec<SourceDataItem>> {
+    let _entered = span.enter();
+    let r = receive_data_bulk(reader);
+    match &r {
+        Ok(items) => tracing::debug!(items = items.len(), "Received items"),
+        Err(e) => tracing::debug!(error = %e, "Failed to receive items"),
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolWriter;
+
+    #[test]
+    fn test_receive_data_bulk_in_span() {
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+
+        let span = tracing::debug_span!("request", id = 1);
+        let r = receive_data_bulk_in_span(&buf[..], span).unwrap();
+        assert_eq!(r[0].content.as_slice(), b"GOOD");
+        assert!(receive_data_bulk_in_span(&buf[1..], tracing::Span::none()).is_err());
+    }
+}
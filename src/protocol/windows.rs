@@ -0,0 +1,320 @@
+use super::source_data::SourceDataItem;
+use anyhow::{Context, Result, bail};
+
+pub const CF_TEXT: u32 = 1;
+pub const CF_UNICODETEXT: u32 = 13;
+pub const CF_HDROP: u32 = 15;
+/// "HTML Format" is not a predefined clipboard format, the real ID is only known after calling
+/// `RegisterClipboardFormatW` at runtime. This value, the first one of the registered range,
+/// stands for it and must be mapped to the registered ID by the caller.
+pub const CF_HTML: u32 = 0xC000;
+
+const TEXT_MIME_TYPES: &[&str] = &[
+    "text/plain",
+    "text/plain;charset=utf-8",
+    "TEXT",
+    "STRING",
+    "UTF8_STRING",
+];
+const HTML_MIME_TYPE: &str = "text/html";
+const URI_LIST_MIME_TYPE: &str = "text/uri-list";
+
+// Size of the DROPFILES struct: pFiles, pt.x, pt.y, fNC and fWide.
+const DROPFILES_SIZE: usize = 20;
+
+impl SourceDataItem {
+    /// Translate the item to the Windows clipboard formats. Text becomes both `CF_TEXT` and
+    /// `CF_UNICODETEXT`, `text/html` becomes `CF_HTML` and `text/uri-list` becomes `CF_HDROP`
+    /// with the local files in it. Mime-types without a Windows counterpart are ignored.
+    pub fn to_windows_clipboard_format(&self) -> Vec<(u32, Vec<u8>)> {
+        let mut ret: Vec<(u32, Vec<u8>)> = vec![];
+        let mut push = |format: u32, data: Vec<u8>| {
+            if !ret.iter().any(|(f, _)| *f == format) {
+                ret.push((format, data));
+            }
+        };
+        let text = String::from_utf8_lossy(&self.content);
+        for mime_type in &self.mime_type {
+            if TEXT_MIME_TYPES
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(mime_type))
+            {
+                push(CF_TEXT, encode_ansi(&text));
+                push(CF_UNICODETEXT, encode_utf16(&text));
+            } else if mime_type.eq_ignore_ascii_case(HTML_MIME_TYPE) {
+                push(CF_HTML, encode_html(&text));
+            } else if mime_type.eq_ignore_ascii_case(URI_LIST_MIME_TYPE) {
+                let paths: Vec<String> = text.lines().filter_map(uri_to_path).collect();
+                if !paths.is_empty() {
+                    push(CF_HDROP, encode_hdrop(&paths));
+                }
+            }
+        }
+        ret
+    }
+
+    /// The inverse of `to_windows_clipboard_format`, for one format read from the clipboard.
+    pub fn from_windows_clipboard_format(format: u32, data: &[u8]) -> Result<SourceDataItem> {
+        let (mime_type, content) = match format {
+            CF_TEXT => {
+                let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+                (vec!["text/plain".to_string()], data[..end].to_vec())
+            }
+            CF_UNICODETEXT => (
+                vec![
+                    "text/plain;charset=utf-8".to_string(),
+                    "text/plain".to_string(),
+                ],
+                decode_utf16(data)?.into_bytes(),
+            ),
+            CF_HTML => (vec![HTML_MIME_TYPE.to_string()], decode_html(data)?),
+            CF_HDROP => {
+                let mut uri_list = String::new();
+                for path in decode_hdrop(data)? {
+                    uri_list.push_str(&path_to_uri(&path));
+                    uri_list.push_str("\r\n");
+                }
+                (vec![URI_LIST_MIME_TYPE.to_string()], uri_list.into_bytes())
+            }
+            _ => bail!("Unsupported Windows clipboard format {format}"),
+        };
+        Ok(SourceDataItem::new(mime_type, content))
+    }
+}
+
+// Non-ASCII characters are replaced with '?', since the code page of the receiver is unknown.
+fn encode_ansi(text: &str) -> Vec<u8> {
+    let mut ret: Vec<u8> = text
+        .chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+        .collect();
+    ret.push(0);
+    ret
+}
+
+fn encode_utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+fn decode_utf16(data: &[u8]) -> Result<String> {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|u| *u != 0)
+        .collect();
+    String::from_utf16(&units).context("Invalid UTF-16 text")
+}
+
+// The header offsets are zero padded to a fixed width, so the header length doesn't depend on
+// the values.
+fn encode_html(html: &str) -> Vec<u8> {
+    const PREFIX: &str = "<html><body><!--StartFragment-->";
+    const SUFFIX: &str = "<!--EndFragment--></body></html>";
+    let header_len = format_html_header(0, 0, 0, 0).len();
+    let start_html = header_len;
+    let start_fragment = start_html + PREFIX.len();
+    let end_fragment = start_fragment + html.len();
+    let end_html = end_fragment + SUFFIX.len();
+    let mut ret = format_html_header(start_html, end_html, start_fragment, end_fragment);
+    ret.push_str(PREFIX);
+    ret.push_str(html);
+    ret.push_str(SUFFIX);
+    ret.into_bytes()
+}
+
+fn format_html_header(
+    start_html: usize,
+    end_html: usize,
+    start_fragment: usize,
+    end_fragment: usize,
+) -> String {
+    format!(
+        "Version:0.9\r\nStartHTML:{start_html:010}\r\nEndHTML:{end_html:010}\r\n\
+        StartFragment:{start_fragment:010}\r\nEndFragment:{end_fragment:010}\r\n"
+    )
+}
+
+// Only the fragment is returned, the surrounding document is added by the clipboard owner.
+fn decode_html(data: &[u8]) -> Result<Vec<u8>> {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    let data = &data[..end];
+    let header_value = |key: &str| -> Result<usize> {
+        let text = String::from_utf8_lossy(data);
+        let value = text
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+            .with_context(|| format!("No {key} in the CF_HTML header"))?;
+        value
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid {key} in the CF_HTML header"))
+    };
+    let start = header_value("StartFragment")?;
+    let end = header_value("EndFragment")?;
+    if start > end || end > data.len() {
+        bail!("Invalid fragment range {start}..{end} in the CF_HTML data");
+    }
+    Ok(data[start..end].to_vec())
+}
+
+// A DROPFILES struct followed by the paths in UTF-16, each one terminated by a null character,
+// and the list terminated by an extra one.
+fn encode_hdrop(paths: &[String]) -> Vec<u8> {
+    let mut ret = Vec::new();
+    ret.extend((DROPFILES_SIZE as u32).to_le_bytes());
+    ret.extend(0i32.to_le_bytes());
+    ret.extend(0i32.to_le_bytes());
+    ret.extend(0u32.to_le_bytes());
+    // fWide, the paths are in UTF-16
+    ret.extend(1u32.to_le_bytes());
+    for path in paths {
+        ret.extend(encode_utf16(path));
+    }
+    ret.extend(0u16.to_le_bytes());
+    ret
+}
+
+fn decode_hdrop(data: &[u8]) -> Result<Vec<String>> {
+    if data.len() < DROPFILES_SIZE {
+        bail!("CF_HDROP data is too short ({} bytes)", data.len());
+    }
+    let read_u32 = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+    let offset = read_u32(0) as usize;
+    let wide = read_u32(16) != 0;
+    if offset > data.len() {
+        bail!("Invalid file list offset {offset} in CF_HDROP data");
+    }
+    let list = &data[offset..];
+    let paths: Vec<String> = if wide {
+        let units: Vec<u16> = list
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        units
+            .split(|u| *u == 0)
+            .take_while(|p| !p.is_empty())
+            .map(String::from_utf16)
+            .collect::<Result<_, _>>()
+            .context("Invalid UTF-16 path in CF_HDROP data")?
+    } else {
+        list.split(|b| *b == 0)
+            .take_while(|p| !p.is_empty())
+            .map(|p| String::from_utf8_lossy(p).into_owned())
+            .collect()
+    };
+    Ok(paths)
+}
+
+// Only local files can be dropped, other URIs and comments yield `None`.
+fn uri_to_path(uri: &str) -> Option<String> {
+    let path = uri.trim().strip_prefix("file://")?;
+    // Skip the host part, which is normally empty or "localhost"
+    let path = &path[path.find('/')?..];
+    let decoded = percent_decode(path)?;
+    // "/C:/dir/file" to "C:\dir\file"
+    let decoded = match decoded.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &decoded[1..],
+        _ => &decoded[..],
+    };
+    Some(decoded.replace('/', "\\"))
+}
+
+fn path_to_uri(path: &str) -> String {
+    let mut ret = String::from("file://");
+    if !path.starts_with(['\\', '/']) {
+        ret.push('/');
+    }
+    for b in path.bytes() {
+        match b {
+            b'\\' => ret.push('/'),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                ret.push(b as char)
+            }
+            _ => ret.push_str(&format!("%{b:02X}")),
+        }
+    }
+    ret
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            ret.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            ret.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(ret).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(mime_type: &str, content: &str) -> SourceDataItem {
+        SourceDataItem::new(vec![mime_type.to_string()], content.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_text_to_windows_format() {
+        let formats = item("text/plain", "GOOD é").to_windows_clipboard_format();
+        assert_eq!(formats.len(), 2);
+        assert_eq!(formats[0], (CF_TEXT, b"GOOD ?\0".to_vec()));
+        assert_eq!(formats[1].0, CF_UNICODETEXT);
+
+        let r = SourceDataItem::from_windows_clipboard_format(CF_UNICODETEXT, &formats[1].1);
+        assert_eq!(r.unwrap().content.as_slice(), "GOOD é".as_bytes());
+        let r = SourceDataItem::from_windows_clipboard_format(CF_TEXT, &formats[0].1);
+        assert_eq!(r.unwrap().content.as_slice(), b"GOOD ?");
+    }
+
+    #[test]
+    fn test_html_to_windows_format() {
+        let formats = item("text/html", "<b>GOOD</b>").to_windows_clipboard_format();
+        assert_eq!(formats.len(), 1);
+        let (format, data) = &formats[0];
+        assert_eq!(*format, CF_HTML);
+        assert!(data.starts_with(b"Version:0.9\r\nStartHTML:0000000105\r\n"));
+
+        let r = SourceDataItem::from_windows_clipboard_format(CF_HTML, data).unwrap();
+        assert_eq!(r.mime_type, vec!["text/html"]);
+        assert_eq!(r.content.as_slice(), b"<b>GOOD</b>");
+    }
+
+    #[test]
+    fn test_uri_list_to_windows_format() {
+        let uri_list = "# comment\r\nfile:///C:/My%20Files/a.txt\r\nhttps://x.org\r\n";
+        let formats = item("text/uri-list", uri_list).to_windows_clipboard_format();
+        assert_eq!(formats.len(), 1);
+        let (format, data) = &formats[0];
+        assert_eq!(*format, CF_HDROP);
+        assert_eq!(decode_hdrop(data).unwrap(), vec!["C:\\My Files\\a.txt"]);
+
+        let r = SourceDataItem::from_windows_clipboard_format(CF_HDROP, data).unwrap();
+        assert_eq!(r.content.as_slice(), b"file:///C:/My%20Files/a.txt\r\n");
+
+        // No local files
+        let formats = item("text/uri-list", "https://x.org").to_windows_clipboard_format();
+        assert!(formats.is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_windows_format() {
+        assert!(
+            item("image/png", "")
+                .to_windows_clipboard_format()
+                .is_empty()
+        );
+        assert!(SourceDataItem::from_windows_clipboard_format(2, b"").is_err());
+        assert!(SourceDataItem::from_windows_clipboard_format(CF_HDROP, b"\0\0").is_err());
+    }
+}
@@ -0,0 +1,564 @@
+use crate::clipboard::{self, ClipboardSink, CopyConfig, sort_by_priority};
+use crate::daemon::{Command, Daemon};
+use crate::protocol::{
+    Capabilities, HealthMonitoredReader, ProtocolWriter, ReaderHealth, RecvConfig, Request,
+    SourceData, SourceDataItem, receive_request,
+};
+use anyhow::{Context, Result, bail};
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fs::Permissions;
+use std::io::{ErrorKind, Read};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The name of the command which re-offers the held content, see `Command::Touch`.
+pub const TOUCH: &str = "touch";
+
+/// Parse a permission mode in octal like `0600`.
+pub fn parse_socket_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("'{s}' is not an octal permission mode like 0600")),
+    }
+}
+
+/// Bind the Unix socket at `path`, with the given permission mode and group.
+/// The socket is bound at a temporary path next to `path` first, and only moved to `path` when
+/// its permissions are set, so no other user can connect in between. A stale socket left at
+/// `path` is replaced, but it fails if another server is still listening on it.
+pub fn bind_socket(path: &Path, mode: Option<u32>, group: Option<&str>) -> Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        bail!(
+            "Another server is already listening on '{}'",
+            path.display()
+        );
+    }
+    let gid = group.map(group_id).transpose()?;
+
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Invalid socket path '{}'", path.display()))?;
+    let tmp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&tmp_path);
+    let listener = UnixListener::bind(&tmp_path)
+        .with_context(|| format!("Failed to bind the socket '{}'", tmp_path.display()))?;
+
+    let ret = (|| -> Result<()> {
+        if let Some(gid) = gid {
+            std::os::unix::fs::chown(&tmp_path, None, Some(gid))
+                .context("Failed to change the group of the socket")?;
+        }
+        if let Some(mode) = mode {
+            std::fs::set_permissions(&tmp_path, Permissions::from_mode(mode))
+                .context("Failed to set the permissions of the socket")?;
+        }
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to move the socket to '{}'", path.display()))?;
+        Ok(())
+    })();
+    if ret.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    ret.map(|_| listener)
+}
+
+fn group_id(name: &str) -> Result<u32> {
+    let c_name = CString::new(name).context("Invalid group name")?;
+    // SAFETY: The returned pointer is only used before the next call of `getgrnam`.
+    let group = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if group.is_null() {
+        bail!("No such group '{name}'");
+    }
+    Ok(unsafe { (*group).gr_gid })
+}
+
+/// Options of the connections of `richclip serve`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectionOptions {
+    /// Send a heartbeat to the client whenever nothing has been sent to it for this long, so it
+    /// and the monitors in between know the server is still alive. The replies start with the
+    /// magic header and the protocol version, like any other stream.
+    pub heartbeat: Option<Duration>,
+    /// Close the connection when nothing has been received from the client for this long, e.g.
+    /// it stopped in the middle of the stream, see `HealthMonitoredReader`.
+    pub stall_timeout: Option<Duration>,
+}
+
+/// Receive what a client sends with the bulk protocol, until it closes its writing side. It is
+/// either the items to copy, or the `TOUCH` command, see `receive_request`.
+/// If the client starts with the handshake, the `Capabilities::intersection` of its and
+/// `Capabilities::supported` is sent back and used for the rest of the stream, see
+/// `receive_data_bulk_negotiated`.
+pub fn receive_command(stream: &UnixStream, options: &ConnectionOptions) -> Result<Command> {
+    let replies = RefCell::new(Replies::new(stream, options.heartbeat.is_some()));
+    let reader = ConnectionReader::new(stream, &replies, options)?;
+    let request = receive_request(reader, &RecvConfig::default(), |client| {
+        let agreed = client.intersection(&Capabilities::supported());
+        let mut replies = replies.borrow_mut();
+        let writer = replies.writer().context("Failed to reply the handshake")?;
+        writer.write_capabilities(&agreed)?;
+        writer.flush()?;
+        Ok(agreed)
+    })?;
+    match request {
+        Request::Items(items) => Ok(Command::Set(items)),
+        Request::Command(name) if name == TOUCH => Ok(Command::Touch),
+        Request::Command(name) => bail!("Unknown command '{name}'"),
+    }
+}
+
+/// Send the command to the server listening on the socket, see `receive_command`.
+pub fn send_command(path: &Path, name: &str) -> Result<()> {
+    let stream = UnixStream::connect(path)
+        .with_context(|| format!("Failed to connect to '{}'", path.display()))?;
+    let mut writer = ProtocolWriter::new(&stream)?;
+    writer.write_command(name)?;
+    writer.flush()?;
+    stream
+        .shutdown(std::net::Shutdown::Write)
+        .context("Failed to close the connection")
+}
+
+/// Wait for the next connection for up to `timeout`, or forever if it is `None`. `None` is
+/// returned if no client connects in time.
+pub fn accept_timeout(
+    listener: &UnixListener,
+    timeout: Option<Duration>,
+) -> Result<Option<UnixStream>> {
+    let mut fds = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+    // SAFETY: `fds` is a valid array of one `pollfd`.
+    match unsafe { libc::poll(&mut fds, 1, timeout) } {
+        0 => return Ok(None),
+        -1 => {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(e).context("Failed to wait for the connection");
+        }
+        _ => {}
+    }
+    let (stream, _) = listener
+        .accept()
+        .context("Failed to accept the connection")?;
+    Ok(Some(stream))
+}
+
+/// Handle the next connection of `richclip serve`, or touch the held content of `daemon` when
+/// `next_touch` is due, then it is moved `reassert` later. It waits for the connection until
+/// `next_touch` at most, but a connected client delays the touch until it is received, or
+/// `ConnectionOptions::stall_timeout` closes it. The errors are logged.
+pub fn handle_next<S: ClipboardSink>(
+    listener: &UnixListener,
+    options: &ConnectionOptions,
+    daemon: &mut Daemon<S>,
+    reassert: Option<Duration>,
+    next_touch: &mut Option<Instant>,
+) {
+    let timeout = next_touch.map(|at| at.saturating_duration_since(Instant::now()));
+    let command = match accept_timeout(listener, timeout) {
+        // The connection is closed before the sink forks, the client doesn't wait for it
+        Ok(Some(stream)) => match receive_command(&stream, options) {
+            Ok(command) => Some(command),
+            Err(e) => {
+                log::warn!("Failed to receive data from the client: {e:#}");
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            log::warn!("{e:#}");
+            None
+        }
+    };
+    if let Some(command) = command
+        && let Err(e) = daemon.handle(command)
+    {
+        log::error!("Failed to copy to clipboard: {e:#}");
+    }
+    if let (Some(at), Some(interval)) = (*next_touch, reassert)
+        && Instant::now() >= at
+    {
+        if let Err(e) = daemon.handle(Command::Touch) {
+            log::error!("Failed to reassert the clipboard: {e:#}");
+        }
+        *next_touch = Some(Instant::now() + interval);
+    }
+}
+
+/// Offer the items from a child process, which keeps serving the clipboard until another
+/// application, or the child of the next offer, takes it over. Unlike `BackendSink`, offering
+/// returns right away. Set `SIGCHLD` to be ignored to have the exited children reaped.
+pub struct ForkingSink {
+    pub use_primary: bool,
+}
+
+impl ClipboardSink for ForkingSink {
+    fn offer_with_priority(&mut self, items: &[(SourceDataItem, u16)]) -> Result<()> {
+        self.offer_source(Box::new(sort_by_priority(items)))
+    }
+
+    fn offer_source(&mut self, source: Box<dyn SourceData>) -> Result<()> {
+        match unsafe { libc::fork() } {
+            -1 => bail!("Failed to fork: {}", std::io::Error::last_os_error()),
+            0 => {
+                let copy_config = CopyConfig {
+                    source_data: source,
+                    use_primary: self.use_primary,
+                    x_chunk_size: 0,
+                };
+                let r = clipboard::create_backend().and_then(|b| b.copy(copy_config));
+                if let Err(e) = &r {
+                    log::error!("Failed to copy to clipboard: {e:#}");
+                }
+                std::process::exit(r.is_err() as i32);
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// The stream written back to the client, it starts with the first reply.
+struct Replies<'a> {
+    stream: &'a UnixStream,
+    writer: Option<ProtocolWriter<&'a UnixStream>>,
+    // The client has stopped reading
+    closed: bool,
+}
+
+impl<'a> Replies<'a> {
+    fn new(stream: &'a UnixStream, start: bool) -> Self {
+        let mut ret = Replies {
+            stream,
+            writer: None,
+            closed: false,
+        };
+        if start {
+            // Counted as a write, so the first heartbeat is due one interval later
+            let _ = ret.writer();
+        }
+        ret
+    }
+
+    fn writer(&mut self) -> Option<&mut ProtocolWriter<&'a UnixStream>> {
+        if self.writer.is_none() && !self.closed {
+            self.writer = ProtocolWriter::new(self.stream).ok();
+            self.closed = self.writer.is_none();
+        }
+        self.writer.as_mut()
+    }
+
+    fn heartbeat_if_idle(&mut self, interval: Duration) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+        if let Err(e) = writer.heartbeat_if_idle(interval) {
+            // Not being read doesn't matter, the client may not expect replies at all
+            log::debug!("Stopped sending heartbeats: {e:#}");
+            self.writer = None;
+            self.closed = true;
+        }
+    }
+}
+
+// Reads from the client, and wakes up in between to keep the connection alive, or to give up on
+// it when it stalls.
+struct ConnectionReader<'a> {
+    reader: HealthMonitoredReader<&'a UnixStream>,
+    replies: &'a RefCell<Replies<'a>>,
+    options: ConnectionOptions,
+}
+
+impl<'a> ConnectionReader<'a> {
+    fn new(
+        stream: &'a UnixStream,
+        replies: &'a RefCell<Replies<'a>>,
+        options: &ConnectionOptions,
+    ) -> Result<Self> {
+        // Wake up often enough to notice a stall soon after it happens
+        let wake_up = [options.heartbeat, options.stall_timeout.map(|t| t / 4)]
+            .into_iter()
+            .flatten()
+            .min();
+        if let Some(interval) = wake_up {
+            stream
+                .set_read_timeout(Some(interval.max(Duration::from_millis(1))))
+                .context("Failed to set the read timeout")?;
+        }
+        let stalled_after = options.stall_timeout.unwrap_or(Duration::MAX);
+        Ok(ConnectionReader {
+            reader: HealthMonitoredReader::new(stream, stalled_after / 2, stalled_after),
+            replies,
+            options: *options,
+        })
+    }
+
+    fn keep_alive(&mut self) {
+        if let Some(interval) = self.options.heartbeat {
+            self.replies.borrow_mut().heartbeat_if_idle(interval);
+        }
+    }
+}
+
+impl Read for ConnectionReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            self.keep_alive();
+            match self.reader.read(buf) {
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                r => return r,
+            }
+            if let ReaderHealth::Stalled { since } = self.reader.health_check() {
+                return Err(std::io::Error::other(format!(
+                    "Nothing received from the client for {since:?}"
+                )));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::receive_data_bulk;
+    use std::os::unix::fs::FileTypeExt;
+
+    fn set_items(command: Command) -> Vec<SourceDataItem> {
+        match command {
+            Command::Set(items) => items,
+            Command::Touch => panic!("Not a set command"),
+        }
+    }
+
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("richclip-{}-{name}.sock", std::process::id()))
+    }
+
+    #[test]
+    fn test_bind_socket_mode() {
+        for mode in [0o600, 0o660] {
+            let path = socket_path(&format!("mode-{mode:o}"));
+            let _ = std::fs::remove_file(&path);
+            let listener = bind_socket(&path, Some(mode), None).unwrap();
+
+            let meta = std::fs::metadata(&path).unwrap();
+            assert!(meta.file_type().is_socket());
+            assert_eq!(meta.permissions().mode() & 0o777, mode);
+            // Still listening after being moved
+            UnixStream::connect(&path).unwrap();
+            listener.accept().unwrap();
+
+            // Already in use
+            assert!(bind_socket(&path, Some(mode), None).is_err());
+            drop(listener);
+            // Stale socket is replaced
+            bind_socket(&path, Some(mode), None).unwrap();
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_bind_socket_bad_group() {
+        let path = socket_path("group");
+        let r = bind_socket(&path, None, Some("no-such-group-for-richclip"));
+        assert!(r.is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_receive_command_heartbeat() {
+        use std::io::Write;
+
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let sent = buf.clone();
+        let handle = std::thread::spawn(move || {
+            client.write_all(&sent[..8]).unwrap();
+            // Idle in the middle of the stream
+            std::thread::sleep(Duration::from_millis(100));
+            client.write_all(&sent[8..]).unwrap();
+            client.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut replies = vec![];
+            client.read_to_end(&mut replies).unwrap();
+            replies
+        });
+        let options = ConnectionOptions {
+            heartbeat: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let items = set_items(receive_command(&server, &options).unwrap());
+        assert_eq!(items[0].content.as_slice(), b"GOOD");
+        drop(server);
+
+        // The replies are heartbeats only
+        let replies = handle.join().unwrap();
+        assert!(replies.len() > 5);
+        assert!(receive_data_bulk(&replies[..]).unwrap().is_empty());
+        assert!(replies[5..].chunks(5).all(|h| h == b"H\0\0\0\0"));
+
+        // Nothing is sent without the option
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(&buf).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let items = set_items(receive_command(&server, &ConnectionOptions::default()).unwrap());
+        assert_eq!(items.len(), 1);
+        drop(server);
+        let mut replies = vec![];
+        client.read_to_end(&mut replies).unwrap();
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn test_receive_command_handshake() {
+        use std::io::Write;
+
+        let client_caps = Capabilities {
+            compression: true,
+            digest: true,
+            length64: false,
+        };
+        let mut writer = ProtocolWriter::new(Vec::new()).unwrap();
+        writer.write_capabilities(&client_caps).unwrap();
+        writer.write_mime("TEXT").unwrap();
+        writer.write_content(b"GOOD").unwrap();
+        let buf = writer.into_inner();
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(&buf).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let items = set_items(receive_command(&server, &ConnectionOptions::default()).unwrap());
+        assert_eq!(items[0].content.as_slice(), b"GOOD");
+        drop(server);
+
+        let mut replies = vec![];
+        client.read_to_end(&mut replies).unwrap();
+        let agreed = crate::protocol::read_capabilities(&mut &replies[5..]).unwrap();
+        assert_eq!(agreed, client_caps.intersection(&Capabilities::supported()));
+        assert!(!agreed.digest);
+    }
+
+    #[test]
+    fn test_receive_command_stalled() {
+        use std::io::Write;
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let handle = std::thread::spawn(move || {
+            // Stops in the middle of a section
+            client
+                .write_all(&[0x20, 0x09, 0x02, 0x14, 0, b'M'])
+                .unwrap();
+            // Until the server closes the connection
+            let mut replies = vec![];
+            client.read_to_end(&mut replies).unwrap();
+        });
+        let options = ConnectionOptions {
+            stall_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let e = receive_command(&server, &options).unwrap_err();
+        assert!(format!("{e:#}").contains("Nothing received from the client"));
+        drop(server);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_touch_command() {
+        let path = socket_path("touch");
+        let _ = std::fs::remove_file(&path);
+        let listener = bind_socket(&path, None, None).unwrap();
+        assert!(
+            accept_timeout(&listener, Some(Duration::from_millis(10)))
+                .unwrap()
+                .is_none()
+        );
+
+        send_command(&path, TOUCH).unwrap();
+        let stream = accept_timeout(&listener, None).unwrap().unwrap();
+        let command = receive_command(&stream, &ConnectionOptions::default()).unwrap();
+        assert!(matches!(command, Command::Touch));
+
+        send_command(&path, "unknown").unwrap();
+        let stream = accept_timeout(&listener, None).unwrap().unwrap();
+        assert!(receive_command(&stream, &ConnectionOptions::default()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_handle_next_idle_client() {
+        struct MockSink {
+            offered: std::rc::Rc<std::cell::Cell<usize>>,
+        }
+
+        impl ClipboardSink for MockSink {
+            fn offer_with_priority(&mut self, _: &[(SourceDataItem, u16)]) -> Result<()> {
+                self.offered.set(self.offered.get() + 1);
+                Ok(())
+            }
+        }
+
+        let path = socket_path("idle");
+        let _ = std::fs::remove_file(&path);
+        let listener = bind_socket(&path, None, None).unwrap();
+        let options = ConnectionOptions {
+            stall_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let offered = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut daemon = Daemon::new(MockSink {
+            offered: offered.clone(),
+        });
+        let items = vec![SourceDataItem::new(
+            vec!["TEXT".to_string()],
+            b"GOOD".to_vec(),
+        )];
+        daemon.handle(Command::Set(items)).unwrap();
+
+        // Connected without sending anything, like a client which hung
+        let idle = UnixStream::connect(&path).unwrap();
+        let reassert = Duration::from_millis(10);
+        let mut next_touch = Some(Instant::now() + reassert);
+        let start = Instant::now();
+        while offered.get() < 2 {
+            assert!(start.elapsed() < Duration::from_secs(5), "Never touched");
+            handle_next(
+                &listener,
+                &options,
+                &mut daemon,
+                Some(reassert),
+                &mut next_touch,
+            );
+        }
+        // Closed by the stall timeout
+        let mut buf = vec![];
+        (&idle).read_to_end(&mut buf).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_socket_mode() {
+        assert_eq!(parse_socket_mode("0600"), Ok(0o600));
+        assert_eq!(parse_socket_mode("660"), Ok(0o660));
+        assert_eq!(parse_socket_mode("0o644"), Ok(0o644));
+        assert!(parse_socket_mode("0800").is_err());
+        assert!(parse_socket_mode("01777").is_err());
+        assert!(parse_socket_mode("rw").is_err());
+    }
+}
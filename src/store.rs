@@ -0,0 +1,174 @@
+use crate::protocol::{SourceData, SourceDataItem};
+use anyhow::{Context, Result, bail};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// An item whose content is kept in a `ContentStore`, only the metadata is in memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredItem {
+    pub mime_type: Vec<String>,
+    /// `SourceDataItem::fingerprint` of the content.
+    pub hash: u64,
+    pub size: usize,
+}
+
+impl StoredItem {
+    /// The name of the file the content is stored in.
+    fn file_name(&self) -> String {
+        format!("{:016x}-{}", self.hash, self.size)
+    }
+}
+
+/// A directory of contents keyed by their hash and size, so the same content is stored once no
+/// matter how many times it is copied.
+/// The fingerprint is not a cryptographic hash. Storing a content whose key is taken by a
+/// different content fails instead of overwriting it, and the content is verified when loaded.
+#[derive(Clone)]
+pub struct ContentStore {
+    dir: PathBuf,
+}
+
+impl ContentStore {
+    /// Use the directory as the store, it is created if missing.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create the store {}", dir.display()))?;
+        Ok(ContentStore { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write the content of the item to the store, unless it is there already.
+    pub fn put(&self, item: &SourceDataItem) -> Result<StoredItem> {
+        let stored = StoredItem {
+            mime_type: item.mime_type.clone(),
+            hash: item.fingerprint(),
+            size: item.content.len(),
+        };
+        let path = self.dir.join(stored.file_name());
+        match std::fs::read(&path) {
+            Ok(existing) if existing == *item.content => {
+                log::debug!("Content {} is already stored", stored.file_name());
+                return Ok(stored);
+            }
+            Ok(_) => bail!(
+                "Content {} is stored with different bytes",
+                stored.file_name()
+            ),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context("Failed to read the stored content"),
+        }
+        // Write to a temporary file first, so a partially written content is never loaded
+        let tmp = self.dir.join(format!(".{}.tmp", stored.file_name()));
+        std::fs::write(&tmp, item.content.as_slice())
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(stored)
+    }
+
+    /// Read the content of the item back from the store.
+    pub fn get(&self, stored: &StoredItem) -> Result<SourceDataItem> {
+        let path = self.dir.join(stored.file_name());
+        let content =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let item = SourceDataItem::new(stored.mime_type.clone(), Rc::new(content));
+        if item.content.len() != stored.size || item.fingerprint() != stored.hash {
+            bail!("Content {} is corrupted", stored.file_name());
+        }
+        Ok(item)
+    }
+}
+
+/// The stored items as a `SourceData`, whose contents are read from the store whenever they are
+/// requested, e.g. on every paste, instead of being held in memory.
+pub struct StoredSource {
+    pub store: ContentStore,
+    pub items: Vec<StoredItem>,
+}
+
+impl SourceData for StoredSource {
+    fn content_by_mime_type(&self, mime_type: &str) -> (bool, Rc<Vec<u8>>) {
+        let found = self.items.iter().find(|item| {
+            item.mime_type
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(mime_type))
+        });
+        match found.map(|item| self.store.get(item)) {
+            Some(Ok(item)) => (true, item.content),
+            Some(Err(e)) => {
+                log::error!("Failed to fetch the content of '{mime_type}': {e:#}");
+                (false, Rc::new(vec![]))
+            }
+            None => (false, Rc::new(vec![])),
+        }
+    }
+
+    fn mime_types(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .flat_map(|item| item.mime_type.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_store() {
+        let dir = std::env::temp_dir().join(format!("richclip-{}-store", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = ContentStore::new(&dir).unwrap();
+
+        let item = SourceDataItem::new(vec!["text/plain".to_string()], b"GOOD".to_vec());
+        let stored = store.put(&item).unwrap();
+        assert_eq!(stored.size, 4);
+        assert_eq!(stored.hash, item.fingerprint());
+        // The same content is stored once
+        let html = SourceDataItem::new(vec!["text/html".to_string()], b"GOOD".to_vec());
+        assert_eq!(store.put(&html).unwrap().hash, stored.hash);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let loaded = store.get(&stored).unwrap();
+        assert_eq!(loaded.mime_type, item.mime_type);
+        assert_eq!(loaded.content, item.content);
+
+        // A content modified on disk is detected
+        std::fs::write(dir.join(stored.file_name()), b"BAAD").unwrap();
+        assert!(store.get(&stored).is_err());
+        assert!(store.put(&item).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(store.get(&stored).is_err());
+    }
+
+    #[test]
+    fn test_stored_source() {
+        let dir = std::env::temp_dir().join(format!("richclip-{}-source", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = ContentStore::new(&dir).unwrap();
+        let item = SourceDataItem::new(
+            vec!["text/plain".to_string(), "TEXT".to_string()],
+            b"GOOD".to_vec(),
+        );
+        let source = StoredSource {
+            items: vec![store.put(&item).unwrap()],
+            store,
+        };
+        assert_eq!(source.mime_types(), ["text/plain", "TEXT"]);
+        let (found, content) = source.content_by_mime_type("text");
+        assert!(found);
+        assert_eq!(content.as_slice(), b"GOOD");
+        assert!(!source.content_by_mime_type("text/html").0);
+
+        // Fetched on every request
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!source.content_by_mime_type("TEXT").0);
+    }
+}
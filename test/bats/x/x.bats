@@ -49,6 +49,47 @@ teardown() {
     [ "$output" = "TestDaTA" ]
 }
 
+@test "X paste size only" {
+    printf "TestDaTA" | xclip -i -selection clipboard -target "image/png" 3>&-
+    run -0 "$RICHCLIP" paste -t "image/png" --size
+    [ "$output" = "8" ]
+}
+
+@test "X paste stats" {
+    printf "Some text\nsecond  line\n" | xclip -i -selection clipboard -target "text/plain" 3>&-
+    run -0 "$RICHCLIP" paste -t "text/plain" --stats
+    [ "$output" = "2 4 23" ]
+
+    printf "\x89PNG\x00\x01" | xclip -i -selection clipboard -target "image/png" 3>&-
+    run -0 "$RICHCLIP" paste -t "image/png" --stats
+    [ "$output" = "6" ]
+}
+
+@test "X paste fields" {
+    printf "a\tb\tc\n1\t2\t3\n" | xclip -i -selection clipboard -target "text/plain" 3>&-
+    run -0 "$RICHCLIP" paste -t "text/plain" --fields
+    [ "$output" = "$(printf "a,b,c\n1,2,3")" ]
+    run -0 "$RICHCLIP" paste -t "text/plain" --fields --ifs '\t' --ofs ' | '
+    [ "$output" = "$(printf "a | b | c\n1 | 2 | 3")" ]
+}
+
+@test "X paste changed since" {
+    printf "TestDaTA" | xclip -i -selection clipboard -target "text/plain" 3>&-
+    run -0 --separate-stderr "$RICHCLIP" paste --changed-since 0
+    [ "$output" = "TestDaTA" ]
+    hash="$stderr"
+    [ ${#hash} -eq 16 ]
+
+    # Unchanged
+    run -3 --separate-stderr "$RICHCLIP" paste --changed-since "$hash"
+    [ "$output" = "" ]
+
+    printf "TestDaTA2" | xclip -i -selection clipboard -target "text/plain" 3>&-
+    run -0 --separate-stderr "$RICHCLIP" paste --changed-since "$hash"
+    [ "$output" = "TestDaTA2" ]
+    [ "$stderr" != "$hash" ]
+}
+
 @test "X paste list mime-types only" {
     # xclip doesn't support multiple types
     echo "TestDaTA" | xclip -i -selection clipboard -target "some-type" 3>&-
@@ -83,3 +124,96 @@ teardown() {
     run -0 xclip -o -selection primary -target "text/html"
     [ "$output" = "BAD" ]
 }
+
+@test "X copy sniff code" {
+    printf "#!/usr/bin/env python\nprint(1)\n" | "$RICHCLIP" copy --one-shot --sniff-code 3>&-
+    run -0 xclip -o -selection clipboard -target TARGETS
+    [ "${lines[1]}" = "text/x-python" ]
+    [ "${lines[2]}" = "text/plain" ]
+    run -0 xclip -o -selection clipboard -target "text/x-python"
+    [ "$output" = "$(printf "#!/usr/bin/env python\nprint(1)")" ]
+
+    # Plain text is not changed
+    printf "GOOD" | "$RICHCLIP" copy --one-shot --sniff-code 3>&-
+    run -0 xclip -o -selection clipboard -target TARGETS
+    [ "${lines[1]}" = "text/plain" ]
+}
+
+@test "X copy echo" {
+    # To a file, the forked process doesn't keep a pipe open
+    printf "GOOD" | "$RICHCLIP" copy --one-shot --echo > "$BATS_TEST_TMPDIR/echo" 3>&-
+    [ "$(cat "$BATS_TEST_TMPDIR/echo")" = "GOOD" ]
+    run -0 xclip -o -selection clipboard
+    [ "$output" = "GOOD" ]
+}
+
+@test "X paste and copy chunk index" {
+    printf "GOOD DATA" | xclip -i -selection clipboard 3>&-
+    "$RICHCLIP" paste --chunk-size 4 --chunk-index > "$BATS_TEST_TMPDIR/chunks"
+    "$RICHCLIP" copy --chunk-index < "$BATS_TEST_TMPDIR/chunks" 3>&-
+    run -0 xclip -o -selection clipboard
+    [ "$output" = "GOOD DATA" ]
+
+    # Truncated before the end marker
+    head -c 10 "$BATS_TEST_TMPDIR/chunks" > "$BATS_TEST_TMPDIR/truncated"
+    run "$RICHCLIP" copy --chunk-index 3>&- < "$BATS_TEST_TMPDIR/truncated"
+    [ "$status" -ne 0 ]
+    run -0 xclip -o -selection clipboard
+    [ "$output" = "GOOD DATA" ]
+}
+
+@test "X copy hold for" {
+    SECONDS=0
+    printf "GOOD" | timeout 10 "$RICHCLIP" copy --foreground --hold-for 1s 3>&-
+    [ "$SECONDS" -lt 5 ]
+    # The selection is released after the process exits
+    run xclip -o -selection clipboard
+    [ "$output" != "GOOD" ]
+}
+
+@test "X paste and copy base64url" {
+    printf '\xfb\xff' | "$RICHCLIP" copy --one-shot --type "application/octet-stream" 3>&-
+    run -0 "$RICHCLIP" paste --type "application/octet-stream" --base64url
+    [ "$output" = "-_8" ]
+
+    # Padding is optional when decoding
+    printf "Zm8=" | "$RICHCLIP" copy --one-shot --base64url 3>&-
+    run -0 xclip -o -selection clipboard
+    [ "$output" = "fo" ]
+}
+
+@test "X paste binary to terminal" {
+    printf '\x89PNG\r\n\x1a\n' | "$RICHCLIP" copy --one-shot --type "image/png" 3>&-
+    # `script` gives it a terminal as stdout
+    run script -qec "\"$RICHCLIP\" paste --type image/png" /dev/null
+    [ "$status" -ne 0 ]
+    [[ "$output" == *"--binary"* ]]
+    run -0 script -qec "\"$RICHCLIP\" paste --type image/png --binary" /dev/null
+
+    # Not to a terminal
+    run -0 "$RICHCLIP" paste --type image/png
+}
+
+@test "X copy from dir" {
+    dir="$BATS_TEST_TMPDIR/items"
+    mkdir -p "$dir"
+    printf "GOOD" > "$dir/a.txt"
+    printf "<b>GOOD</b>" > "$dir/b.html"
+    "$RICHCLIP" copy --from-dir "$dir" 3>&-
+    run -0 xclip -o -selection clipboard -target TARGETS
+    [ "${lines[1]}" = "text/plain;charset=utf-8" ]
+    [ "${lines[2]}" = "text/html" ]
+    run -0 xclip -o -selection clipboard -target "text/html"
+    [ "$output" = "<b>GOOD</b>" ]
+}
+
+@test "X xclip compatible arguments" {
+    ln -s "$RICHCLIP" "$BATS_TEST_TMPDIR/xclip"
+    printf "GOOD" | "$BATS_TEST_TMPDIR/xclip" -selection clipboard 3>&-
+    run -0 "$RICHCLIP" paste
+    [ "$output" = "GOOD" ]
+    run -0 "$BATS_TEST_TMPDIR/xclip" -selection clipboard -o
+    [ "$output" = "GOOD" ]
+    run -0 "$RICHCLIP" paste -o -sel clipboard -target TARGETS
+    [ "${lines[0]}" = "TARGETS" ]
+}